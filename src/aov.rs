@@ -0,0 +1,153 @@
+// arbitrary output variables: what the camera rays saw besides the final color, each written out as an image of its own
+// for picking apart and reassembling the picture in compositing, and for guiding a denoiser
+
+use crate::geometry::Vec3f;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Aov {
+    // distance from the camera to whatever the ray hit first, 0 where it hit nothing
+    Depth,
+    // world space shading normal at the first hit
+    Normal,
+    // base color of the material at the first hit
+    Albedo,
+    // 1 for the first object in the scene, 2 for the second and so on, the floor coming after all of them and 0 being nothing
+    ObjectId,
+    // the number the material was given, see Material::with_id
+    MaterialId,
+    // the first hit's color split up by how the light got there, summing to the picture as long as there's no emission or fog
+    Diffuse,
+    Specular,
+    Reflection,
+    Refraction,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 9] = [
+        Aov::Depth, Aov::Normal, Aov::Albedo, Aov::ObjectId, Aov::MaterialId,
+        Aov::Diffuse, Aov::Specular, Aov::Reflection, Aov::Refraction,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::ObjectId => "object_id",
+            Aov::MaterialId => "material_id",
+            Aov::Diffuse => "diffuse",
+            Aov::Specular => "specular",
+            Aov::Reflection => "reflection",
+            Aov::Refraction => "refraction",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Aov> {
+        Aov::ALL.iter().copied().find(|aov| aov.name() == name)
+    }
+
+    /// whether a pixel's value is the average over its rays, rather than whatever its first ray saw
+    /// averaging the ids of two different objects along an edge would make the id of some third one
+    pub fn averaged(&self) -> bool {
+        !matches!(self, Aov::ObjectId | Aov::MaterialId)
+    }
+}
+
+/// what one camera ray saw
+#[derive(Debug, Clone)]
+pub struct AovSample {
+    pub depth: f32,
+    pub normal: Vec3f,
+    pub albedo: Vec3f,
+    pub object_id: u32,
+    pub material_id: u32,
+    pub diffuse: Vec3f,
+    pub specular: Vec3f,
+    pub reflection: Vec3f,
+    pub refraction: Vec3f,
+}
+
+impl AovSample {
+    /// a ray that didn't hit anything
+    pub fn miss() -> Self {
+        AovSample {
+            depth: 0.0,
+            normal: Vec3f::zero(),
+            albedo: Vec3f::zero(),
+            object_id: 0,
+            material_id: 0,
+            diffuse: Vec3f::zero(),
+            specular: Vec3f::zero(),
+            reflection: Vec3f::zero(),
+            refraction: Vec3f::zero(),
+        }
+    }
+
+    /// the value for one aov, single numbers going in all three channels
+    pub fn get(&self, aov: Aov) -> Vec3f {
+        let grey = |v: f32| Vec3f::new3f(v, v, v);
+        match aov {
+            Aov::Depth => grey(self.depth),
+            Aov::Normal => self.normal.clone(),
+            Aov::Albedo => self.albedo.clone(),
+            Aov::ObjectId => grey(self.object_id as f32),
+            Aov::MaterialId => grey(self.material_id as f32),
+            Aov::Diffuse => self.diffuse.clone(),
+            Aov::Specular => self.specular.clone(),
+            Aov::Reflection => self.reflection.clone(),
+            Aov::Refraction => self.refraction.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampling::Rng;
+
+    #[test]
+    fn names() {
+        for &aov in &Aov::ALL {
+            assert_eq!(Aov::parse(aov.name()), Some(aov));
+        }
+        assert_eq!(Aov::parse("beauty"), None);
+    }
+
+    // straight at the middle of the red rubber sphere in the default scene, which nothing else is in front of
+    #[test]
+    fn known_hit() {
+        let scene = crate::default_scene();
+        let (orig, center) = (Vec3f::zero(), Vec3f::new3f(1.5, -0.5, -18.0));
+        let dir = center.normalize();
+        let (color, _, sample) = crate::cast_camera_ray(&orig, &dir, 0.0, &scene, false, &mut Rng::new(1, 0));
+
+        assert!((sample.depth - (center.magnitude() - 3.0)).abs() < 1e-3, "depth {}", sample.depth);
+        assert!((&sample.normal + &dir).magnitude() < 1e-3, "normal {:?}", sample.normal);
+        assert_eq!(sample.get(Aov::Albedo), Vec3f::new3f(0.3, 0.1, 0.1));
+        assert_eq!(sample.get(Aov::ObjectId), Vec3f::new3f(3.0, 3.0, 3.0));
+        assert_eq!(sample.get(Aov::MaterialId), Vec3f::new3f(3.0, 3.0, 3.0));
+
+        // rubber doesn't reflect or refract, so the diffuse and specular parts make up the whole color
+        assert_eq!(sample.reflection, Vec3f::zero());
+        assert_eq!(sample.refraction, Vec3f::zero());
+        assert!((&(&sample.diffuse + &sample.specular) - &color).magnitude() < 1e-5, "{:?} and {:?} for {:?}", sample.diffuse, sample.specular, color);
+        assert!(sample.diffuse.magnitude() > 0.0);
+    }
+
+    #[test]
+    fn floor_and_miss() {
+        let scene = crate::default_scene();
+        let orig = Vec3f::zero();
+        // the floor comes after the four spheres
+        let (_, _, floor) = crate::cast_camera_ray(&orig, &Vec3f::new3f(0.0, -1.0, -3.0).normalize(), 0.0, &scene, false, &mut Rng::new(1, 0));
+        assert_eq!(floor.object_id, 5);
+        assert_eq!(floor.material_id, 5);
+        assert!((floor.depth - 160f32.sqrt()).abs() < 1e-3, "depth {}", floor.depth);
+        assert_eq!(floor.normal, Vec3f::new3f(0.0, 1.0, 0.0));
+
+        let (_, _, miss) = crate::cast_camera_ray(&orig, &Vec3f::new3f(0.0, 1.0, 0.0), 0.0, &scene, false, &mut Rng::new(1, 0));
+        for &aov in &Aov::ALL {
+            assert_eq!(miss.get(aov), Vec3f::zero(), "{} for a miss", aov.name());
+        }
+    }
+}
@@ -0,0 +1,237 @@
+use std::f32::consts::PI;
+use crate::geometry::Vec3f;
+use crate::image::{luminance, Image};
+use crate::sky::Sky;
+
+/// what a ray sees when it doesn't hit anything
+pub enum Background {
+    // flat color, purely cosmetic: it doesn't light the scene
+    Color(Vec3f),
+    Environment(EnvironmentMap),
+    // importance is the sky tabulated into an image, only used to decide where to sample it
+    Sky { sky: Sky, importance: EnvironmentMap },
+}
+
+impl Background {
+    pub fn sky(sky: Sky) -> Self {
+        let importance = EnvironmentMap::new(sky.bake(512, 256), 0.0, 1.0);
+
+        Background::Sky { sky, importance }
+    }
+
+    /// radiance arriving from direction dir
+    pub fn radiance(&self, dir: &Vec3f) -> Vec3f {
+        match self {
+            Background::Color(color) => color.clone(),
+            Background::Environment(env) => env.lookup(dir),
+            Background::Sky { sky, .. } => sky.radiance(dir),
+        }
+    }
+
+    /// whether the background should be sampled as a light source
+    pub fn illuminates(&self) -> bool {
+        !matches!(self, Background::Color(_))
+    }
+
+    /// returns tuple (direction, pdf), pdf being per solid angle
+    pub fn sample(&self, u: f32, v: f32) -> (Vec3f, f32) {
+        match self {
+            Background::Color(_) => (Vec3f::new3f(0.0, 1.0, 0.0), 0.0),
+            Background::Environment(env) => env.sample(u, v),
+            Background::Sky { importance, .. } => importance.sample(u, v),
+        }
+    }
+
+    /// density with which sample() would have returned dir
+    pub fn pdf(&self, dir: &Vec3f) -> f32 {
+        match self {
+            Background::Color(_) => 0.0,
+            Background::Environment(env) => env.pdf(dir),
+            Background::Sky { importance, .. } => importance.pdf(dir),
+        }
+    }
+}
+
+/// equirectangular (latitude/longitude) image wrapped around the scene
+/// u runs along the horizon, v from straight up (top row) to straight down (bottom row)
+pub struct EnvironmentMap {
+    image: Image,
+    // radians around the vertical axis
+    rotation: f32,
+    intensity: f32,
+    // importance sampling tables, brighter pixels get picked more often
+    // row_cdf[y] is the probability of picking a row before y, col_cdf[y * width + x] likewise within row y
+    row_cdf: Vec<f32>,
+    col_cdf: Vec<f32>,
+    // sum of all pixel weights
+    total_weight: f32,
+}
+
+// index of the bucket of cdf (which starts at 0 and has one entry per bucket) that x falls into
+// the last one starting at or before x, so empty buckets (the same value as the next) never get picked
+fn find_bucket(cdf: &[f32], x: f32) -> usize {
+    cdf.partition_point(|&c| c <= x).saturating_sub(1)
+}
+
+// how likely a pixel is to be picked, relative to the rest
+fn texel_weight(color: &Vec3f, sin_theta: f32) -> f32 {
+    luminance(color) * sin_theta
+}
+
+// a stray nan or infinity in the map would show up as a speck in the background and throw off the whole sampling table
+// so those pixels are made black, and negative values (which no light has) zero
+fn sanitize(color: &Vec3f) -> Vec3f {
+    if (0..3).all(|i| color[i].is_finite()) {
+        Vec3f::new3f(color[0].max(0.0), color[1].max(0.0), color[2].max(0.0))
+    } else {
+        Vec3f::zero()
+    }
+}
+
+impl EnvironmentMap {
+    pub fn new(image: Image, rotation: f32, intensity: f32) -> Self {
+        let (width, height) = (image.width(), image.height());
+        let image = Image::new(width, height, (0..width * height).map(|i| sanitize(image.get(i % width, i / width))).collect());
+        let mut row_cdf = Vec::with_capacity(height);
+        let mut col_cdf = Vec::with_capacity(width * height);
+        let mut row_weights = Vec::with_capacity(height);
+
+        for y in 0..height {
+            // rows near the poles are squashed into a tiny solid angle, so weigh them down accordingly
+            let sin_theta = (PI * (y as f32 + 0.5) / height as f32).sin();
+            let mut row_total = 0.0;
+            for x in 0..width {
+                col_cdf.push(row_total);
+                row_total += texel_weight(image.get(x, y), sin_theta);
+            }
+            if row_total > 0.0 {
+                for c in &mut col_cdf[y * width..] {
+                    *c /= row_total;
+                }
+            } else {
+                // black row, never picked anyway, but keep the table sane
+                for (x, c) in col_cdf[y * width..].iter_mut().enumerate() {
+                    *c = x as f32 / width as f32;
+                }
+            }
+            row_weights.push(row_total);
+        }
+
+        let total_weight: f32 = row_weights.iter().sum();
+        let mut acc = 0.0;
+        for w in &row_weights {
+            row_cdf.push(if total_weight > 0.0 { acc / total_weight } else { row_cdf.len() as f32 / height as f32 });
+            acc += w;
+        }
+
+        EnvironmentMap { image, rotation, intensity, row_cdf, col_cdf, total_weight }
+    }
+
+    // image coordinates in [0, 1) for a direction
+    fn dir_to_uv(&self, dir: &Vec3f) -> (f32, f32) {
+        let phi = dir[0].atan2(-dir[2]) + self.rotation;
+        let theta = dir[1].clamp(-1.0, 1.0).acos();
+
+        ((phi / (2.0 * PI) + 0.5).rem_euclid(1.0), theta / PI)
+    }
+
+    fn uv_to_dir(&self, u: f32, v: f32) -> Vec3f {
+        let phi = (u - 0.5) * 2.0 * PI - self.rotation;
+        let theta = v * PI;
+
+        Vec3f::new3f(theta.sin() * phi.sin(), theta.cos(), -theta.sin() * phi.cos())
+    }
+
+    fn pixel_at(&self, u: f32, v: f32) -> (usize, usize) {
+        let x = ((u * self.image.width() as f32) as usize).min(self.image.width() - 1);
+        let y = ((v * self.image.height() as f32) as usize).min(self.image.height() - 1);
+
+        (x, y)
+    }
+
+    pub fn lookup(&self, dir: &Vec3f) -> Vec3f {
+        let (u, v) = self.dir_to_uv(dir);
+        let (x, y) = self.pixel_at(u, v);
+
+        self.image.get(x, y) * self.intensity
+    }
+
+    pub fn sample(&self, u: f32, v: f32) -> (Vec3f, f32) {
+        if self.total_weight <= 0.0 {
+            return (Vec3f::new3f(0.0, 1.0, 0.0), 0.0);
+        }
+
+        let (width, height) = (self.image.width(), self.image.height());
+        let y = find_bucket(&self.row_cdf, v);
+        let row = &self.col_cdf[y * width..(y + 1) * width];
+        let x = find_bucket(row, u);
+
+        // reuse how far into the bucket we landed to pick a spot inside the pixel
+        let row_end = self.row_cdf.get(y + 1).copied().unwrap_or(1.0);
+        let col_end = row.get(x + 1).copied().unwrap_or(1.0);
+        let fy = if row_end > self.row_cdf[y] { (v - self.row_cdf[y]) / (row_end - self.row_cdf[y]) } else { 0.5 };
+        let fx = if col_end > row[x] { (u - row[x]) / (col_end - row[x]) } else { 0.5 };
+
+        let dir = self.uv_to_dir((x as f32 + fx) / width as f32, (y as f32 + fy) / height as f32);
+        let pdf = self.pdf(&dir);
+
+        (dir, pdf)
+    }
+
+    pub fn pdf(&self, dir: &Vec3f) -> f32 {
+        if self.total_weight <= 0.0 {
+            return 0.0;
+        }
+
+        let (u, v) = self.dir_to_uv(dir);
+        let (x, y) = self.pixel_at(u, v);
+        let sin_theta = (PI * (y as f32 + 0.5) / self.image.height() as f32).sin();
+        let pixel_probability = texel_weight(self.image.get(x, y), sin_theta) / self.total_weight;
+
+        // a pixel covers (2pi / width) * (pi / height) of (phi, theta) space, which is sin(theta) times that in solid angle
+        let pixel_solid_angle = 2.0 * PI * PI * sin_theta / (self.image.width() * self.image.height()) as f32;
+
+        pixel_probability / pixel_solid_angle
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets() {
+        let cdf = [0.0, 0.25, 0.25, 0.5];
+        assert_eq!(find_bucket(&cdf, 0.0), 0);
+        assert_eq!(find_bucket(&cdf, 0.1), 0);
+        // bucket 1 is empty
+        assert_eq!(find_bucket(&cdf, 0.25), 2);
+        assert_eq!(find_bucket(&cdf, 0.99), 3);
+        assert_eq!(find_bucket(&cdf, f32::NAN), 0);
+    }
+
+    #[test]
+    fn non_finite_texels() {
+        let mut pixels = vec![Vec3f::new3f(1.0, 1.0, 1.0); 8 * 4];
+        pixels[3] = Vec3f::new3f(f32::NAN, 0.0, 0.0);
+        pixels[12] = Vec3f::new3f(f32::INFINITY, 1.0, 1.0);
+        pixels[20] = Vec3f::new3f(-5.0, -5.0, -5.0);
+        let env = EnvironmentMap::new(Image::new(8, 4, pixels), 0.0, 1.0);
+        assert!(env.total_weight.is_finite() && env.total_weight > 0.0);
+
+        // every direction looks up something that can be added up, the bad pixels coming out black
+        for i in 0..64 {
+            let dir = env.uv_to_dir((i % 8) as f32 / 8.0 + 0.01, (i / 8 % 4) as f32 / 4.0 + 0.01);
+            let color = env.lookup(&dir);
+            assert!((0..3).all(|c| color[c].is_finite() && color[c] >= 0.0), "{:?} towards {:?}", color, dir);
+        }
+        assert_eq!(env.image.get(3, 0), &Vec3f::zero());
+        assert_eq!(env.image.get(4, 2), &Vec3f::zero());
+
+        for i in 0..64 {
+            let (u, v) = ((i % 8) as f32 / 8.0 + 0.01, (i / 8) as f32 / 8.0 + 0.01);
+            let (dir, pdf) = env.sample(u, v);
+            assert!(pdf.is_finite() && pdf > 0.0, "sample {} has pdf {} towards {:?}", i, pdf, dir);
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use crate::geometry::Vec3f;
+
+// we could compute this from the viewport size and its distance to the camera
+// but this way we don't have to
+static FOV: f32 = std::f32::consts::PI / 3.0;
+
+/// where the picture gets taken from, and how
+#[derive(Debug, Clone)]
+pub struct Camera {
+    position: Vec3f,
+    // unit vectors pointing where the camera looks, to the right of the picture and to its top
+    forward: Vec3f,
+    right: Vec3f,
+    up: Vec3f,
+    // vertical field of view, in radians
+    fov: f32,
+    // the shutter is open from shutter_open to shutter_close, and anything moving in between gets smeared along its path
+    shutter_open: f32,
+    shutter_close: f32,
+    // rays per pixel, spread over the pixel and over the time the shutter is open
+    samples: u32,
+}
+
+impl Camera {
+    /// a camera at position looking towards target, turned so that up points (roughly) to the top of the picture
+    /// looking straight along up, any way round will do, so then -z goes at the top when looking up or down and y otherwise
+    pub fn looking_at(position: &Vec3f, target: &Vec3f, up: &Vec3f, fov: f32) -> Self {
+        let forward = (target - position).normalize();
+        let mut right = forward.cross(up);
+        if right.magnitude() <= 1e-6 * up.magnitude() {
+            // like sampling::basis, whichever axis is far enough from forward for the cross product not to degenerate
+            let helper = if forward[1].abs() > 0.9 { Vec3f::new3f(0.0, 0.0, -1.0) } else { Vec3f::new3f(0.0, 1.0, 0.0) };
+            right = forward.cross(&helper);
+        }
+        let right = right.normalize();
+        let up = right.cross(&forward);
+
+        Camera { position: position.clone(), forward, right, up, fov, ..Camera::default() }
+    }
+
+    pub fn with_shutter(mut self, open: f32, close: f32) -> Self {
+        self.shutter_open = open;
+        self.shutter_close = close;
+        self
+    }
+
+    pub fn with_samples(mut self, samples: u32) -> Self {
+        self.samples = samples.max(1);
+        self
+    }
+
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    /// time at which to cast a ray, u being a number in [0, 1)
+    pub fn time(&self, u: f32) -> f32 {
+        self.shutter_open + (self.shutter_close - self.shutter_open) * u
+    }
+
+    /// origin and direction of the ray through the point (x, y) of the picture
+    /// x goes from -1 on the left to 1 on the right and y from -1 at the bottom to 1 at the top
+    pub fn ray(&self, x: f32, y: f32, aspect_ratio: f32) -> (Vec3f, Vec3f) {
+        let tan_fov = (self.fov / 2.0).tan();
+        let x = x * tan_fov * aspect_ratio;
+        let y = y * tan_fov;
+        let dir = (&(&(&self.right * x) + &(&self.up * y)) + &self.forward).normalize();
+
+        (self.position.clone(), dir)
+    }
+}
+
+impl Default for Camera {
+    // at the origin looking down -z, with one ray through the middle of each pixel and a shutter that's only open at time 0
+    fn default() -> Self {
+        Camera {
+            position: Vec3f::zero(),
+            forward: Vec3f::new3f(0.0, 0.0, -1.0),
+            right: Vec3f::new3f(1.0, 0.0, 0.0),
+            up: Vec3f::new3f(0.0, 1.0, 0.0),
+            fov: FOV,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+            samples: 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: &Vec3f, b: &Vec3f) {
+        assert!((a - b).magnitude() < 1e-5, "{:?} isn't {:?}", a, b);
+    }
+
+    #[test]
+    fn looking_along_up() {
+        for &(target, up) in &[([0.0, -1.0, 0.0], [0.0, 1.0, 0.0]), ([0.0, 5.0, 0.0], [0.0, 2.0, 0.0]), ([3.0, 0.0, 0.0], [-1.0, 0.0, 0.0]), ([0.0, 0.0, -1.0], [0.0, 0.0, 0.0])] {
+            let camera = Camera::looking_at(&Vec3f::zero(), &Vec3f::from(&target), &Vec3f::from(&up), 1.0);
+            let forward = Vec3f::from(&target).normalize();
+            assert_close(&camera.forward, &forward);
+            assert!((camera.right.magnitude() - 1.0).abs() < 1e-5 && (camera.up.magnitude() - 1.0).abs() < 1e-5, "{:?}", camera);
+            assert!(camera.right.dot(&forward).abs() < 1e-5 && camera.up.dot(&forward).abs() < 1e-5, "{:?}", camera);
+            assert_close(&camera.ray(0.0, 0.0, 1.0).1, &forward);
+        }
+
+        // straight down, the top of the picture is away from us along -z
+        let camera = Camera::looking_at(&Vec3f::zero(), &Vec3f::new3f(0.0, -1.0, 0.0), &Vec3f::new3f(0.0, 1.0, 0.0), 1.0);
+        assert_close(&camera.up, &Vec3f::new3f(0.0, 0.0, -1.0));
+        assert_close(&camera.right, &Vec3f::new3f(1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn looking_at_matches_default() {
+        let camera = Camera::looking_at(&Vec3f::zero(), &Vec3f::new3f(0.0, 0.0, -1.0), &Vec3f::new3f(0.0, 1.0, 0.0), FOV);
+        let default = Camera::default();
+        for &(x, y) in &[(0.0, 0.0), (-1.0, 1.0), (0.5, -0.25)] {
+            assert_close(&camera.ray(x, y, 1.5).1, &default.ray(x, y, 1.5).1);
+        }
+    }
+
+    #[test]
+    fn shutter() {
+        let camera = Camera::default().with_shutter(0.25, 0.75).with_samples(0);
+        assert_eq!([camera.time(0.0), camera.time(0.5), camera.time(1.0)], [0.25, 0.5, 0.75]);
+        // there's always at least the one ray
+        assert_eq!(camera.samples(), 1);
+        assert_eq!(Camera::default().time(0.9), 0.0);
+    }
+}
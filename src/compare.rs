@@ -0,0 +1,285 @@
+// telling how far apart two renders of the same thing are, for catching changes that shouldn't have changed the picture
+// rmse and psnr say how big the differences are on average, the flip-like metric how noticeable they'd be to someone flipping between the two
+// for the real thing see andersson et al. 2020, "FLIP: A Difference Evaluator for Alternating Images"
+// this one follows its ldr version, minus a few refinements: values are clamped to [0, 1] and taken as linear rgb
+
+use crate::geometry::Vec3f;
+use crate::image::{self, Image};
+
+// how many pixels make up one degree of the viewer's field of view, for a 0.7m wide 4k monitor 0.7m away as flip assumes
+static PIXELS_PER_DEGREE: f32 = 67.0;
+
+// d65 white point, for the conversions through xyz
+static WHITE: [f32; 3] = [0.950489, 1.0, 1.08884];
+
+pub struct Comparison {
+    /// root mean squared difference over every channel of every pixel
+    pub rmse: f32,
+    /// peak signal to noise ratio in decibels, taking 1 as the peak, infinite for identical images
+    pub psnr: f32,
+    /// mean of the per pixel flip errors, 0 for no visible difference and 1 for the most there can be
+    pub flip: f32,
+    /// the per pixel flip errors, from black for none through red and yellow to white
+    pub heatmap: Image,
+}
+
+/// how different test is from reference, which must be the same size
+pub fn compare(reference: &Image, test: &Image) -> Comparison {
+    assert!((reference.width(), reference.height()) == (test.width(), test.height()), "can't compare a {}x{} image to a {}x{} one",
+        reference.width(), reference.height(), test.width(), test.height());
+    let (width, height) = (reference.width(), reference.height());
+
+    let mut squared = 0.0;
+    for y in 0..height {
+        for x in 0..width {
+            let d = reference.get(x, y) - test.get(x, y);
+            squared += d.dot(&d) as f64;
+        }
+    }
+    let mse = squared / (3 * width * height).max(1) as f64;
+
+    let errors = flip(reference, test);
+    let heatmap = Image::new(width, height, errors.iter().map(|&error| image::heat(error)).collect());
+
+    Comparison {
+        rmse: mse.sqrt() as f32,
+        psnr: (-10.0 * mse.log10()) as f32,
+        flip: (errors.iter().map(|&error| error as f64).sum::<f64>() / errors.len().max(1) as f64) as f32,
+        heatmap,
+    }
+}
+
+// one number per pixel, kept in separate planes for filtering
+struct Planes {
+    width: usize,
+    height: usize,
+    channels: [Vec<f32>; 3],
+}
+
+impl Planes {
+    fn new(width: usize, height: usize) -> Self {
+        Planes { width, height, channels: [vec![0.0; width * height], vec![0.0; width * height], vec![0.0; width * height]] }
+    }
+
+    fn get(&self, i: usize) -> Vec3f {
+        Vec3f::new3f(self.channels[0][i], self.channels[1][i], self.channels[2][i])
+    }
+
+    fn set(&mut self, i: usize, value: &Vec3f) {
+        (0..3).for_each(|c| self.channels[c][i] = value[c as i32]);
+    }
+}
+
+// per pixel errors in [0, 1]
+// differences in color, after blurring away the detail too fine for the eye to make out, made worse where the edges and points that draw the eye differ
+fn flip(reference: &Image, test: &Image) -> Vec<f32> {
+    let (width, height) = (reference.width(), reference.height());
+    let opponent = |image: &Image| {
+        let mut planes = Planes::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let c = image.get(x, y);
+                planes.set(y * width + x, &xyz_to_ycxcz(&rgb_to_xyz(&Vec3f::new3f(c[0].clamp(0.0, 1.0), c[1].clamp(0.0, 1.0), c[2].clamp(0.0, 1.0)))));
+            }
+        }
+        planes
+    };
+    let (reference, test) = (opponent(reference), opponent(test));
+
+    let color = color_errors(&reference, &test);
+    let feature = feature_errors(&reference.channels[0], &test.channels[0], width, height);
+
+    color.iter().zip(&feature).map(|(&c, &f)| c.powf(1.0 - f)).collect()
+}
+
+fn color_errors(reference: &Planes, test: &Planes) -> Vec<f32> {
+    // the contrast sensitivity of the eye, as sums of gaussians with flip's weights and widths (in degrees squared) per channel
+    let sensitivity: [&[(f32, f32)]; 3] = [&[(1.0, 0.0047)], &[(1.0, 0.0053)], &[(34.1, 0.04), (13.5, 0.025)]];
+    let filter = |planes: &Planes| {
+        let mut filtered = Planes::new(planes.width, planes.height);
+        for (c, terms) in sensitivity.iter().enumerate() {
+            // each term integrates to a sqrt(b / pi) over the plane, which decides how much of the mix it makes up
+            let total: f32 = terms.iter().map(|(a, b)| a * b.sqrt()).sum();
+            for (a, b) in terms.iter() {
+                let sigma = (b / (2.0 * std::f32::consts::PI * std::f32::consts::PI)).sqrt() * PIXELS_PER_DEGREE;
+                let kernel = gaussian(sigma);
+                let blurred = convolve(&planes.channels[c], planes.width, planes.height, &kernel, &kernel);
+                let weight = a * b.sqrt() / total;
+                filtered.channels[c].iter_mut().zip(blurred).for_each(|(out, value)| *out += weight * value);
+            }
+        }
+        filtered
+    };
+    let (reference, test) = (filter(reference), filter(test));
+
+    let perceived = |planes: &Planes, i: usize| {
+        let rgb = xyz_to_rgb(&ycxcz_to_xyz(&planes.get(i)));
+        hunt(&xyz_to_lab(&rgb_to_xyz(&Vec3f::new3f(rgb[0].clamp(0.0, 1.0), rgb[1].clamp(0.0, 1.0), rgb[2].clamp(0.0, 1.0)))))
+    };
+    // errors get compressed, then stretched so the smaller 40% of what they can be covers 95% of [0, 1]
+    let hunt_rgb = |c: Vec3f| hunt(&xyz_to_lab(&rgb_to_xyz(&c)));
+    let max_error = hyab(&hunt_rgb(Vec3f::new3f(0.0, 1.0, 0.0)), &hunt_rgb(Vec3f::new3f(0.0, 0.0, 1.0))).powf(0.7);
+    let (cutoff, remapped_cutoff) = (0.4 * max_error, 0.95);
+
+    (0..reference.width * reference.height)
+        .map(|i| {
+            let error = hyab(&perceived(&reference, i), &perceived(&test, i)).powf(0.7);
+            if error < cutoff {
+                remapped_cutoff * error / cutoff
+            } else {
+                (remapped_cutoff + (error - cutoff) / (max_error - cutoff) * (1.0 - remapped_cutoff)).min(1.0)
+            }
+        })
+        .collect()
+}
+
+// how differently edges and points show up in the brightness of the two
+fn feature_errors(reference: &[f32], test: &[f32], width: usize, height: usize) -> Vec<f32> {
+    let sigma = 0.5 * 0.082 * PIXELS_PER_DEGREE;
+    let smooth = gaussian(sigma);
+    let radius = (smooth.len() / 2) as isize;
+    // first and second derivatives of the gaussian, their positive and negative halves scaled to 1 and -1
+    let balance = |kernel: Vec<f32>| {
+        let positive: f32 = kernel.iter().filter(|&&k| k > 0.0).sum();
+        let negative: f32 = -kernel.iter().filter(|&&k| k < 0.0).sum::<f32>();
+        kernel.into_iter().map(|k| if k > 0.0 { k / positive } else { k / negative }).collect::<Vec<f32>>()
+    };
+    let edge = balance((-radius..=radius).zip(&smooth).map(|(x, g)| -(x as f32) * g).collect());
+    let point = balance((-radius..=radius).zip(&smooth).map(|(x, g)| ((x * x) as f32 / (sigma * sigma) - 1.0) * g).collect());
+
+    let features = |brightness: &[f32]| {
+        // ycxcz's y is 116 times the luminance, less 16
+        let luminance: Vec<f32> = brightness.iter().map(|y| (y + 16.0) / 116.0).collect();
+        let magnitude = |kernel: &[f32]| {
+            let along_x = convolve(&luminance, width, height, kernel, &smooth);
+            let along_y = convolve(&luminance, width, height, &smooth, kernel);
+            along_x.iter().zip(along_y).map(|(x, y)| (x * x + y * y).sqrt()).collect::<Vec<f32>>()
+        };
+        (magnitude(&edge), magnitude(&point))
+    };
+    let ((reference_edges, reference_points), (test_edges, test_points)) = (features(reference), features(test));
+
+    (0..width * height)
+        .map(|i| {
+            let difference = (reference_edges[i] - test_edges[i]).abs().max((reference_points[i] - test_points[i]).abs());
+            (difference / std::f32::consts::SQRT_2).sqrt()
+        })
+        .collect()
+}
+
+// normalized gaussian out to three standard deviations either side
+fn gaussian(sigma: f32) -> Vec<f32> {
+    let radius = (3.0 * sigma).ceil() as isize;
+    let kernel: Vec<f32> = (-radius..=radius).map(|x| (-((x * x) as f32) / (2.0 * sigma * sigma)).exp()).collect();
+    let total: f32 = kernel.iter().sum();
+    kernel.into_iter().map(|k| k / total).collect()
+}
+
+// filter a plane with kernel_x along its rows then kernel_y along its columns, the edges repeating outwards
+fn convolve(plane: &[f32], width: usize, height: usize, kernel_x: &[f32], kernel_y: &[f32]) -> Vec<f32> {
+    let pass = |plane: &[f32], kernel: &[f32], horizontal: bool| {
+        let radius = (kernel.len() / 2) as isize;
+        let mut out = vec![0.0; plane.len()];
+        for y in 0..height {
+            for x in 0..width {
+                out[y * width + x] = kernel.iter().enumerate()
+                    .map(|(k, weight)| {
+                        let offset = k as isize - radius;
+                        let (sx, sy) = if horizontal {
+                            ((x as isize + offset).clamp(0, width as isize - 1) as usize, y)
+                        } else {
+                            (x, (y as isize + offset).clamp(0, height as isize - 1) as usize)
+                        };
+                        weight * plane[sy * width + sx]
+                    })
+                    .sum();
+            }
+        }
+        out
+    };
+
+    pass(&pass(plane, kernel_x, true), kernel_y, false)
+}
+
+fn rgb_to_xyz(c: &Vec3f) -> Vec3f {
+    Vec3f::new3f(0.4124564 * c[0] + 0.3575761 * c[1] + 0.1804375 * c[2],
+                 0.2126729 * c[0] + 0.7151522 * c[1] + 0.0721750 * c[2],
+                 0.0193339 * c[0] + 0.119192 * c[1] + 0.9503041 * c[2])
+}
+
+fn xyz_to_rgb(c: &Vec3f) -> Vec3f {
+    Vec3f::new3f(3.2404542 * c[0] - 1.5371385 * c[1] - 0.4985314 * c[2],
+                 -0.969266 * c[0] + 1.8760108 * c[1] + 0.0415560 * c[2],
+                 0.0556434 * c[0] - 0.2040259 * c[1] + 1.0572252 * c[2])
+}
+
+// a linear take on l*a*b*, which is what the filtering happens in
+fn xyz_to_ycxcz(c: &Vec3f) -> Vec3f {
+    let (x, y, z) = (c[0] / WHITE[0], c[1] / WHITE[1], c[2] / WHITE[2]);
+    Vec3f::new3f(116.0 * y - 16.0, 500.0 * (x - y), 200.0 * (y - z))
+}
+
+fn ycxcz_to_xyz(c: &Vec3f) -> Vec3f {
+    let y = (c[0] + 16.0) / 116.0;
+    Vec3f::new3f(WHITE[0] * (c[1] / 500.0 + y), WHITE[1] * y, WHITE[2] * (y - c[2] / 200.0))
+}
+
+fn xyz_to_lab(c: &Vec3f) -> Vec3f {
+    let delta: f32 = 6.0 / 29.0;
+    let f = |t: f32| if t > delta.powi(3) { t.cbrt() } else { t / (3.0 * delta * delta) + 4.0 / 29.0 };
+    let (x, y, z) = (f(c[0] / WHITE[0]), f(c[1] / WHITE[1]), f(c[2] / WHITE[2]));
+    Vec3f::new3f(116.0 * y - 16.0, 500.0 * (x - y), 200.0 * (y - z))
+}
+
+// colors look less colorful the darker they are
+fn hunt(lab: &Vec3f) -> Vec3f {
+    Vec3f::new3f(lab[0], 0.01 * lab[0] * lab[1], 0.01 * lab[0] * lab[2])
+}
+
+// distance between two l*a*b* colors, which does better than the euclidean one for large differences
+fn hyab(a: &Vec3f, b: &Vec3f) -> f32 {
+    let (dl, da, db) = (a[0] - b[0], a[1] - b[1], a[2] - b[2]);
+    dl.abs() + (da * da + db * db).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flat(value: f32) -> Image {
+        Image::new(32, 32, vec![Vec3f::new3f(value, value, value); 32 * 32])
+    }
+
+    // vertical stripes, offset by shift pixels
+    fn stripes(shift: usize) -> Image {
+        let pixels = (0..32 * 32).map(|i| if ((i % 32 + shift) / 4).is_multiple_of(2) { Vec3f::new3f(0.9, 0.8, 0.1) } else { Vec3f::new3f(0.1, 0.2, 0.7) }).collect();
+        Image::new(32, 32, pixels)
+    }
+
+    #[test]
+    fn identical() {
+        let comparison = compare(&stripes(0), &stripes(0));
+        assert_eq!((comparison.rmse, comparison.flip), (0.0, 0.0));
+        assert_eq!(comparison.psnr, f32::INFINITY);
+        assert_eq!((comparison.heatmap.width(), comparison.heatmap.height()), (32, 32));
+    }
+
+    #[test]
+    fn uniform_offset() {
+        let comparison = compare(&flat(0.5), &flat(0.6));
+        assert!((comparison.rmse - 0.1).abs() < 1e-5, "rmse {}", comparison.rmse);
+        assert!((comparison.psnr - 20.0).abs() < 1e-3, "psnr {}", comparison.psnr);
+        assert!(comparison.flip > 0.0 && comparison.flip < 0.2, "flip {}", comparison.flip);
+    }
+
+    // moving edges is a lot more visible than nudging the brightness a little
+    #[test]
+    fn shifted() {
+        let reference = stripes(0);
+        let shifted = compare(&reference, &stripes(2));
+        let nudged = compare(&reference, &Image::new(32, 32, (0..32 * 32).map(|i| reference.get(i % 32, i / 32) * 1.02).collect()));
+        assert!(shifted.flip > nudged.flip && shifted.flip <= 1.0, "shifted {} nudged {}", shifted.flip, nudged.flip);
+        assert!(shifted.rmse > nudged.rmse);
+        assert!(nudged.flip > 0.0);
+    }
+}
@@ -0,0 +1,211 @@
+// constructive solid geometry: solids made by adding, overlapping and carving out other solids
+// works on the crossings of both sides, walking along the ray and keeping track of whether we're inside each one
+
+use std::cell::OnceCell;
+use crate::geometry::Vec3f;
+use crate::material::Material;
+use crate::object::{Object, SurfaceHit};
+use crate::sampling::{self, Rng};
+
+// how many points on either side sample_surface tries before giving up on finding one on what's left of them
+static MAX_TRIES: u32 = 64;
+// how many points on each side area() checks, to tell how much of it is left
+static AREA_SAMPLES: u32 = 16384;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CsgOp {
+    // inside either
+    Union,
+    // inside both
+    Intersection,
+    // inside the first but not the second
+    Difference,
+}
+
+impl CsgOp {
+    fn inside(&self, in_a: bool, in_b: bool) -> bool {
+        match self {
+            CsgOp::Union => in_a || in_b,
+            CsgOp::Intersection => in_a && in_b,
+            CsgOp::Difference => in_a && !in_b,
+        }
+    }
+}
+
+/// two closed objects combined into one, shaded all over with its own material
+/// either side can be another Csg, or Transformed, so whole trees of them can be built up
+pub struct Csg {
+    op: CsgOp,
+    a: Box<dyn Object>,
+    b: Box<dyn Object>,
+    material: Material,
+    // worked out the first time it's asked for, since only glowing csg objects need it
+    area: OnceCell<f32>,
+}
+
+impl Csg {
+    pub fn new(op: CsgOp, a: Box<dyn Object>, b: Box<dyn Object>, material: &Material) -> Self {
+        Csg { op, a, b, material: material.clone(), area: OnceCell::new() }
+    }
+
+    // a point on the surface of a (or b) is on the result's surface if crossing it there takes you into or out of the result
+    // the same test crossings() makes, with the other side's inside or out found by looking from the point
+    fn on_surface(&self, point: &Vec3f, from_a: bool, time: f32) -> bool {
+        if from_a {
+            let in_b = contains(self.b.as_ref(), point, time);
+            self.op.inside(true, in_b) != self.op.inside(false, in_b)
+        } else {
+            let in_a = contains(self.a.as_ref(), point, time);
+            self.op.inside(in_a, true) != self.op.inside(in_a, false)
+        }
+    }
+
+    // a point spread evenly over both sides together, u picking which one by their areas, and whether it's on a
+    fn sample_sides(&self, u: f32, v: f32, time: f32) -> Option<(Vec3f, Vec3f, bool)> {
+        let (area_a, area_b) = (self.a.area(), self.b.area());
+        let split = area_a / (area_a + area_b);
+        if u < split {
+            let (point, normal) = self.a.sample_surface(u / split, v, time)?;
+            Some((point, normal, true))
+        } else {
+            let (point, normal) = self.b.sample_surface((u - split) / (1.0 - split), v, time)?;
+            Some((point, normal, false))
+        }
+    }
+}
+
+// a crossing with its normal facing against the ray is on the way in
+fn entering(hit: &SurfaceHit, dir: &Vec3f) -> bool {
+    hit.normal.dot(dir) < 0.0
+}
+
+// whether point is inside a closed object, going by whether the first crossing from it leaves the object, like crossings() does
+fn contains(object: &dyn Object, point: &Vec3f, time: f32) -> bool {
+    // any direction does, as long as it's unlikely to run right along a face
+    let dir = Vec3f::new3f(0.48, 0.6, 0.64);
+    object.crossings(point, &dir, time).first().is_some_and(|hit| !entering(hit, &dir))
+}
+
+impl Object for Csg {
+    fn ray_intersect(&self, orig: &Vec3f, dir: &Vec3f, time: f32) -> Option<SurfaceHit> {
+        self.crossings(orig, dir, time).into_iter().next()
+    }
+
+    fn crossings(&self, orig: &Vec3f, dir: &Vec3f, time: f32) -> Vec<SurfaceHit> {
+        let a = self.a.crossings(orig, dir, time);
+        let b = self.b.crossings(orig, dir, time);
+
+        // if the first crossing in front of us leaves an object, we must be starting out inside it
+        let mut in_a = a.first().is_some_and(|hit| !entering(hit, dir));
+        let mut in_b = b.first().is_some_and(|hit| !entering(hit, dir));
+        let mut inside = self.op.inside(in_a, in_b);
+
+        let mut crossings = Vec::new();
+        let (mut next_a, mut next_b) = (a.into_iter().peekable(), b.into_iter().peekable());
+        loop {
+            let from_a = match (next_a.peek(), next_b.peek()) {
+                (Some(hit_a), Some(hit_b)) => hit_a.distance <= hit_b.distance,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (None, None) => break,
+            };
+            let mut hit = if from_a { next_a.next() } else { next_b.next() }.unwrap();
+            if from_a {
+                in_a = entering(&hit, dir);
+            } else {
+                in_b = entering(&hit, dir);
+            }
+
+            // only the crossings where we go from outside the result to inside it or back are part of its surface
+            let now_inside = self.op.inside(in_a, in_b);
+            if now_inside != inside {
+                // whatever's carved out of a leaves a surface facing into the carved out part
+                if !from_a && self.op == CsgOp::Difference {
+                    hit.normal = &Vec3f::zero() - &hit.normal;
+                }
+                crossings.push(hit);
+                inside = now_inside;
+            }
+        }
+
+        crossings
+    }
+
+    fn get_material(&self) -> &Material {
+        &self.material
+    }
+
+    // each side's area, times the share of it that's left in the result
+    // counted on halton points rather than random ones, which gets within a percent or so, and the same every time
+    fn area(&self) -> f32 {
+        *self.area.get_or_init(|| {
+            let left = |side: &dyn Object, from_a: bool| {
+                let kept = (0..AREA_SAMPLES)
+                    // points that couldn't be found count as thrown back, like in sample_surface
+                    .filter(|&i| {
+                        side.sample_surface(sampling::halton(i, 0), sampling::halton(i, 1), 0.0)
+                            .is_some_and(|(point, _)| self.on_surface(&point, from_a, 0.0))
+                    })
+                    .count();
+                side.area() * kept as f32 / AREA_SAMPLES as f32
+            };
+            left(self.a.as_ref(), true) + left(self.b.as_ref(), false)
+        })
+    }
+
+    // points on either side, with the ones on bits that were carved away (or swallowed up) thrown back
+    // which leaves the rest spread evenly over what's left, to go with area()
+    fn sample_surface(&self, u: f32, v: f32, time: f32) -> Option<(Vec3f, Vec3f)> {
+        // after the first try, the random numbers come from a hash of the ones given
+        let mut rng = Rng::new(sampling::hash(&[u.to_bits() as u64, v.to_bits() as u64]), 0);
+        let (mut u, mut v) = (u, v);
+        for _ in 0..MAX_TRIES {
+            if let Some((point, normal, from_a)) = self.sample_sides(u, v, time).filter(|(point, _, from_a)| self.on_surface(point, *from_a, time)) {
+                // facing into the carved out part, like in crossings()
+                let normal = if !from_a && self.op == CsgOp::Difference { &Vec3f::zero() - &normal } else { normal };
+                return Some((point, normal));
+            }
+            u = rng.next_f32();
+            v = rng.next_f32();
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::{Cuboid, Sphere};
+
+    // a unit sphere with its top half cut off: a hemisphere and a disk, 3pi all told
+    fn bowl() -> Csg {
+        let material = Material::new(&Vec3f::new3f(1.0, 1.0, 1.0), &Vec3f::new3f(1.0, 0.0, 0.0), 1.0, 1.0);
+        let ball = Sphere::new(Vec3f::zero(), 1.0, &material);
+        let lid = Cuboid::new(&Vec3f::new3f(-2.0, 0.0, -2.0), &Vec3f::new3f(2.0, 2.0, 2.0), &material);
+        Csg::new(CsgOp::Difference, Box::new(ball), Box::new(lid), &material)
+    }
+
+    #[test]
+    fn area_of_what_is_left() {
+        let area = bowl().area();
+        assert!((area - 3.0 * std::f32::consts::PI).abs() < 0.05, "area {}", area);
+    }
+
+    #[test]
+    fn samples_on_what_is_left() {
+        let bowl = bowl();
+        let mut rng = Rng::new(7, 0);
+        for _ in 0..256 {
+            let (point, normal) = bowl.sample_surface(rng.next_f32(), rng.next_f32(), 0.0).unwrap();
+            assert!(point[1] <= 1e-4, "{:?} was carved away", point);
+            assert!(point.magnitude() <= 1.0 + 1e-4, "{:?} isn't on the bowl", point);
+            if point[1] > -1e-4 {
+                // the cut faces out of the bowl, up into the part that was carved away
+                assert!(normal[1] > 0.99, "{:?} at {:?}", normal, point);
+            } else {
+                assert!((point.magnitude() - 1.0).abs() < 1e-4 && normal.dot(&point) > 0.99, "{:?} at {:?}", normal, point);
+            }
+        }
+    }
+}
@@ -0,0 +1,174 @@
+// deflate compression, just enough to write png files that aren't needlessly huge
+// repeats are found with a hash chain and everything goes in one block of fixed huffman codes
+// which is nowhere near as tight as zlib proper, but rendered images are mostly smooth gradients and flat backgrounds anyway
+
+use crate::inflate::{self, DIST_BASE, DIST_EXTRA, LENGTH_BASE, LENGTH_EXTRA};
+
+static WINDOW: usize = 32768;
+static MIN_MATCH: usize = 3;
+static MAX_MATCH: usize = 258;
+// how many earlier positions with the same hash to try before settling for the best match so far
+static MAX_CHAIN: usize = 32;
+static HASH_BITS: u32 = 15;
+
+// the mirror image of BitReader in inflate.rs, filling each byte from its least significant bit up
+struct BitWriter {
+    out: Vec<u8>,
+    bit: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        BitWriter { out: Vec::new(), bit: 0 }
+    }
+
+    fn bits(&mut self, value: u32, count: u32) {
+        for i in 0..count {
+            if self.bit == 0 {
+                self.out.push(0);
+            }
+            let last = self.out.len() - 1;
+            self.out[last] |= (((value >> i) & 1) as u8) << self.bit;
+            self.bit = (self.bit + 1) % 8;
+        }
+    }
+
+    // huffman codes go in starting from their most significant bit, unlike everything else
+    fn code(&mut self, code: u32, length: u32) {
+        for i in (0..length).rev() {
+            self.bits((code >> i) & 1, 1);
+        }
+    }
+
+    // the fixed literal/length code from section 3.2.6 of the rfc
+    fn symbol(&mut self, symbol: u32) {
+        match symbol {
+            0..=143 => self.code(0x30 + symbol, 8),
+            144..=255 => self.code(0x190 + symbol - 144, 9),
+            256..=279 => self.code(symbol - 256, 7),
+            _ => self.code(0xC0 + symbol - 280, 8),
+        }
+    }
+
+    fn copy(&mut self, length: usize, distance: usize) {
+        let l = LENGTH_BASE.iter().rposition(|&base| base as usize <= length).unwrap();
+        self.symbol(257 + l as u32);
+        self.bits((length - LENGTH_BASE[l] as usize) as u32, LENGTH_EXTRA[l] as u32);
+
+        let d = DIST_BASE.iter().rposition(|&base| base as usize <= distance).unwrap();
+        self.code(d as u32, 5);
+        self.bits((distance - DIST_BASE[d] as usize) as u32, DIST_EXTRA[d] as u32);
+    }
+}
+
+fn hash(data: &[u8], pos: usize) -> usize {
+    let key = (data[pos] as u32) << 16 | (data[pos + 1] as u32) << 8 | data[pos + 2] as u32;
+    (key.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize
+}
+
+fn insert(data: &[u8], pos: usize, head: &mut [usize], previous: &mut [usize]) {
+    if pos + MIN_MATCH <= data.len() {
+        let h = hash(data, pos);
+        previous[pos] = head[h];
+        head[h] = pos;
+    }
+}
+
+/// compress into a raw deflate stream
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter::new();
+    // final block, fixed codes
+    writer.bits(1, 1);
+    writer.bits(1, 2);
+
+    // most recent position with each hash, and for every position the one before it with the same hash
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut previous = vec![usize::MAX; data.len()];
+
+    let mut pos = 0;
+    while pos < data.len() {
+        let mut best = (0, 0);
+        if pos + MIN_MATCH <= data.len() {
+            let max_length = MAX_MATCH.min(data.len() - pos);
+            let mut candidate = head[hash(data, pos)];
+            let mut tries = 0;
+            while candidate != usize::MAX && pos - candidate <= WINDOW && tries < MAX_CHAIN {
+                let length = (0..max_length).take_while(|&k| data[candidate + k] == data[pos + k]).count();
+                if length > best.0 {
+                    best = (length, pos - candidate);
+                }
+                candidate = previous[candidate];
+                tries += 1;
+            }
+        }
+
+        if best.0 >= MIN_MATCH {
+            writer.copy(best.0, best.1);
+            for p in pos..pos + best.0 {
+                insert(data, p, &mut head, &mut previous);
+            }
+            pos += best.0;
+        } else {
+            writer.symbol(data[pos] as u32);
+            insert(data, pos, &mut head, &mut previous);
+            pos += 1;
+        }
+    }
+
+    writer.symbol(256);
+    writer.out
+}
+
+/// compress into a zlib stream, the other half of inflate::zlib_decompress
+pub fn zlib_compress(data: &[u8]) -> Vec<u8> {
+    // deflate with a 32k window and no dictionary, the two bytes together being a multiple of 31
+    let mut out = vec![0x78, 0x01];
+    out.extend(deflate(data));
+    out.extend_from_slice(&inflate::adler32(data).to_be_bytes());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampling::Rng;
+
+    fn round_trip(data: &[u8]) {
+        let compressed = zlib_compress(data);
+        // a fixed huffman block, straight after the zlib header
+        assert_eq!((compressed[2] >> 1) & 3, 1);
+        assert_eq!(inflate::zlib_decompress(&compressed).unwrap(), data);
+    }
+
+    #[test]
+    fn empty() {
+        round_trip(b"");
+    }
+
+    #[test]
+    fn text() {
+        round_trip(b"she sells sea shells by the sea shore");
+    }
+
+    // runs longer than the longest match, and copies that overlap what they're copying
+    #[test]
+    fn repeats() {
+        let mut data = vec![7u8; 1000];
+        data.extend(b"abcabcabcabcabcabcabc".iter().cycle().take(600));
+        round_trip(&data);
+    }
+
+    // matches from further back than the window reaches can't be used
+    #[test]
+    fn longer_than_window() {
+        let mut rng = Rng::new(1, 0);
+        let noise: Vec<u8> = (0..WINDOW + 100).map(|_| rng.next_u32() as u8).collect();
+        let data: Vec<u8> = noise.iter().chain(&noise[..5000]).copied().collect();
+        round_trip(&data);
+    }
+
+    #[test]
+    fn every_byte() {
+        round_trip(&(0..=255).cycle().take(2000).collect::<Vec<u8>>());
+    }
+}
@@ -0,0 +1,149 @@
+// cleaning up the noise of renders with few samples per pixel
+// a joint bilateral filter: every pixel becomes a weighted average of its neighbours, the weights falling off with distance
+// and with how different the neighbour is, in color and also in the normal and albedo aovs
+// those come out of the renderer free of noise, so they show where the real edges are even when the color is too noisy to tell
+
+use crate::geometry::Vec3f;
+use crate::image::Image;
+
+// how far apart (as a distance between colors) two pixels can be before they stop being averaged together, at strength 1
+static COLOR_TOLERANCE: f32 = 0.25;
+// the same for the guides, which don't depend on the strength: different normals or albedos mean a different surface
+static NORMAL_TOLERANCE: f32 = 0.2;
+static ALBEDO_TOLERANCE: f32 = 0.05;
+
+pub struct Denoiser {
+    strength: f32,
+    // the neighbourhood is (2 * radius + 1) pixels across
+    radius: usize,
+}
+
+fn distance_squared(a: &Vec3f, b: &Vec3f) -> f32 {
+    let d = a - b;
+    d.dot(&d)
+}
+
+impl Denoiser {
+    /// strength scales how different colors can be and still get averaged, 0 leaving the image as it is
+    pub fn new(strength: f32) -> Self {
+        Denoiser { strength: strength.max(0.0), radius: 3 }
+    }
+
+    pub fn with_radius(mut self, radius: usize) -> Self {
+        self.radius = radius;
+        self
+    }
+
+    /// how many pixels away from each one it looks, in any direction
+    pub fn radius(&self) -> usize {
+        if self.strength == 0.0 { 0 } else { self.radius }
+    }
+
+    /// the image with its noise smoothed over, keeping to the edges in the guides that are there
+    pub fn denoise(&self, image: &Image, normal: Option<&Image>, albedo: Option<&Image>) -> Image {
+        let (width, height) = (image.width(), image.height());
+        if self.strength == 0.0 || self.radius == 0 {
+            return image.clone();
+        }
+
+        let radius = self.radius as isize;
+        let spatial_falloff = 1.0 / (2.0 * (self.radius as f32 / 2.0).powi(2));
+        let color_falloff = 1.0 / (2.0 * (COLOR_TOLERANCE * self.strength).powi(2));
+        let normal_falloff = 1.0 / (2.0 * NORMAL_TOLERANCE * NORMAL_TOLERANCE);
+        let albedo_falloff = 1.0 / (2.0 * ALBEDO_TOLERANCE * ALBEDO_TOLERANCE);
+
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let center = image.get(x, y);
+                let mut total = Vec3f::zero();
+                let mut total_weight = 0.0;
+
+                for dy in -radius..=radius {
+                    for dx in -radius..=radius {
+                        let (nx, ny) = (x as isize + dx, y as isize + dy);
+                        if nx < 0 || ny < 0 || nx >= width as isize || ny >= height as isize {
+                            continue;
+                        }
+                        let (nx, ny) = (nx as usize, ny as usize);
+                        let neighbour = image.get(nx, ny);
+
+                        let mut exponent = (dx * dx + dy * dy) as f32 * spatial_falloff
+                            + distance_squared(center, neighbour) * color_falloff;
+                        if let Some(normal) = normal {
+                            exponent += distance_squared(normal.get(x, y), normal.get(nx, ny)) * normal_falloff;
+                        }
+                        if let Some(albedo) = albedo {
+                            exponent += distance_squared(albedo.get(x, y), albedo.get(nx, ny)) * albedo_falloff;
+                        }
+
+                        let weight = (-exponent).exp();
+                        total = &total + &(neighbour * weight);
+                        total_weight += weight;
+                    }
+                }
+
+                // the pixel itself always has weight 1, so this never divides by 0
+                pixels.push(&total * (1.0 / total_weight));
+            }
+        }
+
+        Image::new(width, height, pixels)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampling::Rng;
+
+    fn grey(v: f32) -> Vec3f {
+        Vec3f::new3f(v, v, v)
+    }
+
+    // the left half at one level and the right half at another, with noise of the given amplitude on top
+    fn step(size: usize, left: f32, right: f32, noise: f32) -> Image {
+        let mut rng = Rng::new(7, 0);
+        let pixels = (0..size * size)
+            .map(|i| grey(if i % size < size / 2 { left } else { right } + noise * (rng.next_f32() - 0.5)))
+            .collect();
+        Image::new(size, size, pixels)
+    }
+
+    // how far the pixels are from what they'd be without noise
+    fn error(image: &Image, left: f32, right: f32) -> f32 {
+        let size = image.width();
+        (0..size * size).map(|i| (image.get(i % size, i / size)[0] - if i % size < size / 2 { left } else { right }).powi(2)).sum::<f32>()
+            / (size * size) as f32
+    }
+
+    #[test]
+    fn keeps_edges() {
+        let noisy = step(16, 0.1, 0.9, 0.1);
+        let denoised = Denoiser::new(1.0).denoise(&noisy, None, None);
+        assert!(error(&denoised, 0.1, 0.9) < 0.5 * error(&noisy, 0.1, 0.9));
+
+        // the pixels right by the edge don't bleed into each other
+        for y in 0..16 {
+            assert!((denoised.get(7, y)[0] - 0.1).abs() < 0.05, "{:?} left of the edge", denoised.get(7, y));
+            assert!((denoised.get(8, y)[0] - 0.9).abs() < 0.05, "{:?} right of the edge", denoised.get(8, y));
+        }
+
+        assert_eq!(Denoiser::new(0.0).denoise(&noisy, None, None).get(3, 3), noisy.get(3, 3));
+    }
+
+    // an edge too faint to tell from the noise gets blurred, unless the albedo shows it's there
+    #[test]
+    fn follows_guides() {
+        let faint = step(16, 0.45, 0.55, 0.0);
+        let blurred = Denoiser::new(1.0).denoise(&faint, None, None);
+        assert!(blurred.get(7, 8)[0] > 0.47, "{:?} without a guide", blurred.get(7, 8));
+
+        let albedo = step(16, 0.0, 1.0, 0.0);
+        let guided = Denoiser::new(1.0).denoise(&faint, None, Some(&albedo));
+        for y in 0..16 {
+            assert!((guided.get(7, y)[0] - 0.45).abs() < 1e-4, "{:?} left of the edge", guided.get(7, y));
+            assert!((guided.get(8, y)[0] - 0.55).abs() < 1e-4, "{:?} right of the edge", guided.get(8, y));
+        }
+    }
+}
@@ -62,6 +62,13 @@ impl VecRT<f32> {
 
         VecRT { _data: data }
     }
+
+    // only makes sense for 3 component vectors
+    pub fn cross(&self, rhs: &VecRT<f32>) -> VecRT<f32> {
+        VecRT::new3f(self[1] * rhs[2] - self[2] * rhs[1],
+                     self[2] * rhs[0] - self[0] * rhs[2],
+                     self[0] * rhs[1] - self[1] * rhs[0])
+    }
 }
 
 impl<T> Clone for VecRT<T>
@@ -137,6 +144,20 @@ impl<T> Mul<T> for &VecRT<T>
     }
 }
 
+// component-wise product, mostly for tinting one color by another
+impl<T> Mul<&VecRT<T>> for &VecRT<T>
+    where T: Copy + Mul<Output=T> {
+    type Output = VecRT<T>;
+
+    fn mul(self, rhs: &VecRT<T>) -> Self::Output {
+        let data = self._data.iter().zip(rhs._data.iter())
+            .map(|(&l, &r)| l * r)
+            .collect();
+
+        VecRT { _data: data }
+    }
+}
+
 // TODO what
 pub fn reflect(i: &Vec2f, n: &Vec2f) -> Vec2f {
     i - &(&(n * 2.0) * (i.dot(n)))
@@ -162,7 +183,7 @@ pub fn refract(incident_ray: &Vec3f, normal: &Vec3f, refractive_index: f32) -> V
     let k = 1.0 - ((ref_index_ratio * ref_index_ratio) * (1.0 - (cos_corrected * cos_corrected)));
 
     // if sin < 0, refraction angle is negative, so there is no refracted ray because total internal reflection
-    return if k < 0.0 {
+    if k < 0.0 {
         Vec3f::zero()
     } else {
         let r_i = incident_ray * ref_index_ratio;
@@ -180,3 +201,244 @@ pub fn max(a: f32, b: f32) -> f32 {
 pub fn min(a: f32, b: f32) -> f32 {
     if a < b { a } else { b }
 }
+
+/// 4x4 matrix for affine transforms of points and directions, stored row by row
+/// points are treated as columns (x, y, z, 1), so a * b applies b first and then a
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mat4 {
+    m: [[f32; 4]; 4],
+}
+
+impl Mat4 {
+    pub fn identity() -> Self {
+        Mat4 { m: [[1.0, 0.0, 0.0, 0.0],
+                   [0.0, 1.0, 0.0, 0.0],
+                   [0.0, 0.0, 1.0, 0.0],
+                   [0.0, 0.0, 0.0, 1.0]] }
+    }
+
+    pub fn translation(offset: &Vec3f) -> Self {
+        let mut t = Mat4::identity();
+        for i in 0..3 {
+            t.m[i][3] = offset[i as i32];
+        }
+        t
+    }
+
+    pub fn scaling(factors: &Vec3f) -> Self {
+        let mut s = Mat4::identity();
+        for i in 0..3 {
+            s.m[i][i] = factors[i as i32];
+        }
+        s
+    }
+
+    /// counterclockwise by angle radians when looking down axis towards the origin
+    pub fn rotation(axis: &Vec3f, angle: f32) -> Self {
+        let a = axis.normalize();
+        let (x, y, z) = (a[0], a[1], a[2]);
+        let (sin, cos) = angle.sin_cos();
+        let k = 1.0 - cos;
+
+        // rodrigues' rotation formula
+        Mat4 { m: [[cos + x * x * k, x * y * k - z * sin, x * z * k + y * sin, 0.0],
+                   [y * x * k + z * sin, cos + y * y * k, y * z * k - x * sin, 0.0],
+                   [z * x * k - y * sin, z * y * k + x * sin, cos + z * z * k, 0.0],
+                   [0.0, 0.0, 0.0, 1.0]] }
+    }
+
+    pub fn transpose(&self) -> Self {
+        let mut t = Mat4::identity();
+        for (i, row) in self.m.iter().enumerate() {
+            for (j, value) in row.iter().enumerate() {
+                t.m[j][i] = *value;
+            }
+        }
+        t
+    }
+
+    // of the upper left 3x3 part, which is all that matters for affine transforms
+    pub fn determinant(&self) -> f32 {
+        let m = &self.m;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    /// the least and the most the transform lengthens any direction by (its smallest and largest singular values)
+    /// the same for rotations and uniform scales, further apart the more unevenly it stretches things
+    pub fn stretch_range(&self) -> (f32, f32) {
+        // eigenvalues of the symmetric m^T m, which are the squared singular values
+        // found in closed form, see https://en.wikipedia.org/wiki/Eigenvalue_algorithm#3%C3%973_matrices
+        let m = &self.m;
+        let mut a = [[0.0f64; 3]; 3];
+        for (i, row) in a.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..3).map(|k| m[k][i] as f64 * m[k][j] as f64).sum();
+            }
+        }
+
+        let off_diagonal = a[0][1] * a[0][1] + a[0][2] * a[0][2] + a[1][2] * a[1][2];
+        let q = (a[0][0] + a[1][1] + a[2][2]) / 3.0;
+        let spread = (0..3).map(|i| (a[i][i] - q) * (a[i][i] - q)).sum::<f64>() + 2.0 * off_diagonal;
+        if spread <= 1e-24 * q * q {
+            return (q.sqrt() as f32, q.sqrt() as f32);
+        }
+        let p = (spread / 6.0).sqrt();
+        let b = |i: usize, j: usize| (a[i][j] - if i == j { q } else { 0.0 }) / p;
+        let det_b = b(0, 0) * (b(1, 1) * b(2, 2) - b(1, 2) * b(2, 1))
+            - b(0, 1) * (b(1, 0) * b(2, 2) - b(1, 2) * b(2, 0))
+            + b(0, 2) * (b(1, 0) * b(2, 1) - b(1, 1) * b(2, 0));
+        let phi = (det_b / 2.0).clamp(-1.0, 1.0).acos() / 3.0;
+        let largest = q + 2.0 * p * phi.cos();
+        let smallest = q + 2.0 * p * (phi + 2.0 * std::f64::consts::PI / 3.0).cos();
+
+        (smallest.max(0.0).sqrt() as f32, largest.max(0.0).sqrt() as f32)
+    }
+
+    /// inverse of an affine transform, None if it squashes space flat (e.g. a scale of 0)
+    pub fn inverse(&self) -> Option<Self> {
+        let det = self.determinant();
+        if det.abs() < 1e-12 {
+            return None;
+        }
+
+        // the linear part is inverted through its adjugate, the translation then has to be undone by that inverse
+        let m = &self.m;
+        let cofactor = |r0: usize, r1: usize, c0: usize, c1: usize| m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0];
+        let mut inv = Mat4::identity();
+        inv.m[0][0] = cofactor(1, 2, 1, 2) / det;
+        inv.m[0][1] = -cofactor(0, 2, 1, 2) / det;
+        inv.m[0][2] = cofactor(0, 1, 1, 2) / det;
+        inv.m[1][0] = -cofactor(1, 2, 0, 2) / det;
+        inv.m[1][1] = cofactor(0, 2, 0, 2) / det;
+        inv.m[1][2] = -cofactor(0, 1, 0, 2) / det;
+        inv.m[2][0] = cofactor(1, 2, 0, 1) / det;
+        inv.m[2][1] = -cofactor(0, 2, 0, 1) / det;
+        inv.m[2][2] = cofactor(0, 1, 0, 1) / det;
+
+        let offset = inv.transform_vector(&Vec3f::new3f(m[0][3], m[1][3], m[2][3]));
+        for i in 0..3 {
+            inv.m[i][3] = -offset[i as i32];
+        }
+        Some(inv)
+    }
+
+    pub fn transform_point(&self, p: &Vec3f) -> Vec3f {
+        let m = &self.m;
+        Vec3f::new3f(m[0][0] * p[0] + m[0][1] * p[1] + m[0][2] * p[2] + m[0][3],
+                     m[1][0] * p[0] + m[1][1] * p[1] + m[1][2] * p[2] + m[1][3],
+                     m[2][0] * p[0] + m[2][1] * p[1] + m[2][2] * p[2] + m[2][3])
+    }
+
+    // directions don't move with translation
+    pub fn transform_vector(&self, v: &Vec3f) -> Vec3f {
+        let m = &self.m;
+        Vec3f::new3f(m[0][0] * v[0] + m[0][1] * v[1] + m[0][2] * v[2],
+                     m[1][0] * v[0] + m[1][1] * v[1] + m[1][2] * v[2],
+                     m[2][0] * v[0] + m[2][1] * v[1] + m[2][2] * v[2])
+    }
+}
+
+impl Mul for &Mat4 {
+    type Output = Mat4;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        let mut product = Mat4 { m: [[0.0; 4]; 4] };
+        for i in 0..4 {
+            for j in 0..4 {
+                product.m[i][j] = (0..4).map(|k| self.m[i][k] * rhs.m[k][j]).sum();
+            }
+        }
+        product
+    }
+}
+
+/// straight line from a (at t = 0) to b (at t = 1)
+pub fn lerp(a: &Vec3f, b: &Vec3f, t: f32) -> Vec3f {
+    a + &(&(b - a) * t)
+}
+
+/// a scale, then a rotation (degrees around x, then y, then z), both around pivot, and then a move
+/// kept in pieces rather than as a matrix so that two of them can be blended, which blending matrices doesn't do for rotations
+#[derive(Debug, Clone)]
+pub struct Placement {
+    pub pivot: Vec3f,
+    pub scale: Vec3f,
+    pub rotation: Vec3f,
+    pub translation: Vec3f,
+}
+
+impl Placement {
+    pub fn matrix(&self) -> Mat4 {
+        let mut transform = &Mat4::scaling(&self.scale) * &Mat4::translation(&(&Vec3f::zero() - &self.pivot));
+        for (i, axis) in [Vec3f::new3f(1.0, 0.0, 0.0), Vec3f::new3f(0.0, 1.0, 0.0), Vec3f::new3f(0.0, 0.0, 1.0)].iter().enumerate() {
+            transform = &Mat4::rotation(axis, self.rotation[i as i32].to_radians()) * &transform;
+        }
+
+        &Mat4::translation(&(&self.pivot + &self.translation)) * &transform
+    }
+
+    /// part of the way from this placement to other, each piece blended on its own
+    pub fn lerp(&self, other: &Placement, t: f32) -> Placement {
+        Placement {
+            pivot: lerp(&self.pivot, &other.pivot, t),
+            scale: lerp(&self.scale, &other.scale, t),
+            rotation: lerp(&self.rotation, &other.rotation, t),
+            translation: lerp(&self.translation, &other.translation, t),
+        }
+    }
+}
+
+/// axis aligned bounding box
+#[derive(Debug, Clone)]
+pub struct Aabb {
+    pub min: Vec3f,
+    pub max: Vec3f,
+}
+
+impl Aabb {
+    // inside out, so that growing it by anything gives exactly that thing's bounds
+    pub fn empty() -> Self {
+        Aabb { min: Vec3f::new3f(f32::MAX, f32::MAX, f32::MAX), max: Vec3f::new3f(f32::MIN, f32::MIN, f32::MIN) }
+    }
+
+    pub fn grow(&mut self, p: &Vec3f) {
+        for i in 0..3 {
+            self.min[i] = self.min[i].min(p[i]);
+            self.max[i] = self.max[i].max(p[i]);
+        }
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        let mut bounds = self.clone();
+        bounds.grow(&other.min);
+        bounds.grow(&other.max);
+        bounds
+    }
+
+    pub fn center(&self) -> Vec3f {
+        &(&self.min + &self.max) * 0.5
+    }
+
+    // 0, 1 or 2 for x, y or z
+    pub fn longest_axis(&self) -> i32 {
+        let size = &self.max - &self.min;
+        if size[0] >= size[1] && size[0] >= size[2] { 0 } else if size[1] >= size[2] { 1 } else { 2 }
+    }
+
+    /// the stretch of distances along the ray spent inside the box, None if it misses or the box is behind orig
+    /// inv_dir is 1 / dir per component, worked out once per ray since boxes get tested a lot
+    pub fn ray_range(&self, orig: &Vec3f, inv_dir: &Vec3f) -> Option<(f32, f32)> {
+        let mut near = f32::MIN;
+        let mut far = f32::MAX;
+        for i in 0..3 {
+            let t0 = (self.min[i] - orig[i]) * inv_dir[i];
+            let t1 = (self.max[i] - orig[i]) * inv_dir[i];
+            near = near.max(t0.min(t1));
+            far = far.min(t0.max(t1));
+        }
+
+        if near <= far && far >= 0.0 { Some((near, far)) } else { None }
+    }
+}
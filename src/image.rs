@@ -0,0 +1,1225 @@
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+use crate::deflate;
+use crate::geometry::{self, Vec3f};
+use crate::inflate;
+
+/// linear float RGB image, rows stored top to bottom
+/// optionally with an alpha channel, the colors then being premultiplied by it
+#[derive(Debug, Clone)]
+pub struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec<Vec3f>,
+    // how much of each pixel is covered, None for images that are opaque all over
+    alpha: Option<Vec<f32>>,
+}
+
+/// how bright a linear rgb color looks, with the rec. 709 weights
+pub fn luminance(c: &Vec3f) -> f32 {
+    0.2126 * c[0] + 0.7152 * c[1] + 0.0722 * c[2]
+}
+
+/// black for 0, through red and yellow to white for 1 and over
+pub fn heat(t: f32) -> Vec3f {
+    let t = 3.0 * t;
+    Vec3f::new3f(t.clamp(0.0, 1.0), (t - 1.0).clamp(0.0, 1.0), (t - 2.0).clamp(0.0, 1.0))
+}
+
+// exr pixel types
+const EXR_UINT: i32 = 0;
+const EXR_HALF: i32 = 1;
+const EXR_FLOAT: i32 = 2;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg.to_string())
+}
+
+// product of sizes read from a header, which a bad one can make overflow
+fn checked_size(factors: &[usize], msg: &str) -> io::Result<usize> {
+    factors.iter().try_fold(1usize, |size, &factor| size.checked_mul(factor)).ok_or_else(|| invalid(msg))
+}
+
+// the count bytes starting at pos, if they're all there
+fn checked_slice(bytes: &[u8], pos: usize, count: usize) -> Option<&[u8]> {
+    bytes.get(pos..)?.get(..count)
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize, pixels: Vec<Vec3f>) -> Self {
+        assert_eq!(pixels.len(), width * height, "pixel count doesn't match {}x{}", width, height);
+
+        Image { width, height, pixels, alpha: None }
+    }
+
+    pub fn with_alpha(mut self, alpha: Vec<f32>) -> Self {
+        assert_eq!(alpha.len(), self.width * self.height, "alpha count doesn't match {}x{}", self.width, self.height);
+        self.alpha = Some(alpha);
+        self
+    }
+
+    /// the colors as they'd be if the image was opaque, for textures and the like that only care about color
+    pub fn without_alpha(self) -> Self {
+        let pixels = match &self.alpha {
+            Some(alpha) => self.pixels.iter().zip(alpha).map(|(px, &a)| if a > 0.0 { px * (1.0 / a) } else { Vec3f::zero() }).collect(),
+            None => self.pixels,
+        };
+        Image::new(self.width, self.height, pixels)
+    }
+
+    /// picks a decoder based on the file extension
+    pub fn load(path: &str) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+        let extension = Path::new(path).extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase());
+
+        match extension.as_deref() {
+            Some("hdr") | Some("pic") => Image::decode_hdr(&bytes),
+            Some("pfm") => Image::decode_pfm(&bytes),
+            Some("ppm") => Image::decode_ppm(&bytes),
+            Some("png") => Image::decode_png(&bytes),
+            Some("exr") => Image::decode_exr(&bytes),
+            _ => Err(io::Error::new(ErrorKind::Unsupported, format!("don't know how to read {}", path))),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> &Vec3f {
+        &self.pixels[y * self.width + x]
+    }
+
+    /// 1 everywhere for images without an alpha channel
+    pub fn alpha(&self, x: usize, y: usize) -> f32 {
+        self.alpha.as_ref().map_or(1.0, |alpha| alpha[y * self.width + x])
+    }
+
+    /// copy other over this image, with its top left corner at (x, y)
+    pub fn paste(&mut self, other: &Image, x: usize, y: usize) {
+        assert!(x + other.width <= self.width && y + other.height <= self.height, "{}x{} image doesn't fit at {},{}", other.width, other.height, x, y);
+
+        // anything opaque pasted into something that isn't stays opaque, and the other way around
+        if other.alpha.is_some() && self.alpha.is_none() {
+            self.alpha = Some(vec![1.0; self.width * self.height]);
+        }
+        for row in 0..other.height {
+            let start = (y + row) * self.width + x;
+            self.pixels[start..start + other.width].clone_from_slice(&other.pixels[row * other.width..(row + 1) * other.width]);
+            if let Some(alpha) = &mut self.alpha {
+                for (i, a) in alpha[start..start + other.width].iter_mut().enumerate() {
+                    *a = other.alpha(i, row);
+                }
+            }
+        }
+    }
+
+    /// the width by height rectangle with its top left corner at (x, y)
+    pub fn region(&self, x: usize, y: usize, width: usize, height: usize) -> Image {
+        assert!(x + width <= self.width && y + height <= self.height, "{}x{} region at {},{} doesn't fit in the image", width, height, x, y);
+
+        let pixels = (y..y + height).flat_map(|row| self.pixels[row * self.width + x..row * self.width + x + width].iter().cloned()).collect();
+        let region = Image::new(width, height, pixels);
+        match &self.alpha {
+            Some(alpha) => region.with_alpha((y..y + height).flat_map(|row| alpha[row * self.width + x..row * self.width + x + width].iter().copied()).collect()),
+            None => region,
+        }
+    }
+
+    /// picks an encoder based on the file extension, like load
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let extension = Path::new(path).extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase());
+
+        let bytes = match extension.as_deref() {
+            Some("ppm") => self.encode_ppm(),
+            Some("png") => self.encode_png(),
+            Some("pfm") => self.encode_pfm(),
+            Some("exr") => self.encode_exr(),
+            _ => return Err(io::Error::new(ErrorKind::Unsupported, format!("don't know how to write {}", path))),
+        };
+
+        fs::write(path, bytes)
+    }
+
+    // 8 bits per channel, anything outside [0, 1] clipped
+    // TODO gamma/color correction
+    // formats without an alpha channel get the premultiplied colors, i.e. the image over black
+    fn bytes(&self) -> Vec<u8> {
+        self.pixels.iter()
+            .flat_map(|px| (0..3).map(move |channel| to_byte(px[channel])))
+            .collect()
+    }
+
+    // the same with alpha after each pixel, and the colors no longer premultiplied, since that's how png has them
+    fn rgba_bytes(&self) -> Vec<u8> {
+        self.pixels.iter().enumerate()
+            .flat_map(|(i, px)| {
+                let alpha = self.alpha.as_ref().map_or(1.0, |alpha| alpha[i]);
+                // nudged up a hair, or the rounding from premultiplying can knock a png that's read back in and written out again down a level
+                let channel = |c: i32| if alpha > 0.0 { to_byte(px[c] / alpha + 1e-5) } else { 0 };
+                [channel(0), channel(1), channel(2), to_byte(alpha)]
+            })
+            .collect()
+    }
+
+    fn encode_ppm(&self) -> Vec<u8> {
+        let mut out = format!("P6\n{} {}\n255\n", self.width, self.height).into_bytes();
+        out.extend(self.bytes());
+        out
+    }
+
+    // full float values, nothing clipped, for anything that isn't meant to be looked at directly
+    // little endian (the negative scale), and rows bottom to top like decode_pfm expects
+    fn encode_pfm(&self) -> Vec<u8> {
+        let mut out = format!("PF\n{} {}\n-1.0\n", self.width, self.height).into_bytes();
+        for y in (0..self.height).rev() {
+            for px in &self.pixels[y * self.width..(y + 1) * self.width] {
+                for channel in 0..3 {
+                    out.extend_from_slice(&px[channel].to_le_bytes());
+                }
+            }
+        }
+        out
+    }
+
+    fn encode_png(&self) -> Vec<u8> {
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&(self.width as u32).to_be_bytes());
+        header.extend_from_slice(&(self.height as u32).to_be_bytes());
+        // 8 bit truecolor (with alpha if there is any), then the only compression and filter methods there are, and no interlacing
+        let (color_type, channels, bytes) = if self.alpha.is_some() { (6, 4, self.rgba_bytes()) } else { (2, 3, self.bytes()) };
+        header.extend_from_slice(&[8, color_type, 0, 0, 0]);
+
+        let stride = self.width * channels;
+        let mut raw = Vec::with_capacity((stride + 1) * self.height);
+        let mut previous = vec![0u8; stride];
+        for row in bytes.chunks_exact(stride) {
+            let (filter, filtered) = filter_png_row(row, &previous, channels);
+            raw.push(filter);
+            raw.extend(filtered);
+            previous = row.to_vec();
+        }
+
+        let mut out = vec![0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+        write_png_chunk(&mut out, b"IHDR", &header);
+        write_png_chunk(&mut out, b"IDAT", &deflate::zlib_compress(&raw));
+        write_png_chunk(&mut out, b"IEND", &[]);
+        out
+    }
+
+    // openexr, see https://openexr.com/en/latest/OpenEXRFileLayout.html
+    // the simplest kind there is: one part, uncompressed scanlines of 32 bit floats, premultiplied like exr expects
+    fn encode_exr(&self) -> Vec<u8> {
+        // channels have to be in alphabetical order
+        let channels: &[&str] = if self.alpha.is_some() { &["A", "B", "G", "R"] } else { &["B", "G", "R"] };
+        let mut out = vec![0x76, 0x2F, 0x31, 0x01, 2, 0, 0, 0];
+
+        let attribute = |out: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]| {
+            for text in [name, kind] {
+                out.extend_from_slice(text.as_bytes());
+                out.push(0);
+            }
+            out.extend_from_slice(&(value.len() as i32).to_le_bytes());
+            out.extend_from_slice(value);
+        };
+        let mut list = Vec::new();
+        for name in channels {
+            list.extend_from_slice(name.as_bytes());
+            // a 0 terminator, then float pixels, not linear (whatever that means), 3 reserved bytes and no subsampling
+            list.push(0);
+            list.extend_from_slice(&EXR_FLOAT.to_le_bytes());
+            list.extend_from_slice(&[0, 0, 0, 0]);
+            list.extend_from_slice(&1i32.to_le_bytes());
+            list.extend_from_slice(&1i32.to_le_bytes());
+        }
+        list.push(0);
+        let window: Vec<u8> = [0, 0, self.width as i32 - 1, self.height as i32 - 1].iter().flat_map(|v| v.to_le_bytes()).collect();
+        attribute(&mut out, "channels", "chlist", &list);
+        attribute(&mut out, "compression", "compression", &[0]);
+        attribute(&mut out, "dataWindow", "box2i", &window);
+        attribute(&mut out, "displayWindow", "box2i", &window);
+        attribute(&mut out, "lineOrder", "lineOrder", &[0]);
+        attribute(&mut out, "pixelAspectRatio", "float", &1f32.to_le_bytes());
+        attribute(&mut out, "screenWindowCenter", "v2f", &[0; 8]);
+        attribute(&mut out, "screenWindowWidth", "float", &1f32.to_le_bytes());
+        out.push(0);
+
+        // then where each scanline starts, and the scanlines themselves, each channel's values for the whole line after one another
+        let line_size = self.width * channels.len() * 4;
+        let first_line = out.len() + 8 * self.height;
+        for y in 0..self.height {
+            out.extend_from_slice(&((first_line + y * (8 + line_size)) as u64).to_le_bytes());
+        }
+        for y in 0..self.height {
+            out.extend_from_slice(&(y as i32).to_le_bytes());
+            out.extend_from_slice(&(line_size as i32).to_le_bytes());
+            for name in channels {
+                for x in 0..self.width {
+                    let value = match *name {
+                        "A" => self.alpha(x, y),
+                        "B" => self.get(x, y)[2],
+                        "G" => self.get(x, y)[1],
+                        _ => self.get(x, y)[0],
+                    };
+                    out.extend_from_slice(&value.to_le_bytes());
+                }
+            }
+        }
+        out
+    }
+
+    // 8 bit formats are read as is, with no gamma decoding, since that's also how we write them out
+
+    // binary (P6) ppm like encode_ppm writes, or the plain text (P3) kind
+    // either with up to 16 bits per sample, the binary kind then taking two bytes (most significant first) for each one
+    fn decode_ppm(bytes: &[u8]) -> io::Result<Self> {
+        let (tokens, pos) = header_tokens(bytes, 4)?;
+        let width: usize = tokens[1].parse().map_err(|_| invalid("bad ppm width"))?;
+        let height: usize = tokens[2].parse().map_err(|_| invalid("bad ppm height"))?;
+        let max_value: u32 = tokens[3].parse().map_err(|_| invalid("bad ppm max value"))?;
+        if max_value == 0 || max_value > 65535 {
+            return Err(invalid("bad ppm max value"));
+        }
+        let count = checked_size(&[width, height, 3], "ppm too big")?;
+
+        let samples: Vec<u32> = match tokens[0].as_str() {
+            "P6" if max_value < 256 => checked_slice(bytes, pos, count).ok_or_else(|| invalid("truncated ppm data"))?
+                .iter().map(|&b| b as u32).collect(),
+            "P6" => checked_slice(bytes, pos, checked_size(&[count, 2], "ppm too big")?).ok_or_else(|| invalid("truncated ppm data"))?
+                .chunks_exact(2).map(|b| u16::from_be_bytes([b[0], b[1]]) as u32).collect(),
+            // every value takes at least a digit and the space before it
+            "P3" if count > (bytes.len() + 1 - pos) / 2 => return Err(invalid("truncated ppm data")),
+            "P3" => {
+                let (values, _) = header_tokens(&bytes[pos - 1..], count).map_err(|_| invalid("truncated ppm data"))?;
+                values.iter().map(|v| v.parse().map_err(|_| invalid("bad ppm sample"))).collect::<io::Result<Vec<u32>>>()?
+            }
+            _ => return Err(invalid("not a ppm file")),
+        };
+
+        let max_value = max_value as f32;
+        let pixels = samples.chunks_exact(3)
+            .map(|px| Vec3f::new3f(px[0] as f32 / max_value, px[1] as f32 / max_value, px[2] as f32 / max_value))
+            .collect();
+
+        Ok(Image::new(width, height, pixels))
+    }
+
+    // see https://www.w3.org/TR/png/
+    // handles every kind there is: greyscale, truecolor and palette, 1 to 16 bits per sample, interlaced or not
+    // 16 bit samples are read at full precision, and any alpha (from its own channel or a tRNS chunk) gets premultiplied in
+    fn decode_png(bytes: &[u8]) -> io::Result<Self> {
+        if bytes.get(..8) != Some(&[0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'][..]) {
+            return Err(invalid("not a png file"));
+        }
+
+        let mut pos = 8;
+        let mut header = None;
+        let mut palette = Vec::new();
+        let mut transparency = None;
+        let mut compressed = Vec::new();
+        while pos + 8 <= bytes.len() {
+            let len = u32::from_be_bytes([bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]]) as usize;
+            let kind = &bytes[pos + 4..pos + 8];
+            let data = checked_slice(bytes, pos + 8, len).ok_or_else(|| invalid("truncated png chunk"))?;
+            // skip over the crc too
+            pos += 12 + len;
+
+            match kind {
+                b"IHDR" => header = Some(data.to_vec()),
+                b"PLTE" => palette = data.chunks_exact(3).map(|c| [c[0], c[1], c[2]]).collect(),
+                b"tRNS" => transparency = Some(data.to_vec()),
+                b"IDAT" => compressed.extend_from_slice(data),
+                b"IEND" => break,
+                _ => {}
+            }
+        }
+
+        let header = header.filter(|h| h.len() == 13).ok_or_else(|| invalid("missing png header"))?;
+        let width = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let height = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        let (bit_depth, color_type, interlace) = (header[8] as usize, header[9], header[12]);
+        let (channels, depths): (usize, &[usize]) = match color_type {
+            0 => (1, &[1, 2, 4, 8, 16]),
+            2 => (3, &[8, 16]),
+            3 => (1, &[1, 2, 4, 8]),
+            4 => (2, &[8, 16]),
+            6 => (4, &[8, 16]),
+            _ => return Err(invalid("unsupported png color type")),
+        };
+        if !depths.contains(&bit_depth) {
+            return Err(invalid("bad png bit depth for its color type"));
+        }
+        if color_type == 3 && palette.is_empty() {
+            return Err(invalid("missing png palette"));
+        }
+        // tRNS is an alpha for each palette entry, or else the one color (as 16 bit samples) that's see-through
+        // greyscale and truecolor images with an alpha channel of their own shouldn't have one
+        let (palette_alpha, key) = match (color_type, transparency) {
+            (3, Some(alphas)) => (alphas, None),
+            (0, Some(t)) if t.len() == 2 => (Vec::new(), Some(vec![u16::from_be_bytes([t[0], t[1]]) as u32])),
+            (2, Some(t)) if t.len() == 6 => (Vec::new(), Some(t.chunks_exact(2).map(|c| u16::from_be_bytes([c[0], c[1]]) as u32).collect())),
+            (0 | 2, Some(_)) => return Err(invalid("bad png transparency")),
+            _ => (Vec::new(), None),
+        };
+        let has_alpha = color_type == 4 || color_type == 6 || !palette_alpha.is_empty() || key.is_some();
+        let format = PngFormat { color_type, bit_depth, channels, palette, palette_alpha, key };
+
+        let raw = inflate::zlib_decompress(&compressed)?;
+        // whatever the filtering and interlacing, every sample's bits have to be in there somewhere
+        if checked_size(&[width, height, channels, bit_depth], "png too big")? > raw.len().saturating_mul(8) {
+            return Err(invalid("png image data too short"));
+        }
+        let mut pixels = vec![Vec3f::zero(); width * height];
+        let mut alpha = vec![1.0; width * height];
+        // adam7 sends every 8th pixel of every 8th row first, then fills in between them over 6 more passes
+        // each pass being a little image of its own, filtered separately; without interlacing there's just the one
+        let passes: &[(usize, usize, usize, usize)] = match interlace {
+            0 => &[(0, 0, 1, 1)],
+            1 => &[(0, 0, 8, 8), (4, 0, 8, 8), (0, 4, 4, 8), (2, 0, 4, 4), (0, 2, 2, 4), (1, 0, 2, 2), (0, 1, 1, 2)],
+            _ => return Err(invalid("unknown png interlace method")),
+        };
+
+        let mut data = &raw[..];
+        for &(x0, y0, dx, dy) in passes {
+            let pass_width = (width + dx - 1 - x0) / dx;
+            let pass_height = (height + dy - 1 - y0) / dy;
+            if pass_width == 0 || pass_height == 0 {
+                continue;
+            }
+
+            let stride = (pass_width * channels * bit_depth).div_ceil(8);
+            // filters work on whole bytes, so smaller samples get compared with the byte before
+            let bpp = (channels * bit_depth / 8).max(1);
+            let mut previous = vec![0u8; stride];
+            for y in 0..pass_height {
+                let line = data.get(..stride + 1).ok_or_else(|| invalid("png image data too short"))?;
+                let mut row = line[1..].to_vec();
+                unfilter_png_row(line[0], &mut row, &previous, bpp)?;
+                data = &data[stride + 1..];
+
+                for (x, (pixel, a)) in format.pixels(&row, pass_width)?.into_iter().enumerate() {
+                    let i = (y0 + y * dy) * width + x0 + x * dx;
+                    pixels[i] = &pixel * a;
+                    alpha[i] = a;
+                }
+                previous = row;
+            }
+        }
+
+        let image = Image::new(width, height, pixels);
+        Ok(if has_alpha { image.with_alpha(alpha) } else { image })
+    }
+
+    // radiance RGBE, see https://www.graphics.cornell.edu/~bjw/rgbe.html
+    // each pixel is an 8 bit mantissa per channel sharing one 8 bit exponent
+    fn decode_hdr(bytes: &[u8]) -> io::Result<Self> {
+        let mut pos = 0;
+        let mut next_line = || {
+            let start = pos;
+            while pos < bytes.len() && bytes[pos] != b'\n' {
+                pos += 1;
+            }
+            let line = String::from_utf8_lossy(&bytes[start..pos]).into_owned();
+            // running off the end just gives empty lines, which the header and resolution line checks turn into errors
+            pos = (pos + 1).min(bytes.len());
+            line
+        };
+
+        if !next_line().starts_with("#?") {
+            return Err(invalid("missing radiance header"));
+        }
+
+        // header is a list of VARIABLE=value lines terminated by an empty line
+        // the pixel values have already been multiplied by any EXPOSUREs in it, which we undo to get back the actual radiance
+        let mut exposure = 1.0;
+        loop {
+            let line = next_line();
+            if line.is_empty() {
+                break;
+            }
+            if line.starts_with("FORMAT=") && line != "FORMAT=32-bit_rle_rgbe" {
+                return Err(invalid("only 32-bit_rle_rgbe hdr files are supported"));
+            }
+            if let Some(value) = line.strip_prefix("EXPOSURE=") {
+                exposure *= value.trim().parse::<f32>().ok().filter(|e| *e > 0.0).ok_or_else(|| invalid("bad hdr exposure"))?;
+            }
+        }
+
+        // something like "-Y height +X width", the usual one, which has scanlines going top to bottom and each one left to right
+        // the first axis is the one the scanlines step along, and a sign of + means in the direction of increasing y (upwards) or x
+        let resolution = next_line();
+        let fields: Vec<&str> = resolution.split_whitespace().collect();
+        let axis = |i: usize| match fields.get(i).copied() {
+            Some(field @ ("-Y" | "+Y" | "-X" | "+X")) => Ok((field.ends_with('Y'), field.starts_with('+'))),
+            _ => Err(invalid("unsupported hdr resolution line")),
+        };
+        let (outer_is_y, outer_up) = axis(0)?;
+        let (inner_is_y, inner_up) = axis(2)?;
+        if fields.len() != 4 || outer_is_y == inner_is_y {
+            return Err(invalid("unsupported hdr resolution line"));
+        }
+        let outer: usize = fields[1].parse().map_err(|_| invalid("bad hdr resolution"))?;
+        let inner: usize = fields[3].parse().map_err(|_| invalid("bad hdr resolution"))?;
+        let (width, height) = if outer_is_y { (inner, outer) } else { (outer, inner) };
+        let data = &bytes[pos.min(bytes.len())..];
+        if inner == 0 {
+            return Err(invalid("empty hdr scanlines"));
+        }
+        if checked_size(&[outer, hdr_scanline_size(inner)], "hdr too big")? > data.len() {
+            return Err(invalid("truncated hdr data"));
+        }
+
+        // position along an axis in image coordinates, where y goes down
+        let place = |i: usize, len: usize, is_y: bool, up: bool| if is_y == up { len - 1 - i } else { i };
+
+        let mut data = data;
+        let mut pixels = vec![Vec3f::zero(); width * height];
+        let mut scanline = vec![[0u8; 4]; inner];
+
+        for i in 0..outer {
+            data = read_hdr_scanline(data, &mut scanline)?;
+            let a = place(i, outer, outer_is_y, outer_up);
+            for (j, rgbe) in scanline.iter().enumerate() {
+                let b = place(j, inner, inner_is_y, inner_up);
+                let (x, y) = if outer_is_y { (b, a) } else { (a, b) };
+                pixels[y * width + x] = &rgbe_to_float(rgbe) * (1.0 / exposure);
+            }
+        }
+
+        Ok(Image::new(width, height, pixels))
+    }
+
+    // the kind of exr encode_exr writes: a single part of uncompressed scanlines, in 16 or 32 bit floats or 32 bit ints
+    // r, g and b channels, and a (taken as already premultiplied) if there is one, anything else being ignored
+    fn decode_exr(bytes: &[u8]) -> io::Result<Self> {
+        if bytes.get(..4) != Some(&[0x76, 0x2F, 0x31, 0x01][..]) {
+            return Err(invalid("not an exr file"));
+        }
+        // version 2, with none of the tiled, long names, deep data or multipart flags set
+        if bytes.get(4..8) != Some(&[2, 0, 0, 0][..]) {
+            return Err(invalid("only single part scanline exr files are supported"));
+        }
+
+        let truncated = || invalid("truncated exr header");
+        let int = |b: &[u8]| i32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+        let text = |pos: usize| -> io::Result<(String, usize)> {
+            let end = bytes[pos..].iter().position(|&b| b == 0).map(|i| pos + i).ok_or_else(truncated)?;
+            Ok((String::from_utf8_lossy(&bytes[pos..end]).into_owned(), end + 1))
+        };
+
+        let mut pos = 8;
+        let mut channels = Vec::new();
+        let mut window = None;
+        loop {
+            let (name, next) = text(pos)?;
+            if name.is_empty() {
+                pos = next;
+                break;
+            }
+            let (_, next) = text(next)?;
+            let size = checked_slice(bytes, next, 4).map(int).ok_or_else(truncated)?;
+            if size < 0 {
+                return Err(invalid("bad exr attribute size"));
+            }
+            let size = size as usize;
+            let value = checked_slice(bytes, next + 4, size).ok_or_else(truncated)?;
+            pos = next + 4 + size;
+
+            match name.as_str() {
+                "channels" => {
+                    let mut i = 0;
+                    while value.get(i).is_some_and(|&b| b != 0) {
+                        let end = value[i..].iter().position(|&b| b == 0).map(|n| i + n).ok_or_else(truncated)?;
+                        let kind = value.get(end + 1..end + 5).map(int).ok_or_else(truncated)?;
+                        channels.push((String::from_utf8_lossy(&value[i..end]).into_owned(), kind));
+                        i = end + 17;
+                    }
+                }
+                "compression" if value != [0] => return Err(invalid("only uncompressed exr files are supported")),
+                "dataWindow" if value.len() == 16 => window = Some((int(&value[0..4]), int(&value[4..8]), int(&value[8..12]), int(&value[12..16]))),
+                _ => {}
+            }
+        }
+
+        let (x_min, y_min, x_max, y_max) = window.ok_or_else(|| invalid("missing exr data window"))?;
+        if x_max < x_min || y_max < y_min {
+            return Err(invalid("bad exr data window"));
+        }
+        // in 64 bits, since a window running from one end of the i32s to the other doesn't fit in one
+        let (width, height) = ((x_max as i64 - x_min as i64 + 1) as usize, (y_max as i64 - y_min as i64 + 1) as usize);
+        // bytes per value of each channel, which are in alphabetical order in the scanlines
+        let sizes = channels.iter()
+            .map(|(_, kind)| match *kind {
+                EXR_HALF => Ok(2),
+                EXR_UINT | EXR_FLOAT => Ok(4),
+                _ => Err(invalid("unknown exr pixel type")),
+            })
+            .collect::<io::Result<Vec<usize>>>()?;
+        let line_size = checked_size(&[sizes.iter().sum(), width], "exr too big")?;
+        // each scanline has its own offset, then its y and size, before its data
+        let chunk_size = line_size.checked_add(16).ok_or_else(|| invalid("exr too big"))?;
+        if checked_size(&[height, chunk_size], "exr too big")? > bytes.len() - pos {
+            return Err(invalid("truncated exr data"));
+        }
+
+        let mut values = vec![vec![0.0f32; width * height]; channels.len()];
+        for line in 0..height {
+            let offset = checked_slice(bytes, pos + 8 * line, 8).ok_or_else(truncated)?;
+            let start = u64::from_le_bytes([offset[0], offset[1], offset[2], offset[3], offset[4], offset[5], offset[6], offset[7]]) as usize;
+            let y = checked_slice(bytes, start, 4).map(int).ok_or_else(|| invalid("truncated exr data"))? as i64 - y_min as i64;
+            let data = checked_slice(bytes, start.saturating_add(8), line_size).ok_or_else(|| invalid("truncated exr data"))?;
+            if y < 0 || y as usize >= height {
+                return Err(invalid("exr scanline outside the data window"));
+            }
+
+            let mut i = 0;
+            for (c, (_, kind)) in channels.iter().enumerate() {
+                for x in 0..width {
+                    values[c][y as usize * width + x] = match *kind {
+                        EXR_HALF => half_to_f32(u16::from_le_bytes([data[i], data[i + 1]])),
+                        EXR_UINT => int(&data[i..i + 4]) as u32 as f32,
+                        _ => f32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]),
+                    };
+                    i += sizes[c];
+                }
+            }
+        }
+
+        let channel = |name: &str| channels.iter().position(|(n, _)| n == name);
+        let (r, g, b) = match (channel("R"), channel("G"), channel("B"), channel("Y")) {
+            (Some(r), Some(g), Some(b), _) => (r, g, b),
+            (_, _, _, Some(y)) => (y, y, y),
+            _ => return Err(invalid("exr file has no r, g and b or y channels")),
+        };
+        let pixels = (0..width * height).map(|i| Vec3f::new3f(values[r][i], values[g][i], values[b][i])).collect();
+
+        let image = Image::new(width, height, pixels);
+        Ok(match channel("A") {
+            Some(a) => image.with_alpha(values[a].clone()),
+            None => image,
+        })
+    }
+
+    // portable float map, the floating point cousin of ppm
+    // header is "PF" (rgb) or "Pf" (greyscale), dimensions, then a scale whose sign gives the endianness
+    fn decode_pfm(bytes: &[u8]) -> io::Result<Self> {
+        let (tokens, pos) = header_tokens(bytes, 4)?;
+
+        let channels = match tokens[0].as_str() {
+            "PF" => 3,
+            "Pf" => 1,
+            _ => return Err(invalid("not a pfm file")),
+        };
+        let width: usize = tokens[1].parse().map_err(|_| invalid("bad pfm width"))?;
+        let height: usize = tokens[2].parse().map_err(|_| invalid("bad pfm height"))?;
+        let scale: f32 = tokens[3].parse().map_err(|_| invalid("bad pfm scale"))?;
+        let little_endian = scale < 0.0;
+
+        let data = checked_slice(bytes, pos, checked_size(&[width, height, channels, 4], "pfm too big")?)
+            .ok_or_else(|| invalid("truncated pfm data"))?;
+        let floats: Vec<f32> = data.chunks_exact(4)
+            .map(|b| {
+                let raw = [b[0], b[1], b[2], b[3]];
+                if little_endian { f32::from_le_bytes(raw) } else { f32::from_be_bytes(raw) }
+            })
+            .collect();
+
+        // pfm rows go bottom to top
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in (0..height).rev() {
+            for x in 0..width {
+                let i = (y * width + x) * channels;
+                pixels.push(if channels == 3 {
+                    Vec3f::new3f(floats[i], floats[i + 1], floats[i + 2])
+                } else {
+                    Vec3f::new3f(floats[i], floats[i], floats[i])
+                });
+            }
+        }
+
+        Ok(Image::new(width, height, pixels))
+    }
+}
+
+// netpbm style header: whitespace separated tokens, with # comments running to the end of the line
+// returns the tokens and the position of the data, which starts after exactly one whitespace byte
+fn header_tokens(bytes: &[u8], count: usize) -> io::Result<(Vec<String>, usize)> {
+    let mut pos = 0;
+    let mut tokens = Vec::new();
+    while tokens.len() < count {
+        while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        if bytes.get(pos) == Some(&b'#') {
+            while pos < bytes.len() && bytes[pos] != b'\n' {
+                pos += 1;
+            }
+            continue;
+        }
+        let start = pos;
+        while pos < bytes.len() && !bytes[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        if start == pos {
+            return Err(invalid("truncated image header"));
+        }
+        tokens.push(String::from_utf8_lossy(&bytes[start..pos]).into_owned());
+    }
+
+    Ok((tokens, pos + 1))
+}
+
+// what the samples in a png's rows mean
+struct PngFormat {
+    color_type: u8,
+    bit_depth: usize,
+    channels: usize,
+    palette: Vec<[u8; 3]>,
+    // alpha of the palette entries, any past the end of it being opaque
+    palette_alpha: Vec<u8>,
+    // samples of the one greyscale or truecolor value that's transparent, if there is one
+    key: Option<Vec<u32>>,
+}
+
+impl PngFormat {
+    // the first width pixels of an unfiltered row, and their alpha
+    fn pixels(&self, row: &[u8], width: usize) -> io::Result<Vec<(Vec3f, f32)>> {
+        // samples smaller than a byte are packed in from the most significant bit
+        let sample = |i: usize| -> u32 {
+            match self.bit_depth {
+                8 => row[i] as u32,
+                16 => u16::from_be_bytes([row[2 * i], row[2 * i + 1]]) as u32,
+                depth => {
+                    let bit = i * depth;
+                    ((row[bit / 8] >> (8 - depth - bit % 8)) & ((1 << depth) - 1) as u8) as u32
+                }
+            }
+        };
+        let mask = (1u32 << self.bit_depth) - 1;
+        let max_value = mask as f32;
+
+        (0..width).map(|x| {
+            let first = x * self.channels;
+            let value = |c: usize| sample(first + c) as f32 / max_value;
+            let alpha = match self.color_type {
+                3 => self.palette_alpha.get(sample(first) as usize).map_or(1.0, |&a| a as f32 / 255.0),
+                4 => value(1),
+                6 => value(3),
+                // only as many bits of the key as the image has are meant to count
+                _ if self.key.as_ref().is_some_and(|key| key.iter().enumerate().all(|(c, &k)| sample(first + c) == k & mask)) => 0.0,
+                _ => 1.0,
+            };
+            Ok((match self.color_type {
+                3 => {
+                    let [r, g, b] = *self.palette.get(sample(first) as usize).ok_or_else(|| invalid("png palette index out of range"))?;
+                    Vec3f::new3f(r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0)
+                }
+                0 | 4 => Vec3f::new3f(value(0), value(0), value(0)),
+                _ => Vec3f::new3f(value(0), value(1), value(2)),
+            }, alpha))
+        }).collect()
+    }
+}
+
+// undo png's per row prediction in place, bpp being the bytes per pixel
+fn unfilter_png_row(filter: u8, row: &mut [u8], previous: &[u8], bpp: usize) -> io::Result<()> {
+    for i in 0..row.len() {
+        let left = if i >= bpp { row[i - bpp] } else { 0 };
+        let up = previous[i];
+        let up_left = if i >= bpp { previous[i - bpp] } else { 0 };
+
+        let prediction = match filter {
+            0 => 0,
+            1 => left,
+            2 => up,
+            3 => ((left as u16 + up as u16) / 2) as u8,
+            4 => paeth(left, up, up_left),
+            _ => return Err(invalid("unknown png filter")),
+        };
+        row[i] = row[i].wrapping_add(prediction);
+    }
+
+    Ok(())
+}
+
+// the other way around: tries every filter and keeps whichever leaves the smallest numbers behind
+// (counting bytes as signed), which tends to be what deflate can squeeze the most
+fn filter_png_row(row: &[u8], previous: &[u8], bpp: usize) -> (u8, Vec<u8>) {
+    (0..5u8).map(|filter| {
+        let filtered: Vec<u8> = (0..row.len()).map(|i| {
+            let left = if i >= bpp { row[i - bpp] } else { 0 };
+            let up = previous[i];
+            let up_left = if i >= bpp { previous[i - bpp] } else { 0 };
+
+            let prediction = match filter {
+                0 => 0,
+                1 => left,
+                2 => up,
+                3 => ((left as u16 + up as u16) / 2) as u8,
+                _ => paeth(left, up, up_left),
+            };
+            row[i].wrapping_sub(prediction)
+        }).collect();
+
+        (filter, filtered)
+    }).min_by_key(|(_, filtered)| filtered.iter().map(|&b| (b as i8).unsigned_abs() as u32).sum::<u32>()).unwrap()
+}
+
+fn to_byte(value: f32) -> u8 {
+    (255f32 * geometry::max(0f32, geometry::min(1f32, value))) as u8
+}
+
+// ieee 754 half precision, 1 sign bit, 5 exponent bits and 10 mantissa bits
+fn half_to_f32(half: u16) -> f32 {
+    let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((half >> 10) & 0x1F) as i32;
+    let mantissa = (half & 0x3FF) as f32;
+    sign * match exponent {
+        // subnormal
+        0 => mantissa * 2f32.powi(-24),
+        0x1F if mantissa == 0.0 => f32::INFINITY,
+        0x1F => f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+// length, type, data and a crc of the type and data
+fn write_png_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32(&out[start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+// the crc-32 used by png (and zip, and ethernet), one bit at a time
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+// whichever of the three neighbours is closest to left + up - up_left
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+
+    if pa <= pb && pa <= pc { a } else if pb <= pc { b } else { c }
+}
+
+fn rgbe_to_float(rgbe: &[u8; 4]) -> Vec3f {
+    if rgbe[3] == 0 {
+        return Vec3f::zero();
+    }
+
+    // mantissas are in [0, 256), so scale by 2^(e - 128) / 256
+    let f = 2f32.powi(rgbe[3] as i32 - 136);
+    Vec3f::new3f(rgbe[0] as f32 * f, rgbe[1] as f32 * f, rgbe[2] as f32 * f)
+}
+
+// fewest bytes a scanline of width pixels can take, run length encoded if it can be
+// which is a 4 byte marker, then 2 bytes for each run of up to 127 in each channel
+fn hdr_scanline_size(width: usize) -> usize {
+    if (8..0x8000).contains(&width) {
+        4 + 4 * 2 * width.div_ceil(127)
+    } else {
+        width.saturating_mul(4)
+    }
+}
+
+// fills scanline and returns whatever input is left after it
+fn read_hdr_scanline<'a>(data: &'a [u8], scanline: &mut [[u8; 4]]) -> io::Result<&'a [u8]> {
+    let width = scanline.len();
+    let truncated = || invalid("truncated hdr data");
+
+    // new style run length encoding starts with 2 2 and the width, and stores each channel separately
+    // lines too short or too long for it are stored flat
+    let is_rle = (8..0x8000).contains(&width) && data.len() >= 4 && data[0] == 2 && data[1] == 2 && data[2] & 0x80 == 0;
+    if !is_rle {
+        let size = width.checked_mul(4).ok_or_else(truncated)?;
+        let flat = data.get(..size).ok_or_else(truncated)?;
+        for (px, bytes) in scanline.iter_mut().zip(flat.chunks_exact(4)) {
+            px.copy_from_slice(bytes);
+        }
+        return Ok(&data[size..]);
+    }
+
+    if ((data[2] as usize) << 8 | data[3] as usize) != width {
+        return Err(invalid("hdr scanline width mismatch"));
+    }
+
+    let mut pos = 4;
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let count = *data.get(pos).ok_or_else(truncated)? as usize;
+            pos += 1;
+
+            // counts above 128 are a run of one repeated value, otherwise that many literal values follow
+            if count > 128 {
+                let run = count - 128;
+                let value = *data.get(pos).ok_or_else(truncated)?;
+                pos += 1;
+                if x + run > width {
+                    return Err(invalid("hdr run overflows scanline"));
+                }
+                for px in &mut scanline[x..x + run] {
+                    px[channel] = value;
+                }
+                x += run;
+            } else {
+                if count == 0 || x + count > width {
+                    return Err(invalid("bad hdr literal run"));
+                }
+                let values = data.get(pos..pos + count).ok_or_else(truncated)?;
+                pos += count;
+                for (px, &value) in scanline[x..x + count].iter_mut().zip(values) {
+                    px[channel] = value;
+                }
+                x += count;
+            }
+        }
+    }
+
+    Ok(&data[pos..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a little picture whose values are all whole bytes, so 8 bit formats keep them exactly
+    fn gradient(width: usize, height: usize) -> Image {
+        let pixels = (0..width * height)
+            .map(|i| Vec3f::new3f((i * 17 % 256) as f32 / 255.0, (i * 101 % 256) as f32 / 255.0, (255 - i % 256) as f32 / 255.0))
+            .collect();
+        Image::new(width, height, pixels)
+    }
+
+    fn assert_same(expected: &Image, actual: &Image) {
+        assert_eq!((expected.width(), expected.height()), (actual.width(), actual.height()));
+        for y in 0..expected.height() {
+            for x in 0..expected.width() {
+                assert_eq!(expected.get(x, y), actual.get(x, y), "pixel {},{}", x, y);
+                assert_eq!(expected.alpha(x, y), actual.alpha(x, y), "alpha of {},{}", x, y);
+            }
+        }
+    }
+
+    // a png with the given rows of samples, each unfiltered, and any extra chunks before the image data
+    fn png(width: u32, height: u32, bit_depth: u8, color_type: u8, interlace: u8, rows: &[Vec<u8>], chunks: &[(&[u8; 4], Vec<u8>)]) -> Vec<u8> {
+        let mut header = Vec::new();
+        header.extend_from_slice(&width.to_be_bytes());
+        header.extend_from_slice(&height.to_be_bytes());
+        header.extend_from_slice(&[bit_depth, color_type, 0, 0, interlace]);
+        let raw: Vec<u8> = rows.iter().flat_map(|row| std::iter::once(0).chain(row.iter().copied())).collect();
+
+        let mut out = vec![0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+        write_png_chunk(&mut out, b"IHDR", &header);
+        for (kind, data) in chunks {
+            write_png_chunk(&mut out, kind, data);
+        }
+        write_png_chunk(&mut out, b"IDAT", &deflate::zlib_compress(&raw));
+        write_png_chunk(&mut out, b"IEND", &[]);
+        out
+    }
+
+    fn grey(value: f32) -> Vec3f {
+        Vec3f::new3f(value, value, value)
+    }
+
+    // an error about the file, rather than a panic or running out of memory
+    fn assert_invalid(result: io::Result<Image>) {
+        match result {
+            Ok(image) => panic!("decoded a {}x{} image", image.width(), image.height()),
+            Err(err) => assert_eq!(err.kind(), ErrorKind::InvalidData, "{}", err),
+        }
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let image = gradient(7, 5);
+        assert_same(&image, &Image::decode_png(&image.encode_png()).unwrap());
+    }
+
+    // straight alpha in the file, premultiplied once it's read, and the same file again when it's written back out
+    #[test]
+    fn png_alpha_round_trip() {
+        let image = gradient(7, 5);
+        let alpha: Vec<f32> = (0..35).map(|i| (i * 7 % 256) as f32 / 255.0).collect();
+        let pixels = (0..35).map(|i| image.get(i % 7, i / 7) * alpha[i]).collect();
+        let image = Image::new(7, 5, pixels).with_alpha(alpha.clone());
+
+        let encoded = image.encode_png();
+        let decoded = Image::decode_png(&encoded).unwrap();
+        assert_eq!(decoded.alpha, Some(alpha));
+        assert_eq!(decoded.encode_png(), encoded);
+    }
+
+    #[test]
+    fn png_16_bit() {
+        let rows = vec![vec![0x00, 0x00, 0x80, 0x00, 0xFF, 0xFF]];
+        let image = Image::decode_png(&png(3, 1, 16, 0, 0, &rows, &[])).unwrap();
+        assert_eq!(image.get(0, 0), &grey(0.0));
+        assert_eq!(image.get(1, 0), &grey(32768.0 / 65535.0));
+        assert_eq!(image.get(2, 0), &grey(1.0));
+    }
+
+    #[test]
+    fn png_1_bit() {
+        // ten pixels take two bytes, packed from the top bit down
+        let image = Image::decode_png(&png(10, 1, 1, 0, 0, &[vec![0b1010_0000, 0b0100_0000]], &[])).unwrap();
+        let values: Vec<f32> = (0..10).map(|x| image.get(x, 0)[0]).collect();
+        assert_eq!(values, [1.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn png_palette() {
+        let palette = vec![255, 0, 0, 0, 255, 0, 0, 0, 255];
+        // 2 bit indices 2, 0, 1
+        let image = Image::decode_png(&png(3, 1, 2, 3, 0, &[vec![0b1000_0100]], &[(b"PLTE", palette)])).unwrap();
+        assert_eq!(image.get(0, 0), &Vec3f::new3f(0.0, 0.0, 1.0));
+        assert_eq!(image.get(1, 0), &Vec3f::new3f(1.0, 0.0, 0.0));
+        assert_eq!(image.get(2, 0), &Vec3f::new3f(0.0, 1.0, 0.0));
+        assert!(image.alpha.is_none());
+    }
+
+    #[test]
+    fn png_palette_transparency() {
+        let palette = vec![255, 0, 0, 0, 255, 0, 0, 0, 255];
+        // entries past the end of the trns chunk are opaque
+        let chunks = [(b"PLTE", palette), (b"tRNS", vec![0, 51])];
+        let image = Image::decode_png(&png(3, 1, 8, 3, 0, &[vec![0, 1, 2]], &chunks)).unwrap();
+        assert_eq!((image.alpha(0, 0), image.alpha(1, 0), image.alpha(2, 0)), (0.0, 0.2, 1.0));
+        assert_eq!(image.get(1, 0), &Vec3f::new3f(0.0, 0.2, 0.0));
+    }
+
+    #[test]
+    fn png_key_transparency() {
+        let image = Image::decode_png(&png(2, 1, 8, 0, 0, &[vec![7, 9]], &[(b"tRNS", vec![0, 7])])).unwrap();
+        assert_eq!((image.alpha(0, 0), image.alpha(1, 0)), (0.0, 1.0));
+        assert_eq!(image.get(0, 0), &grey(0.0));
+
+        let key: Vec<u8> = [1u16, 2, 3].iter().flat_map(|v| v.to_be_bytes()).collect();
+        let row: Vec<u8> = [1u16, 2, 3, 1, 2, 4].iter().flat_map(|v| v.to_be_bytes()).collect();
+        let image = Image::decode_png(&png(2, 1, 16, 2, 0, &[row], &[(b"tRNS", key)])).unwrap();
+        assert_eq!((image.alpha(0, 0), image.alpha(1, 0)), (0.0, 1.0));
+    }
+
+    #[test]
+    fn png_interlaced() {
+        let (width, height) = (10, 9);
+        let value = |x: usize, y: usize| (x * 20 + y * 3) as u8;
+        let plain: Vec<Vec<u8>> = (0..height).map(|y| (0..width).map(|x| value(x, y)).collect()).collect();
+
+        // the seven adam7 passes, each a smaller image of every so many pixels
+        let mut rows = Vec::new();
+        for (x0, y0, dx, dy) in [(0, 0, 8, 8), (4, 0, 8, 8), (0, 4, 4, 8), (2, 0, 4, 4), (0, 2, 2, 4), (1, 0, 2, 2), (0, 1, 1, 2)] {
+            for y in (y0..height).step_by(dy) {
+                let row: Vec<u8> = (x0..width).step_by(dx).map(|x| value(x, y)).collect();
+                if !row.is_empty() {
+                    rows.push(row);
+                }
+            }
+        }
+
+        let interlaced = Image::decode_png(&png(width as u32, height as u32, 8, 0, 1, &rows, &[])).unwrap();
+        assert_same(&Image::decode_png(&png(width as u32, height as u32, 8, 0, 0, &plain, &[])).unwrap(), &interlaced);
+    }
+
+    #[test]
+    fn exr_round_trip() {
+        let pixels = (0..12).map(|i| Vec3f::new3f(i as f32 * 0.37, -(i as f32), 1e6 / (i + 1) as f32)).collect();
+        let image = Image::new(4, 3, pixels);
+        assert_same(&image, &Image::decode_exr(&image.encode_exr()).unwrap());
+
+        let image = image.with_alpha((0..12).map(|i| i as f32 / 11.0).collect());
+        assert_same(&image, &Image::decode_exr(&image.encode_exr()).unwrap());
+    }
+
+    #[test]
+    fn exr_bad_sizes() {
+        let bytes = gradient(4, 3).encode_exr();
+        assert_invalid(Image::decode_exr(&bytes[..bytes.len() - 10]));
+
+        // a data window as big as can be
+        let mut huge = bytes.clone();
+        let at = huge.windows(17).position(|w| w == b"dataWindow\0box2i\0").unwrap() + 21;
+        for (i, value) in [i32::MIN, i32::MIN, i32::MAX, i32::MAX].iter().enumerate() {
+            huge[at + 4 * i..at + 4 * i + 4].copy_from_slice(&value.to_le_bytes());
+        }
+        assert_invalid(Image::decode_exr(&huge));
+    }
+
+    #[test]
+    fn half_floats() {
+        assert_eq!(half_to_f32(0x3C00), 1.0);
+        assert_eq!(half_to_f32(0xC000), -2.0);
+        assert_eq!(half_to_f32(0x3555), 0.333_251_95);
+        assert_eq!(half_to_f32(0x7BFF), 65504.0);
+        assert_eq!(half_to_f32(0x0001), 2f32.powi(-24));
+        assert_eq!(half_to_f32(0x7C00), f32::INFINITY);
+        assert!(half_to_f32(0x7E00).is_nan());
+    }
+
+    #[test]
+    fn pfm_round_trip() {
+        let pixels = (0..12).map(|i| Vec3f::new3f(i as f32 * 0.37, -(i as f32), 1e6 / (i + 1) as f32)).collect();
+        let image = Image::new(4, 3, pixels);
+        assert_same(&image, &Image::decode_pfm(&image.encode_pfm()).unwrap());
+    }
+
+    // greyscale, big endian, and the bottom row first
+    #[test]
+    fn pfm_greyscale_big_endian() {
+        let mut bytes = b"Pf\n2 2\n1.0\n".to_vec();
+        for value in [1.0f32, 2.0, 3.0, 4.0] {
+            bytes.extend_from_slice(&value.to_be_bytes());
+        }
+        let image = Image::decode_pfm(&bytes).unwrap();
+        assert_eq!([image.get(0, 0), image.get(1, 0), image.get(0, 1), image.get(1, 1)], [&grey(3.0), &grey(4.0), &grey(1.0), &grey(2.0)]);
+    }
+
+    #[test]
+    fn pfm_bad_sizes() {
+        let bytes = gradient(4, 3).encode_pfm();
+        assert_invalid(Image::decode_pfm(&bytes[..bytes.len() - 1]));
+        assert_invalid(Image::decode_pfm(b"PF\n4294967296 4294967296\n-1.0\n\0\0\0\0"));
+    }
+
+    #[test]
+    fn ppm_round_trip() {
+        let image = gradient(7, 5);
+        assert_same(&image, &Image::decode_ppm(&image.encode_ppm()).unwrap());
+    }
+
+    #[test]
+    fn plain_ppm() {
+        let image = Image::decode_ppm(b"P3\n# a comment\n2 1\n# another one\n10\n0 5 10\n10 10 10\n").unwrap();
+        assert_eq!(image.get(0, 0), &Vec3f::new3f(0.0, 0.5, 1.0));
+        assert_eq!(image.get(1, 0), &grey(1.0));
+    }
+
+    #[test]
+    fn ppm_16_bit() {
+        let mut bytes = b"P6\n1 1\n65535\n".to_vec();
+        bytes.extend_from_slice(&[0x00, 0x00, 0x80, 0x00, 0xFF, 0xFF]);
+        let image = Image::decode_ppm(&bytes).unwrap();
+        assert_eq!(image.get(0, 0), &Vec3f::new3f(0.0, 32768.0 / 65535.0, 1.0));
+    }
+
+    // an rgbe pixel for each position, with an exponent that makes them mantissa / 128
+    fn rgbe(x: usize, y: usize) -> [u8; 4] {
+        [(x * 10 + y) as u8, (y * 10 + x) as u8, 200, 129]
+    }
+
+    fn hdr(header: &[&str], resolution: &str, order: &[(usize, usize)]) -> Vec<u8> {
+        let header: String = header.iter().map(|line| format!("{}\n", line)).collect();
+        let mut bytes = format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n{}\n{}\n", header, resolution).into_bytes();
+        for &(x, y) in order {
+            bytes.extend_from_slice(&rgbe(x, y));
+        }
+        bytes
+    }
+
+    #[test]
+    fn hdr_orientations() {
+        let (width, height) = (3, 2);
+        // the order pixels come in, going along rows (or columns), from the top (or bottom) and left (or right)
+        let scan = |rows: bool, from_bottom: bool, from_right: bool| -> Vec<(usize, usize)> {
+            let ys: Vec<usize> = if from_bottom { (0..height).rev().collect() } else { (0..height).collect() };
+            let xs: Vec<usize> = if from_right { (0..width).rev().collect() } else { (0..width).collect() };
+            if rows {
+                ys.iter().flat_map(|&y| xs.iter().map(move |&x| (x, y))).collect()
+            } else {
+                xs.iter().flat_map(|&x| ys.iter().map(move |&y| (x, y))).collect()
+            }
+        };
+        let orientations = [
+            ("-Y 2 +X 3", scan(true, false, false)),
+            ("+Y 2 +X 3", scan(true, true, false)),
+            ("-Y 2 -X 3", scan(true, false, true)),
+            ("+Y 2 -X 3", scan(true, true, true)),
+            ("+X 3 -Y 2", scan(false, false, false)),
+            ("+X 3 +Y 2", scan(false, true, false)),
+            ("-X 3 -Y 2", scan(false, false, true)),
+            ("-X 3 +Y 2", scan(false, true, true)),
+        ];
+
+        for (resolution, order) in orientations {
+            let image = Image::decode_hdr(&hdr(&[], resolution, &order)).unwrap();
+            assert_eq!((image.width(), image.height()), (width, height), "{}", resolution);
+            for y in 0..height {
+                for x in 0..width {
+                    assert_eq!(image.get(x, y), &rgbe_to_float(&rgbe(x, y)), "{} at {},{}", resolution, x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn hdr_exposure() {
+        let image = Image::decode_hdr(&hdr(&["EXPOSURE=2", "EXPOSURE=2"], "-Y 1 +X 1", &[(1, 1)])).unwrap();
+        assert_eq!(image.get(0, 0), &(&rgbe_to_float(&rgbe(1, 1)) * 0.25));
+    }
+
+    #[test]
+    fn ppm_bad_sizes() {
+        let bytes = gradient(4, 3).encode_ppm();
+        assert_invalid(Image::decode_ppm(&bytes[..bytes.len() - 1]));
+        assert_invalid(Image::decode_ppm(b"P6\n18446744073709551615 2\n255\n\0\0\0"));
+        assert_invalid(Image::decode_ppm(b"P3\n1000000 1000000\n255\n0 0 0\n"));
+    }
+
+    #[test]
+    fn png_bad_sizes() {
+        let bytes = png(2, 2, 8, 0, 0, &[vec![1, 2], vec![3, 4]], &[]);
+        assert_invalid(Image::decode_png(&bytes[..bytes.len() / 2]));
+        assert_invalid(Image::decode_png(&png(100000, 100000, 8, 0, 0, &[vec![1, 2]], &[])));
+        assert_invalid(Image::decode_png(&png(u32::MAX, u32::MAX, 16, 6, 0, &[vec![1, 2]], &[])));
+    }
+
+    #[test]
+    fn hdr_bad_sizes() {
+        assert_invalid(Image::decode_hdr(b"#?RADIANCE\n\n-Y 100000 +X 100000\n\0\0\0\0"));
+        assert_invalid(Image::decode_hdr(b"#?RADIANCE\n\n-Y 18446744073709551615 +X 18446744073709551615\n\0\0\0\0"));
+        assert_invalid(Image::decode_hdr(b"#?RADIANCE\n\n-Y 18446744073709551615 +X 0\n"));
+    }
+
+    #[test]
+    fn hdr_unterminated_header() {
+        assert!(Image::decode_hdr(b"#?RADIANCE\nEXPOSURE=2").is_err());
+    }
+
+    #[test]
+    fn hdr_run_length() {
+        let mut bytes = b"#?RADIANCE\n\n-Y 1 +X 8\n".to_vec();
+        bytes.extend_from_slice(&[2, 2, 0, 8]);
+        // red one run, green all literals, blue a run then literals, and the exponent one run
+        bytes.extend_from_slice(&[128 + 8, 64]);
+        bytes.extend_from_slice(&[8, 1, 2, 3, 4, 5, 6, 7, 8]);
+        bytes.extend_from_slice(&[128 + 4, 9, 4, 10, 11, 12, 13]);
+        bytes.extend_from_slice(&[128 + 8, 129]);
+
+        let image = Image::decode_hdr(&bytes).unwrap();
+        let blue = [9, 9, 9, 9, 10, 11, 12, 13];
+        for (x, &blue) in blue.iter().enumerate() {
+            assert_eq!(image.get(x, 0), &rgbe_to_float(&[64, x as u8 + 1, blue, 129]));
+        }
+    }
+}
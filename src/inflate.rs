@@ -0,0 +1,297 @@
+// deflate decompression, just enough to unpack png image data
+// see https://www.rfc-editor.org/rfc/rfc1951 for deflate and https://www.rfc-editor.org/rfc/rfc1950 for the zlib wrapper around it
+
+use std::io::{self, ErrorKind};
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg.to_string())
+}
+
+// deflate packs bits starting from the least significant one of each byte
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        BitReader { data, pos: 0, bit: 0 }
+    }
+
+    fn bits(&mut self, count: u32) -> io::Result<u32> {
+        let mut value = 0;
+        for i in 0..count {
+            let byte = *self.data.get(self.pos).ok_or_else(|| invalid("deflate stream ended early"))?;
+            value |= (((byte >> self.bit) & 1) as u32) << i;
+            self.bit += 1;
+            if self.bit == 8 {
+                self.bit = 0;
+                self.pos += 1;
+            }
+        }
+        Ok(value)
+    }
+
+    fn align_to_byte(&mut self) {
+        if self.bit != 0 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+    }
+}
+
+// canonical huffman code, stored as the number of codes of each length plus the symbols sorted by code
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut offsets = [0u16; 16];
+        for len in 1..16 {
+            offsets[len] = offsets[len - 1] + counts[len - 1];
+        }
+
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+
+        Huffman { counts, symbols }
+    }
+
+    // codes are read one bit at a time, most significant first, until they fall into the range of some length
+    fn decode(&self, reader: &mut BitReader) -> io::Result<u16> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for len in 1..16 {
+            code |= reader.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - count < first {
+                return Ok(self.symbols[(index + (code - first)) as usize]);
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+        Err(invalid("bad huffman code"))
+    }
+}
+
+// deflate.rs uses these too, going the other way
+pub static LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+pub static LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+pub static DIST_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+pub static DIST_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+// order in which the code length code lengths are stored, most commonly used first
+static CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+fn inflate_block(reader: &mut BitReader, out: &mut Vec<u8>, lengths: &Huffman, distances: &Huffman) -> io::Result<()> {
+    loop {
+        let symbol = lengths.decode(reader)? as usize;
+        match symbol {
+            0..=255 => out.push(symbol as u8),
+            256 => return Ok(()),
+            _ => {
+                let i = symbol - 257;
+                if i >= LENGTH_BASE.len() {
+                    return Err(invalid("bad deflate length symbol"));
+                }
+                let length = LENGTH_BASE[i] as usize + reader.bits(LENGTH_EXTRA[i] as u32)? as usize;
+
+                let d = distances.decode(reader)? as usize;
+                if d >= DIST_BASE.len() {
+                    return Err(invalid("bad deflate distance symbol"));
+                }
+                let distance = DIST_BASE[d] as usize + reader.bits(DIST_EXTRA[d] as u32)? as usize;
+                if distance > out.len() {
+                    return Err(invalid("deflate distance reaches before start of output"));
+                }
+
+                // copies may overlap what they're producing, e.g. distance 1 repeats the last byte length times
+                let start = out.len() - distance;
+                for k in 0..length {
+                    out.push(out[start + k]);
+                }
+            }
+        }
+    }
+}
+
+fn dynamic_tables(reader: &mut BitReader) -> io::Result<(Huffman, Huffman)> {
+    let literal_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_length_count = reader.bits(4)? as usize + 4;
+
+    let mut code_lengths = [0u8; 19];
+    for &i in CODE_LENGTH_ORDER.iter().take(code_length_count) {
+        code_lengths[i] = reader.bits(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&code_lengths);
+
+    // literal/length and distance code lengths come as one run-length encoded sequence
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let symbol = code_length_code.decode(reader)?;
+        match symbol {
+            0..=15 => lengths.push(symbol as u8),
+            16 => {
+                let previous = *lengths.last().ok_or_else(|| invalid("repeat with no previous code length"))?;
+                let repeat = 3 + reader.bits(2)?;
+                lengths.extend((0..repeat).map(|_| previous));
+            }
+            17 => {
+                let repeat = 3 + reader.bits(3)?;
+                lengths.extend((0..repeat).map(|_| 0));
+            }
+            _ => {
+                let repeat = 11 + reader.bits(7)?;
+                lengths.extend((0..repeat).map(|_| 0));
+            }
+        }
+    }
+    if lengths.len() != literal_count + distance_count {
+        return Err(invalid("code lengths overrun"));
+    }
+
+    Ok((Huffman::new(&lengths[..literal_count]), Huffman::new(&lengths[literal_count..])))
+}
+
+fn fixed_tables() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    for (i, len) in lengths.iter_mut().enumerate() {
+        *len = match i {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        };
+    }
+
+    (Huffman::new(&lengths), Huffman::new(&[5u8; 30]))
+}
+
+/// decompress a raw deflate stream
+pub fn inflate(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut reader = BitReader::new(data);
+    let mut out = Vec::new();
+
+    loop {
+        let is_last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            // stored, the length and its complement follow on a byte boundary
+            0 => {
+                reader.align_to_byte();
+                let header = data.get(reader.pos..reader.pos + 4).ok_or_else(|| invalid("truncated stored block"))?;
+                let len = u16::from_le_bytes([header[0], header[1]]) as usize;
+                let nlen = u16::from_le_bytes([header[2], header[3]]) as usize;
+                if len != !nlen & 0xFFFF {
+                    return Err(invalid("stored block length check failed"));
+                }
+                let start = reader.pos + 4;
+                out.extend_from_slice(data.get(start..start + len).ok_or_else(|| invalid("truncated stored block"))?);
+                reader.pos = start + len;
+            }
+            1 => {
+                let (lengths, distances) = fixed_tables();
+                inflate_block(&mut reader, &mut out, &lengths, &distances)?;
+            }
+            2 => {
+                let (lengths, distances) = dynamic_tables(&mut reader)?;
+                inflate_block(&mut reader, &mut out, &lengths, &distances)?;
+            }
+            _ => return Err(invalid("reserved deflate block type")),
+        }
+
+        if is_last {
+            return Ok(out);
+        }
+    }
+}
+
+/// decompress a zlib stream: two header bytes, deflate data, then an adler-32 checksum
+pub fn zlib_decompress(data: &[u8]) -> io::Result<Vec<u8>> {
+    if data.len() < 6 || data[0] & 0x0F != 8 || !((data[0] as u16) << 8 | data[1] as u16).is_multiple_of(31) {
+        return Err(invalid("not a zlib stream"));
+    }
+    if data[1] & 0x20 != 0 {
+        return Err(invalid("zlib preset dictionaries aren't supported"));
+    }
+
+    let out = inflate(&data[2..])?;
+    let expected = u32::from_be_bytes([data[data.len() - 4], data[data.len() - 3], data[data.len() - 2], data[data.len() - 1]]);
+    if adler32(&out) != expected {
+        return Err(invalid("zlib checksum mismatch"));
+    }
+
+    Ok(out)
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+
+    b << 16 | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stored_block() {
+        // final block of type 0, then the length and its complement, and the bytes as they are
+        let mut data = vec![0b001, 5, 0, !5, !0];
+        data.extend_from_slice(b"hello");
+        assert_eq!(inflate(&data).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn dynamic_block() {
+        // what python's zlib.compress(text, 9) makes of it, without the zlib header and checksum
+        let text = b"abbaadbabbabadcaabaababcbaabcaabacdbababcaacbaacaccaabbddabc";
+        let data = [0x1d, 0x89, 0x89, 0x0d, 0x00, 0x00, 0x0c, 0x01, 0x67, 0x3d, 0xec, 0x3f, 0x43, 0x69, 0x22, 0x9e, 0x83, 0x04,
+                    0x11, 0x0d, 0x11, 0x53, 0xaf, 0xe4, 0xc5, 0x2f, 0xef, 0xfc, 0x3e, 0x66, 0x3c, 0xaa, 0xa4, 0xe8, 0x00];
+        assert_eq!((data[0] >> 1) & 3, 2, "should be a dynamic block");
+        assert_eq!(inflate(&data).unwrap(), text);
+    }
+
+    #[test]
+    fn adler32_of_wikipedia() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn bad_checksum() {
+        let mut data = crate::deflate::zlib_compress(b"some data");
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        assert!(zlib_decompress(&data).is_err());
+    }
+
+    #[test]
+    fn truncated() {
+        let data = crate::deflate::deflate(b"some data that gets cut off");
+        assert!(inflate(&data[..data.len() / 2]).is_err());
+    }
+}
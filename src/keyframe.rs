@@ -0,0 +1,163 @@
+// values that change over the frames of an animation
+// in a scene file a track takes the place of any number or list of numbers, as keys separated by slashes
+// each key is frame:value, e.g. center=1:-3,0,-16/48:3,0,-16 moves from x = -3 on frame 1 to x = 3 on frame 48
+// starting the track with bezier/ makes it ease smoothly through the keys rather than going in straight lines between them (linear/, the default)
+// before the first key it holds the first value, and after the last key the last
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interpolation {
+    Linear,
+    // a cubic bezier curve between each pair of keys, with its handles set so the curve passes smoothly through every key
+    // and comes to a gentle stop at the first and last
+    Bezier,
+}
+
+#[derive(Debug)]
+pub struct Track {
+    interpolation: Interpolation,
+    // frame and value, in order of frame
+    keys: Vec<(f32, Vec<f32>)>,
+}
+
+impl Track {
+    /// None if the text isn't a track at all, just a plain value (or a name, or a path)
+    pub fn parse(text: &str) -> Option<Result<Track, String>> {
+        let (interpolation, keys) = match text.split_once('/') {
+            Some(("linear", rest)) => (Interpolation::Linear, rest),
+            Some(("bezier", rest)) => (Interpolation::Bezier, rest),
+            _ => (Interpolation::Linear, text),
+        };
+
+        // it has to at least start like one
+        let (first_frame, _) = keys.split_once(':')?;
+        first_frame.trim().parse::<f32>().ok()?;
+
+        Some(Track::parse_keys(interpolation, keys))
+    }
+
+    fn parse_keys(interpolation: Interpolation, text: &str) -> Result<Track, String> {
+        let mut keys: Vec<(f32, Vec<f32>)> = Vec::new();
+        for key in text.split('/') {
+            let (frame, value) = key.split_once(':').ok_or_else(|| format!("keyframe {} should be frame:value", key))?;
+            let frame = frame.trim().parse::<f32>().map_err(|_| format!("keyframe frame should be a number, got {}", frame))?;
+            let value = value.split(',')
+                .map(|v| v.trim().parse::<f32>().map_err(|_| format!("keyframe value should be a number, got {}", v)))
+                .collect::<Result<Vec<f32>, String>>()?;
+
+            if let Some((last_frame, last_value)) = keys.last() {
+                if frame <= *last_frame {
+                    return Err(format!("keyframes should go in order of frame, but {} comes after {}", frame, last_frame));
+                }
+                if value.len() != last_value.len() {
+                    return Err(format!("keyframes should all have as many numbers, but {} has {} and {} has {}", last_frame, last_value.len(), frame, value.len()));
+                }
+            }
+            keys.push((frame, value));
+        }
+
+        Ok(Track { interpolation, keys })
+    }
+
+    /// value at the given frame
+    pub fn at(&self, frame: f32) -> Vec<f32> {
+        let keys = &self.keys;
+        let last = keys.len() - 1;
+        if frame <= keys[0].0 {
+            return keys[0].1.clone();
+        }
+        if frame >= keys[last].0 {
+            return keys[last].1.clone();
+        }
+
+        // the key at or before frame, and the one after it
+        let i = keys.iter().rposition(|(f, _)| *f <= frame).unwrap();
+        let (t0, p0) = &keys[i];
+        let (t1, p1) = &keys[i + 1];
+        let span = t1 - t0;
+        let s = (frame - t0) / span;
+
+        match self.interpolation {
+            Interpolation::Linear => p0.iter().zip(p1).map(|(a, b)| a + (b - a) * s).collect(),
+            Interpolation::Bezier => {
+                // handles a third of the way along the tangent at each end, which makes the bezier the same curve as a cubic hermite spline
+                let (m0, m1) = (self.slope(i), self.slope(i + 1));
+                (0..p0.len()).map(|c| {
+                    let c0 = p0[c] + m0[c] * span / 3.0;
+                    let c1 = p1[c] - m1[c] * span / 3.0;
+                    let r = 1.0 - s;
+                    r * r * r * p0[c] + 3.0 * r * r * s * c0 + 3.0 * r * s * s * c1 + s * s * s * p1[c]
+                }).collect()
+            }
+        }
+    }
+
+    // rate of change per frame through key i, from its neighbours on either side
+    // flat at the first and last keys, so the animation eases in and out
+    fn slope(&self, i: usize) -> Vec<f32> {
+        let keys = &self.keys;
+        if i == 0 || i == keys.len() - 1 {
+            return vec![0.0; keys[i].1.len()];
+        }
+
+        let ((before, p0), (after, p1)) = (&keys[i - 1], &keys[i + 1]);
+        p0.iter().zip(p1).map(|(a, b)| (b - a) / (after - before)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(text: &str) -> Track {
+        Track::parse(text).unwrap().unwrap()
+    }
+
+    fn assert_close(actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-5, "{:?} should be {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn linear() {
+        let track = track("1:0,10/11:10,0/21:10,10");
+        assert_close(&track.at(6.0), &[5.0, 5.0]);
+        assert_close(&track.at(11.0), &[10.0, 0.0]);
+        assert_close(&track.at(13.5), &[10.0, 2.5]);
+        // held before the first key and after the last
+        assert_close(&track.at(-5.0), &[0.0, 10.0]);
+        assert_close(&track.at(100.0), &[10.0, 10.0]);
+    }
+
+    #[test]
+    fn bezier() {
+        // with flat ends it's a smoothstep between two keys, 3s^2 - 2s^3
+        let two = track("bezier/0:0/4:10");
+        assert_close(&two.at(1.0), &[1.5625]);
+        assert_close(&two.at(2.0), &[5.0]);
+        assert_close(&two.at(3.0), &[8.4375]);
+
+        // passes through every key, and smoothly, the slope through the middle one being that between its neighbours
+        let three = track("bezier/0:0/10:10/30:0");
+        assert_close(&three.at(10.0), &[10.0]);
+        let step = 0.01;
+        let before = (three.at(10.0)[0] - three.at(10.0 - step)[0]) / step;
+        let after = (three.at(10.0 + step)[0] - three.at(10.0)[0]) / step;
+        assert!((before - after).abs() < 0.01 && before.abs() < 0.01, "slopes {} and {}", before, after);
+    }
+
+    #[test]
+    fn not_tracks() {
+        assert!(Track::parse("1.5").is_none());
+        assert!(Track::parse("red").is_none());
+        assert!(Track::parse("textures/wood.png").is_none());
+    }
+
+    #[test]
+    fn bad_tracks() {
+        assert!(Track::parse("10:0/5:1").unwrap().is_err());
+        assert!(Track::parse("1:0,0/5:1").unwrap().is_err());
+        assert!(Track::parse("1:0/5:x").unwrap().is_err());
+    }
+}
@@ -1,22 +1,58 @@
+mod aov;
+mod background;
+mod camera;
+mod compare;
+mod csg;
+mod deflate;
+mod denoise;
 mod geometry;
+mod image;
+mod inflate;
+mod keyframe;
 mod material;
+mod medium;
+mod mesh;
 mod object;
 mod light;
+mod noise;
+mod postprocess;
+mod progressive;
+mod sampling;
+mod scene;
+mod scene_file;
+mod sdf;
+mod sky;
+mod texture;
 
-use std::io::{BufWriter, Write};
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use crate::aov::{Aov, AovSample};
+use crate::background::{Background, EnvironmentMap};
+use crate::denoise::Denoiser;
 use crate::geometry::{Vec2f, Vec3f, Vec4f};
+use crate::image::Image;
 use crate::light::Light;
 use crate::material::Material;
-use crate::object::{Object, Sphere};
+use crate::object::{Object, Sphere, SurfaceHit};
+use crate::postprocess::Frame;
+use crate::progressive::{Accumulator, Settings};
+use crate::sampling::{Rng, Sampler};
+use crate::scene::Scene;
+use crate::sky::{Sky, SkyModel};
+use crate::texture::Texture;
 
 static BG_COLOR: [f32; 3] = [0.2f32, 0.7f32, 0.8f32];
-static GREEN: [f32; 3] = [0f32, 1f32, 0f32];
 static WIDTH: u32 = 1024;
 static HEIGHT: u32 = 768;
-// we could compute this from the viewport size and its distance to the camera
-// but this way we don't have to
-static FOV: f32 = std::f32::consts::PI / 3.0;
 static MAX_BOUNCES: u32 = 4;
+// how many points to pick on each emissive object (and how many directions to try from the material) per shading point
+static EMITTER_SAMPLES: u32 = 8;
+// how many points along each stretch of fog or smoke to gather light at
+static VOLUME_SAMPLES: u32 = 16;
+// passes every pixel gets with adaptive sampling, before its noise is worth judging
+static MIN_ADAPTIVE_PASSES: u32 = 4;
 
 #[derive(Debug)]
 pub struct RayIntersectInfo {
@@ -28,147 +64,406 @@ pub struct RayIntersectInfo {
     // this is that normal vector for a given point
     // direction from object origin to the point of ray intersection, normalized because all "direction" vectors are normalized for convenience
     first_intersect_normal: Vec3f,
+    // the actual surface normal, which first_intersect_normal can differ from if the material has a normal or bump map
+    // rays leaving the surface are offset along this one, so they don't start off inside the object
+    geometric_normal: Vec3f,
+    // index into the object list of whatever was hit, None for misses and the checkerboard
+    object_id: Option<usize>,
 }
 
 impl RayIntersectInfo {
-    pub fn from(tup: (bool, Material, Vec3f, Vec3f)) -> Self {
+    pub fn from(tup: (bool, Material, Vec3f, Vec3f, Vec3f, Option<usize>)) -> Self {
         RayIntersectInfo {
             intersects_with_scene: tup.0,
             closest_material: tup.1,
             first_intersect_point: tup.2,
             first_intersect_normal: tup.3,
+            geometric_normal: tup.4,
+            object_id: tup.5,
         }
     }
 }
 
 // initially, get properties of the first intersection the ray has with any object in the scene
-fn scene_intersect<T>(orig: &Vec3f, dir: &Vec3f, objs: &[Box<T>]) -> RayIntersectInfo
-    where T: Object {
-    let mut distance = f32::MAX;
-    let closest_material: Material = Material::new(&Vec3f::new(3), &Vec4f::from(&[1.0, 0.0, 0.0, 0.0]), 0.0, 1.0);
-    let first_intersect_point = Vec3f::new(3);
-    let first_intersect_dir = Vec3f::new(3);
-
-    let mut obj_intersect = RayIntersectInfo::from(objs.iter().fold((false, closest_material, first_intersect_point, first_intersect_dir), |prev, obj| {
-        let (intersects, dist_i) = obj.ray_intersect(orig, dir);
-        // objects closer to the camera will block further away ones
-        return if intersects && dist_i < distance {
-            distance = dist_i;
-            let pt = orig + &(dir * dist_i);
-            (true,
-             obj.get_material().clone(),
-             pt.clone(),
-             (&pt - obj.get_position()).normalize())
-        } else {
-            prev
-        };
-    }));
+fn scene_intersect<T>(orig: &Vec3f, dir: &Vec3f, time: f32, scene: &Scene<T>) -> RayIntersectInfo
+    where T: Object + ?Sized {
+    let objs = scene.objects();
+
+    // only the nearest hit is kept, its material is looked up once it's known to be the one that's seen
+    let nearest = objs.iter().enumerate().fold(None, |prev: Option<(SurfaceHit, usize)>, (id, obj)| {
+        match obj.ray_intersect(orig, dir, time) {
+            // objects closer to the camera will block further away ones
+            Some(hit) if hit.distance < prev.as_ref().map_or(f32::MAX, |(closest, _)| closest.distance) => Some((hit, id)),
+            _ => prev,
+        }
+    });
+    let distance = nearest.as_ref().map_or(f32::MAX, |(hit, _)| hit.distance);
 
     // TODO checkerboard as a plane instead of this
-    let mut checker_board_distance = f32::MAX;
+    let mut checker_board = None;
 
-    if dir[1].abs() > 0.0001 {
+    if let Some(floor) = scene.floor().filter(|_| dir[1].abs() > 0.0001) {
         let d = -(orig[1] + 4.0) / dir[1]; // the checkerboard plane has equation y = -4
-        let pt = orig + &(dir * d);
+        let mut pt = orig + &(dir * d);
         if d > 0.0 && pt[0].abs() < 10.0 && pt[2] < -10.0 && pt[2] > -30.0 && d < distance {
-            checker_board_distance = d;
-            obj_intersect.first_intersect_point = pt.clone();
-            obj_intersect.first_intersect_normal = Vec3f::new3f(0.0, 1.0, 0.0);
-            obj_intersect.closest_material = if ((0.5 * pt[0] + 1000.0) as i32 + ((0.5 * pt[2]) as i32)) & 1 == 0 {
-                Material::new(&(&Vec3f::new3f(1.0, 1.0, 1.0) * 0.3),
-                              obj_intersect.closest_material.albedo(),
-                              obj_intersect.closest_material.specular_exponent(),
-                              obj_intersect.closest_material.refractive_index())
-            } else {
-                Material::new(&(&Vec3f::new3f(1.0, 0.7, 0.3) * 0.3),
-                              obj_intersect.closest_material.albedo(),
-                              obj_intersect.closest_material.specular_exponent(),
-                              obj_intersect.closest_material.refractive_index())
-            };
+            // snap to the plane exactly, so solid textures with a cell border at y = -4 don't flicker between cells
+            pt[1] = -4.0;
+            let hit = SurfaceHit { distance: d, normal: Vec3f::new3f(0.0, 1.0, 0.0), uv: Vec2f::from(&[pt[0], -pt[2]]), tangent: Vec3f::new3f(1.0, 0.0, 0.0) };
+            checker_board = Some((floor, hit, pt));
         }
     }
 
-    obj_intersect.intersects_with_scene = obj_intersect.intersects_with_scene || checker_board_distance < f32::MAX;
+    let (material, hit, pt, object_id) = match (checker_board, nearest) {
+        (Some((floor, hit, pt)), _) => (floor, hit, pt, None),
+        (None, Some((hit, id))) => {
+            let pt = orig + &(dir * hit.distance);
+            (objs[id].get_material(), hit, pt, Some(id))
+        }
+        (None, None) => {
+            let nothing = Material::new(&Vec3f::new(3), &Vec4f::from(&[1.0, 0.0, 0.0, 0.0]), 0.0, 1.0);
+            return RayIntersectInfo::from((false, nothing, Vec3f::new(3), Vec3f::new(3), Vec3f::new(3), None));
+        }
+    };
 
-    obj_intersect
+    // look up any textures now, so shading only ever deals with plain materials
+    RayIntersectInfo::from((true, material.resolve(&hit.uv, &pt), pt.clone(), material.shading_normal(&hit, &pt), hit.normal, object_id))
 }
 
 // cast a ray into the scene from point orig, get back the color of that point on the canvas
-// we need lights to determine how bright the point of intersection with the scene is, and thus to know how bright the pixel should be
-// we need objs to know where our objects are in space and what their surface properties (color, roughness, etc) are
+// we need the scene's lights to determine how bright the point of intersection with the scene is, and thus to know how bright the pixel should be
+// we need its objects to know where they are in space and what their surface properties (color, roughness, etc) are
+// and its background for whatever the ray sees when it misses everything
 // depth is used for reflection
 // we get the color contributed by the reflection of the object surface by recursively casting a ray from that point
 // this ray may strike another object, and that other object may in turn have its own reflection, contributing the object's color and sending off another ray
 // this continues until MAX_REFLECT_BOUNCES is reached
 // i mean, realistically nobody will ever notice reflections more than 2 layers deep, but whatever
-fn cast_ray<T>(orig: &Vec3f, dir: &Vec3f, lights: &[Light], objs: &[Box<T>], depth: u32) -> Vec3f
-    where T: Object {
-    let intersect_info = scene_intersect(orig, dir, objs);
+// see_through is for a transparent background: it's set for rays it could be seen through along, from the camera and refracted on the way
+// those leave the background out, and give back how much of it would have shown through per channel as well, which is otherwise always zero
+fn cast_ray<T>(orig: &Vec3f, dir: &Vec3f, time: f32, scene: &Scene<T>, depth: u32, see_through: bool, rng: &mut Rng) -> (Vec3f, Vec3f)
+    where T: Object + ?Sized {
+    let intersect_info = scene_intersect(orig, dir, time, scene);
 
-    if !intersect_info.intersects_with_scene || depth > MAX_BOUNCES {
-        Vec3f::from(&BG_COLOR)
+    let (surface_color, background) = if !intersect_info.intersects_with_scene || depth > MAX_BOUNCES {
+        if see_through { (Vec3f::zero(), Vec3f::new3f(1.0, 1.0, 1.0)) } else { (scene.background().radiance(dir), Vec3f::zero()) }
     } else {
-        // reflections
-        // hoo man
-        // reflect dir is the angle of incidence reflected about the intersect normal
-        // i learned that in high school physics, so there
-        // the original code normalizes this vector, but dir and first_intersect_dir are already normalized, so the reflection must be as well
-        // see also https://github.com/ssloy/tinyraytracer/commit/c80479d1d22fe98f41b584972affeb43422a23a6#r32081856
-        // origin is exactly the intersection point, moved a tiny bit along the normal
-        // he says it's so that the reflection point doesn't lie exactly on the object surface, but i'm not sure
-        let reflect_dir = geometry::reflect(dir, &intersect_info.first_intersect_normal);
-        let reflect_origin = shift_point_along_normal(&reflect_dir, &intersect_info.first_intersect_normal, &intersect_info.first_intersect_point);
-        let reflect_color = &cast_ray(&reflect_origin, &reflect_dir, lights, objs, depth + 1) * intersect_info.closest_material.albedo()[2];
-
-        // save some computation on materials that don't refract
-        let refract_color = if intersect_info.closest_material.refractive_index() != 1.0 {
-            let refract_dir = geometry::refract(dir, &intersect_info.first_intersect_normal, intersect_info.closest_material.refractive_index());
-            let refract_origin = shift_point_along_normal(&refract_dir, &intersect_info.first_intersect_normal, &intersect_info.first_intersect_point);
-            &cast_ray(&refract_origin, &refract_dir, lights, objs, depth + 1) * intersect_info.closest_material.albedo()[3]
+        let shading = shade(dir, time, &intersect_info, scene, depth, see_through, rng);
+        (shading.total(), shading.background)
+    };
+
+    // whatever fog or smoke the ray went through on its way dims what's behind it, and adds light of its own
+    if scene.volumes().is_empty() {
+        return (surface_color, background);
+    }
+    let (scattered_color, transmittance) = march_media(orig, dir, time, hit_distance(orig, &intersect_info), scene, rng);
+
+    (&(&surface_color * &transmittance) + &scattered_color, &background * &transmittance)
+}
+
+// cast_ray for a ray leaving the camera, also noting down what it hit and how that got its color for the aovs
+// it has to use up random numbers exactly like cast_ray does, so asking for aovs doesn't change the picture
+fn cast_camera_ray<T>(orig: &Vec3f, dir: &Vec3f, time: f32, scene: &Scene<T>, see_through: bool, rng: &mut Rng) -> (Vec3f, Vec3f, AovSample)
+    where T: Object + ?Sized {
+    let intersect_info = scene_intersect(orig, dir, time, scene);
+
+    let (surface_color, background, mut sample) = if !intersect_info.intersects_with_scene {
+        if see_through {
+            (Vec3f::zero(), Vec3f::new3f(1.0, 1.0, 1.0), AovSample::miss())
         } else {
-            Vec3f::zero()
+            (scene.background().radiance(dir), Vec3f::zero(), AovSample::miss())
+        }
+    } else {
+        let shading = shade(dir, time, &intersect_info, scene, 1, see_through, rng);
+        let material = &intersect_info.closest_material;
+        let sample = AovSample {
+            depth: hit_distance(orig, &intersect_info),
+            normal: intersect_info.first_intersect_normal.clone(),
+            albedo: material.color().clone(),
+            // the floor isn't one of the objects, so it goes after all of them
+            object_id: intersect_info.object_id.unwrap_or(scene.objects().len()) as u32 + 1,
+            material_id: material.id(),
+            diffuse: shading.diffuse.clone(),
+            specular: shading.specular.clone(),
+            reflection: shading.reflection.clone(),
+            refraction: shading.refraction.clone(),
         };
+        (shading.total(), shading.background, sample)
+    };
 
-        let (diffuse_light_intensity, specular_light_intensity) = lights.iter().fold((0.0, 0.0), |val, light| {
-            let light_vec = light.get_position() - &intersect_info.first_intersect_point;
-            // direction of light onto intersection point (position of light source - point of intersect)
-            // angle of incidence, i guess you could call it
-            let light_dir = light_vec.normalize();
+    if scene.volumes().is_empty() {
+        return (surface_color, background, sample);
+    }
+    let (scattered_color, transmittance) = march_media(orig, dir, time, hit_distance(orig, &intersect_info), scene, rng);
+    for part in [&mut sample.diffuse, &mut sample.specular, &mut sample.reflection, &mut sample.refraction] {
+        *part = &*part * &transmittance;
+    }
+
+    (&(&surface_color * &transmittance) + &scattered_color, &background * &transmittance, sample)
+}
+
+// how far along the ray its first hit is, infinitely far if it didn't hit anything
+fn hit_distance(orig: &Vec3f, intersect_info: &RayIntersectInfo) -> f32 {
+    if intersect_info.intersects_with_scene { (&intersect_info.first_intersect_point - orig).magnitude() } else { f32::INFINITY }
+}
+
+// the light a surface sends back along a ray, kept apart by how it got there
+struct Shading {
+    diffuse: Vec3f,
+    specular: Vec3f,
+    reflection: Vec3f,
+    refraction: Vec3f,
+    // from emissive objects and the background, then the surface's own glow
+    emitters: Vec3f,
+    emission: Vec3f,
+    // how much of a transparent background shows through the surface, see cast_ray
+    background: Vec3f,
+}
+
+impl Shading {
+    fn total(&self) -> Vec3f {
+        &(&(&(&(&self.diffuse + &self.specular) + &self.reflection) + &self.refraction) + &self.emitters) + &self.emission
+    }
+}
+
+// the color of the surface the ray hit, as seen from the direction the ray came from
+fn shade<T>(dir: &Vec3f, time: f32, intersect_info: &RayIntersectInfo, scene: &Scene<T>, depth: u32, see_through: bool, rng: &mut Rng) -> Shading
+    where T: Object + ?Sized {
+    // reflections
+    // hoo man
+    // reflect dir is the angle of incidence reflected about the intersect normal
+    // i learned that in high school physics, so there
+    // the original code normalizes this vector, but dir and first_intersect_dir are already normalized, so the reflection must be as well
+    // see also https://github.com/ssloy/tinyraytracer/commit/c80479d1d22fe98f41b584972affeb43422a23a6#r32081856
+    // origin is exactly the intersection point, moved a tiny bit along the normal
+    // he says it's so that the reflection point doesn't lie exactly on the object surface, but i'm not sure
+    let reflect_dir = geometry::reflect(dir, &intersect_info.first_intersect_normal);
+    let reflect_origin = shift_point_along_normal(&reflect_dir, &intersect_info.geometric_normal, &intersect_info.first_intersect_point);
+    // the background seen in a reflection is part of the surface's color, transparent or not
+    let reflect_color = &cast_ray(&reflect_origin, &reflect_dir, time, scene, depth + 1, false, rng).0 * intersect_info.closest_material.albedo()[2];
+
+    // save some computation on materials that don't refract
+    let (refract_color, background) = if intersect_info.closest_material.refractive_index() != 1.0 {
+        let refract_dir = geometry::refract(dir, &intersect_info.first_intersect_normal, intersect_info.closest_material.refractive_index());
+        let refract_origin = shift_point_along_normal(&refract_dir, &intersect_info.geometric_normal, &intersect_info.first_intersect_point);
+        let (color, background) = cast_ray(&refract_origin, &refract_dir, time, scene, depth + 1, see_through, rng);
+        (&color * intersect_info.closest_material.albedo()[3], &background * intersect_info.closest_material.albedo()[3])
+    } else {
+        (Vec3f::zero(), Vec3f::zero())
+    };
+
+    let (diffuse_light_intensity, specular_light_intensity) = scene.lights().iter().fold((Vec3f::zero(), Vec3f::zero()), |val, light| {
+        let light_vec = light.get_position() - &intersect_info.first_intersect_point;
+        // direction of light onto intersection point (position of light source - point of intersect)
+        // angle of incidence, i guess you could call it
+        let light_dir = light_vec.normalize();
+        let distance_to_light = light_vec.magnitude();
+
+        // cast a "shadow ray" from the intersection point towards the light source
+        // if the ray hits an object in the scene before reaching the light source, the light source doesn't illuminate this point (the point is in the shadow of that object)
+        let shadow_origin = shift_point_along_normal(&light_dir, &intersect_info.geometric_normal, &intersect_info.first_intersect_point);
+
+        // TODO shouldn't this be -light_dir, since we're going the opposite way?
+        let shadow_intersect_info = scene_intersect(&shadow_origin, &light_dir, time, scene);
+
+        // point lies in shadow of some object with regard to this light, don't contribute any color from the light
+        if shadow_intersect_info.intersects_with_scene && (&shadow_intersect_info.first_intersect_point - &reflect_origin).magnitude() < distance_to_light {
+            return val;
+        }
+
+        // fog or smoke between here and the light dims it, by a different amount per color channel
+        let light_intensity = &medium::transmittance(scene.volumes(), &shadow_origin, &light_dir, time, distance_to_light) * light.get_intensity();
+
+        // add contribution of this light source to this intersection point's diffuse intensity
+        // light intensity is affected by how "head on" the surface is to the light source
+        // e.g. if the normal of a plane is exactly parallel to the light ray, the plane will get the full force of that light and thus be brighter
+        // if the normal of a plane is perpendicular to (or facing away from) the light ray, the plane isn't being illuminated at all, so the dot product is 0 (or negative, but negative brightness is out of scope)
+        // and that gets multiplied by the light intensity
+        // we know the "normal" of our "plane" here because it's the first_intersect_dir
+        (&val.0 + &(&light_intensity * geometry::max(0.0, light_dir.dot(&intersect_info.first_intersect_normal))),
+         // i know this looks insane, but i have done the math, and it does work out. check my notes
+         &val.1 + &(&light_intensity * f32::powf(geometry::max(0.0, geometry::reflect(&light_dir, &intersect_info.first_intersect_normal).dot(dir)),
+                                                 intersect_info.closest_material.specular_exponent())))
+    });
+
+    // we need this monstrosity of refs because of how we implemented multiplication on vecs as taking refs
+    // in hindsight, whoops.
+    let diffuse_color = &(intersect_info.closest_material.color() * &diffuse_light_intensity)
+        * intersect_info.closest_material.albedo()[0];
+    let specular_color = &specular_light_intensity * intersect_info.closest_material.albedo()[1];
+
+    // area lights and the environment get integrated properly instead, and emissive surfaces add their own glow on top of whatever they reflect
+    let emitter_color = sample_emitters(dir, time, intersect_info, scene, rng);
+
+    Shading {
+        diffuse: diffuse_color,
+        specular: specular_color,
+        reflection: reflect_color,
+        refraction: refract_color,
+        emitters: emitter_color,
+        emission: intersect_info.closest_material.emission().clone(),
+        background,
+    }
+}
+
+// light scattered towards the camera by the media along the ray, and the fraction of light from behind them that makes it through
+// only single scattering of the point lights is taken into account: light that has already bounced off a particle doesn't bounce again
+// the shadow rays towards the lights are what make light shafts, since parts of the medium in an object's shadow stay dark
+fn march_media<T>(orig: &Vec3f, dir: &Vec3f, time: f32, distance: f32, scene: &Scene<T>, rng: &mut Rng) -> (Vec3f, Vec3f)
+    where T: Object + ?Sized {
+    let mut scattered_color = Vec3f::zero();
+    let mut transmittance = Vec3f::new3f(1.0, 1.0, 1.0);
+
+    for segment in medium::segments(scene.volumes(), orig, dir, time, distance) {
+        let extinction = segment.medium.extinction();
+        let length = segment.end - segment.start;
+
+        if segment.medium.scatters() {
+            // evenly spaced points, all shifted by the same random amount so nothing lines up in bands across the image
+            let step = length / VOLUME_SAMPLES as f32;
+            let jitter = rng.next_f32();
+
+            for k in 0..VOLUME_SAMPLES {
+                let offset = (k as f32 + jitter) * step;
+                let point = orig + &(dir * (segment.start + offset));
+
+                let in_scattered = scene.lights().iter().fold(Vec3f::zero(), |total, light| {
+                    let light_vec = light.get_position() - &point;
+                    let light_dir = light_vec.normalize();
+                    let distance_to_light = light_vec.magnitude();
+
+                    let shadow_info = scene_intersect(&point, &light_dir, time, scene);
+                    if shadow_info.intersects_with_scene && (&shadow_info.first_intersect_point - &point).magnitude() < distance_to_light {
+                        return total;
+                    }
+
+                    let light_transmittance = medium::transmittance(scene.volumes(), &point, &light_dir, time, distance_to_light);
+                    &total + &(&light_transmittance * (light.get_intensity() * segment.medium.phase(light_dir.dot(dir))))
+                });
+
+                let reaching_camera = &(&transmittance * &medium::attenuation(&extinction, offset)) * segment.medium.scattering();
+                scattered_color = &scattered_color + &(&(&reaching_camera * &in_scattered) * step);
+            }
+        }
+
+        transmittance = &transmittance * &medium::attenuation(&extinction, length);
+    }
+
+    (scattered_color, transmittance)
+}
+
+// direct light arriving at the intersection from every object with an emissive material, and from the background if it lights the scene
+// there are two ways to find it: pick a point on the emitter (or a direction on the background) and check it's visible,
+// or pick a direction from the material and see if it hits an emitter (or nothing at all)
+// picking points works well for small lights and rough surfaces, picking directions works well for big lights and shiny surfaces
+// multiple importance sampling (veach 1997, chapter 9) weighs both estimates so that each covers for the other's weak spots
+fn sample_emitters<T>(dir: &Vec3f, time: f32, intersect_info: &RayIntersectInfo, scene: &Scene<T>, rng: &mut Rng) -> Vec3f
+    where T: Object + ?Sized {
+    let objs = scene.objects();
+    let background = scene.background();
+    let material = &intersect_info.closest_material;
+    let normal = &intersect_info.first_intersect_normal;
+    let geometric_normal = &intersect_info.geometric_normal;
+    let point = &intersect_info.first_intersect_point;
+
+    if !material.scatters() || !(background.illuminates() || objs.iter().any(|obj| obj.get_material().is_emissive())) {
+        return Vec3f::zero();
+    }
+
+    // solid angle density of hitting `hit` on emitter obj from the shading point, when sampling the emitter by area
+    let emitter_pdf = |obj: &T, hit: &Vec3f, hit_normal: &Vec3f| {
+        let to_hit = hit - point;
+        let cos_emitter = -hit_normal.dot(&to_hit.normalize());
+
+        if cos_emitter <= 0.0 { 0.0 } else { to_hit.dot(&to_hit) / (cos_emitter * obj.area()) }
+    };
+
+    let mut total = Vec3f::zero();
+
+    for (id, obj) in objs.iter().enumerate() {
+        if !obj.get_material().is_emissive() || intersect_info.object_id == Some(id) {
+            continue;
+        }
+
+        for _ in 0..EMITTER_SAMPLES {
+            // some objects can fail to come up with a point, which leaves this sample with nothing to add
+            let (light_point, light_normal) = match obj.sample_surface(rng.next_f32(), rng.next_f32(), time) {
+                Some(sample) => sample,
+                None => continue,
+            };
+            let light_vec = &light_point - point;
             let distance_to_light = light_vec.magnitude();
+            let light_dir = light_vec.normalize();
+            let light_pdf = emitter_pdf(obj, &light_point, &light_normal);
+            let f = material.eval(normal, &light_dir, dir);
 
-            // cast a "shadow ray" from the intersection point towards the light source
-            // if the ray hits an object in the scene before reaching the light source, the light source doesn't illuminate this point (the point is in the shadow of that object)
-            let shadow_origin = shift_point_along_normal(&light_dir, &intersect_info.first_intersect_normal, &intersect_info.first_intersect_point);
+            if light_pdf > 0.0 && f.dot(&f) > 0.0 {
+                let shadow_origin = shift_point_along_normal(&light_dir, geometric_normal, point);
+                let shadow_info = scene_intersect(&shadow_origin, &light_dir, time, scene);
+                let blocked = shadow_info.intersects_with_scene
+                    && (&shadow_info.first_intersect_point - &shadow_origin).magnitude() < distance_to_light * 0.999;
 
-            // TODO shouldn't this be -light_dir, since we're going the opposite way?
-            let shadow_intersect_info = scene_intersect(&shadow_origin, &light_dir, objs);
+                if !blocked {
+                    let weight = sampling::power_heuristic(light_pdf, material.pdf(normal, &light_dir, dir));
+                    let transmittance = medium::transmittance(scene.volumes(), &shadow_origin, &light_dir, time, distance_to_light);
+                    let contribution = &(&(obj.get_material().emission() * &f) * &transmittance) * (normal.dot(&light_dir) * weight / light_pdf);
+                    total = &total + &contribution;
+                }
+            }
+        }
+    }
 
-            // point lies in shadow of some object with regard to this light, don't contribute any color from the light
-            if shadow_intersect_info.intersects_with_scene && (&shadow_intersect_info.first_intersect_point - &reflect_origin).magnitude() < distance_to_light {
-                return (val.0, val.1);
+    if background.illuminates() {
+        for _ in 0..EMITTER_SAMPLES {
+            let (light_dir, light_pdf) = background.sample(rng.next_f32(), rng.next_f32());
+            let f = material.eval(normal, &light_dir, dir);
+
+            if light_pdf > 0.0 && f.dot(&f) > 0.0 {
+                // the background is infinitely far away, so anything at all in the way blocks it
+                let shadow_origin = shift_point_along_normal(&light_dir, geometric_normal, point);
+                if !scene_intersect(&shadow_origin, &light_dir, time, scene).intersects_with_scene {
+                    let weight = sampling::power_heuristic(light_pdf, material.pdf(normal, &light_dir, dir));
+                    let transmittance = medium::transmittance(scene.volumes(), &shadow_origin, &light_dir, time, f32::INFINITY);
+                    let contribution = &(&(&background.radiance(&light_dir) * &f) * &transmittance) * (normal.dot(&light_dir) * weight / light_pdf);
+                    total = &total + &contribution;
+                }
             }
+        }
+    }
 
-            // add contribution of this light source to this intersection point's diffuse intensity
-            // light intensity is affected by how "head on" the surface is to the light source
-            // e.g. if the normal of a plane is exactly parallel to the light ray, the plane will get the full force of that light and thus be brighter
-            // if the normal of a plane is perpendicular to (or facing away from) the light ray, the plane isn't being illuminated at all, so the dot product is 0 (or negative, but negative brightness is out of scope)
-            // and that gets multiplied by the light intensity
-            // we know the "normal" of our "plane" here because it's the first_intersect_dir
-            (val.0 + light.get_intensity() * geometry::max(0.0, light_dir.dot(&intersect_info.first_intersect_normal)),
-             // i know this looks insane, but i have done the math, and it does work out. check my notes
-             val.1 + f32::powf(geometry::max(0.0, geometry::reflect(&light_dir, &intersect_info.first_intersect_normal).dot(dir)),
-                               intersect_info.closest_material.specular_exponent()) * light.get_intensity())
-        });
+    for _ in 0..EMITTER_SAMPLES {
+        let sample_dir = material.sample(normal, dir, rng.next_f32(), rng.next_f32());
+        let f = material.eval(normal, &sample_dir, dir);
+
+        if f.dot(&f) == 0.0 {
+            continue;
+        }
 
-        // we need this monstrosity of refs because of how we implemented multiplication on vecs as taking refs
-        // in hindsight, whoops.
-        let diffuse_color = &(intersect_info.closest_material.color() * diffuse_light_intensity)
-            * intersect_info.closest_material.albedo()[0];
-        let specular_color = &(&Vec3f::new3f(1.0, 1.0, 1.0) * specular_light_intensity)
-            * intersect_info.closest_material.albedo()[1];
+        let sample_origin = shift_point_along_normal(&sample_dir, geometric_normal, point);
+        let sample_info = scene_intersect(&sample_origin, &sample_dir, time, scene);
+        let material_pdf = material.pdf(normal, &sample_dir, dir);
 
-        &(&(&diffuse_color + &specular_color) + &reflect_color) + &refract_color
+        if !sample_info.intersects_with_scene {
+            if background.illuminates() {
+                let weight = sampling::power_heuristic(material_pdf, background.pdf(&sample_dir));
+                let transmittance = medium::transmittance(scene.volumes(), &sample_origin, &sample_dir, time, f32::INFINITY);
+                let contribution = &(&(&background.radiance(&sample_dir) * &f) * &transmittance) * (normal.dot(&sample_dir) * weight / material_pdf);
+                total = &total + &contribution;
+            }
+            continue;
+        }
+
+        let obj = match sample_info.object_id {
+            Some(id) if Some(id) != intersect_info.object_id && objs[id].get_material().is_emissive() => &objs[id],
+            _ => continue,
+        };
+
+        let weight = sampling::power_heuristic(material_pdf, emitter_pdf(obj, &sample_info.first_intersect_point, &sample_info.geometric_normal));
+        let transmittance = medium::transmittance(scene.volumes(), &sample_origin, &sample_dir, time, (&sample_info.first_intersect_point - &sample_origin).magnitude());
+        let contribution = &(&(obj.get_material().emission() * &f) * &transmittance) * (normal.dot(&sample_dir) * weight / material_pdf);
+        total = &total + &contribution;
     }
+
+    &total * (1.0 / EMITTER_SAMPLES as f32)
 }
 
 fn shift_point_along_normal(dir: &Vec3f, normal: &Vec3f, point: &Vec3f) -> Vec3f {
@@ -184,57 +479,232 @@ fn shift_point_along_normal(dir: &Vec3f, normal: &Vec3f, point: &Vec3f) -> Vec3f
 *  field of view angle
 *  location of camera object in 3d space (as Vec3f)
 *  camera orientation, default is directly along the negative z direction
+*  the last three (and the shutter) come from the scene's Camera
 */
-fn render<T>(lights: &[Light], objs: &[Box<T>], filename: &str) where T: Object {
-    let tan_fov = (FOV / 2.0).tan();
+// renders the camera's samples per pixel as that many passes over the whole image, and writes the result to filename
+// given a checkpoint interval, the average of the passes so far gets written there too whenever that much time has gone by
+// so a long render can be looked at early, and stopped once it looks good enough
+// along with it goes a .state file with the running totals, which resuming picks back up to finish the render
+// exactly as it would have turned out if it had never been stopped
+// the state gets written first, so whatever point it's stopped at the picture is never ahead of it
+// and it's kept once the render is done, saying so with its pass count
+// with adaptive sampling, pixels that have stopped changing much sit out the rest of the passes
+// and with a crop window only the pixels inside it get traced, making either a picture of just that bit
+// or (compositing) a new version of filename with that bit rendered over again
+// (plus as many around it as the denoiser and effects look at, which get trimmed off again, so it's exactly that bit of the whole picture)
+// any aovs asked for get written next to it once it's done, as out_depth.pfm and so on, just for the pixels that were rendered
+fn render<T>(scene: &Scene<T>, filename: &str, options: &RenderOptions) where T: Object + ?Sized {
+    let samples = scene.camera().samples();
+    let state_path = format!("{}.state", filename);
+    let window = options.crop.unwrap_or(Window { x: 0, y: 0, width: WIDTH, height: HEIGHT });
+    let whole = Frame { x: 0, y: 0, width: WIDTH as usize, height: HEIGHT as usize };
+    let reach = options.denoiser.as_ref().map_or(0, |denoiser| denoiser.radius()) + postprocess::reach_all(scene.effects(), &whole);
+    let traced = window.padded(reach as u32);
+    let trim = |image: Image| image.region((window.x - traced.x) as usize, (window.y - traced.y) as usize, window.width as usize, window.height as usize);
+
+    let base = options.composite.then(|| {
+        let image = Image::load(filename).unwrap_or_else(|err| { panic!("Could not composite into {} due to {}", filename, err) });
+        if (image.width(), image.height()) != (WIDTH as usize, HEIGHT as usize) {
+            panic!("Could not composite into {} due to it being {}x{} instead of {}x{}", filename, image.width(), image.height(), WIDTH, HEIGHT);
+        }
+        image
+    });
+    // denoised if asked, along the edges in the normal and albedo aovs, which get kept track of for it whether they're written out or not
+    // then put through the scene's effects
+    let frame = Frame { x: traced.x as usize, y: traced.y as usize, ..whole };
+    let picture = |accumulator: &Accumulator| {
+        let image = match &options.denoiser {
+            Some(denoiser) => denoiser.denoise(&accumulator.image(), accumulator.aov_image(Aov::Normal).as_ref(), accumulator.aov_image(Aov::Albedo).as_ref()),
+            None => accumulator.image(),
+        };
+        let image = postprocess::apply_all(scene.effects(), image, &frame);
+        let image = trim(match accumulator.alpha() {
+            Some(alpha) => image.with_alpha(alpha),
+            None => image,
+        });
+        match &base {
+            Some(base) => {
+                let mut composited = base.clone();
+                composited.paste(&image, window.x as usize, window.y as usize);
+                composited
+            }
+            None => image,
+        }
+    };
+    let mut aovs = options.aovs.clone();
+    if options.denoiser.is_some() {
+        aovs.extend([Aov::Normal, Aov::Albedo].iter().filter(|aov| !options.aovs.contains(aov)));
+    }
+
+    let (mut accumulator, settings) = match options.resume {
+        true if Path::new(&state_path).exists() => {
+            let (accumulator, settings) = Accumulator::load_state(&state_path)
+                .unwrap_or_else(|err| { panic!("Could not resume from {} due to {}", state_path, err) });
+            if (accumulator.width(), accumulator.height()) != (traced.width as usize, traced.height as usize) {
+                panic!("Could not resume from {} due to it being {}x{} instead of {}x{}", state_path, accumulator.width(), accumulator.height(), traced.width, traced.height);
+            }
+            if (settings.x, settings.y) != (traced.x, traced.y) {
+                panic!("Could not resume from {} due to it being cropped at {},{} instead of {},{}", state_path, settings.x, settings.y, traced.x, traced.y);
+            }
+            if accumulator.aovs() != aovs {
+                panic!("Could not resume from {} due to it having aovs {:?} instead of {:?}", state_path, accumulator.aovs(), aovs);
+            }
+            if accumulator.has_alpha() != options.transparent {
+                panic!("Could not resume from {} due to it {} alpha", state_path, if accumulator.has_alpha() { "having" } else { "not having" });
+            }
+            if settings.max_error != options.max_error {
+                panic!("Could not resume from {} due to its adaptive error being {:?} instead of {:?}", state_path, settings.max_error, options.max_error);
+            }
+            if settings.sampler != options.sampler {
+                panic!("Could not resume from {} due to it using the {} sampler instead of {}", state_path, settings.sampler.name(), options.sampler.name());
+            }
+            if accumulator.passes() >= samples {
+                eprintln!("{}: already done", filename);
+                return;
+            }
+            eprintln!("{}: resuming after {} of {} passes", filename, accumulator.passes(), samples);
+            (accumulator, settings)
+        }
+        _ => {
+            let accumulator = Accumulator::new(traced.width as usize, traced.height as usize, &aovs);
+            let settings = Settings { seed: options.seed, x: traced.x, y: traced.y, max_error: options.max_error, sampler: options.sampler };
+            (if options.transparent { accumulator.with_alpha() } else { accumulator }, settings)
+        }
+    };
+    let mut last_checkpoint = Instant::now();
+
+    for pass in accumulator.passes()..samples {
+        render_pass(scene, pass, &traced, settings.seed, options, &mut accumulator);
+
+        if let Some(interval) = options.checkpoint.filter(|interval| last_checkpoint.elapsed() >= *interval && pass + 1 < samples) {
+            write_replacing(&state_path, |path| accumulator.save_state(&settings, path));
+            save(&picture(&accumulator), filename);
+            eprintln!("{}: {} of {} passes", filename, accumulator.passes(), samples);
+            last_checkpoint += interval;
+        }
+    }
+
+    save(&picture(&accumulator), filename);
+    let beside = |suffix: &str| {
+        let path = Path::new(filename);
+        path.with_file_name(format!("{}_{}", path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("out"), suffix)).to_string_lossy().into_owned()
+    };
+    if options.sample_map {
+        save(&trim(accumulator.sample_map()), &beside("samples.png"));
+    }
+    for aov in &options.aovs {
+        if let Some(image) = accumulator.aov_image(*aov) {
+            save(&trim(image), &beside(&format!("{}.pfm", aov.name())));
+        }
+    }
+    // the finished state is what tells resuming this one is done, or (with more samples) what to carry on from
+    // (though the random sampler splits the exposure into as many stretches as there are samples, so those don't line up with the passes before)
+    // with neither checkpoints nor resuming there's no call for one, and any left over from before is out of date
+    if options.checkpoint.is_some() || options.resume {
+        write_replacing(&state_path, |path| accumulator.save_state(&settings, path));
+    } else if Path::new(&state_path).exists() {
+        std::fs::remove_file(&state_path).unwrap_or_else(|err| { panic!("Could not remove {} due to {}", state_path, err) });
+    }
+}
+
+// one ray for every pixel in the window, or given a max_error, for every pixel that doesn't look to be within it yet
+// the accumulator only covers the window, so its pixels are counted from the window's top left corner
+fn render_pass<T>(scene: &Scene<T>, pass: u32, window: &Window, seed: u64, options: &RenderOptions, accumulator: &mut Accumulator) where T: Object + ?Sized {
     let aspect_ratio = (WIDTH as f32) / (HEIGHT as f32);
+    let camera = scene.camera();
+    let samples = camera.samples();
+    let with_aovs = !accumulator.aovs().is_empty();
 
     // TODO not sure this is the most idiomatic way to do this
     // but it makes sense given the whole "iterating over each pixel in the viewport" procedure
-    let mut frame_buf: Vec<Vec3f> = Vec::with_capacity((WIDTH * HEIGHT) as usize);
-
-    for j in 0..HEIGHT {
-        for i in 0..WIDTH {
-            let x = (2f32 * (i as f32 + 0.5) / WIDTH as f32 - 1f32) * tan_fov * aspect_ratio;
-            let y = -(2f32 * (j as f32 + 0.5) / HEIGHT as f32 - 1f32) * tan_fov;
-            let dir = Vec3f::from(&[x, y, -1f32]).normalize();
-            frame_buf.push(cast_ray(&Vec3f::from(&[0f32, 0f32, 0f32]), &dir, lights, objs, 1))
+    for j in window.y..window.y + window.height {
+        for i in window.x..window.x + window.width {
+            let (local_x, local_y) = ((i - window.x) as usize, (j - window.y) as usize);
+            if options.max_error.is_some_and(|error| pass >= MIN_ADAPTIVE_PASSES && accumulator.converged(local_x, local_y, error)) {
+                continue;
+            }
+
+            // every ray gets random numbers of its own, seeded from where it is and which pass it's in
+            // so they come out the same whichever pixels get rendered, in whatever order, and however many times the render gets stopped and resumed
+            let pixel = sampling::hash(&[seed, i as u64, j as u64]);
+            let mut rng = Rng::new(pixel, pass as u64);
+
+            // a single ray goes through the middle of the pixel halfway through the exposure
+            // more get spread over the pixel and the exposure however the sampler does it
+            let (offset_x, offset_y, moment) = if samples == 1 {
+                (0.5, 0.5, 0.5)
+            } else {
+                options.sampler.camera_sample(pass, samples, pixel, &mut rng)
+            };
+            let x = 2f32 * (i as f32 + offset_x) / WIDTH as f32 - 1f32;
+            let y = -(2f32 * (j as f32 + offset_y) / HEIGHT as f32 - 1f32);
+            let (orig, dir) = camera.ray(x, y, aspect_ratio);
+            // whatever of a transparent background shows through goes into the pixel's alpha instead
+            let coverage = |background: &Vec3f| (1.0 - (background[0] + background[1] + background[2]) / 3.0).clamp(0.0, 1.0);
+            if with_aovs {
+                let (color, background, sample) = cast_camera_ray(&orig, &dir, camera.time(moment), scene, options.transparent, &mut rng);
+                accumulator.add(local_x, local_y, &color, coverage(&background), Some(&sample));
+            } else {
+                let (color, background) = cast_ray(&orig, &dir, camera.time(moment), scene, 1, options.transparent, &mut rng);
+                accumulator.add(local_x, local_y, &color, coverage(&background), None);
+            }
         }
     }
 
-    write_buf_to_file(&frame_buf,
-                      &Vec2f::from(&[WIDTH as f32, HEIGHT as f32]),
-                      filename);
+    accumulator.finish_pass();
 }
 
-fn write_buf_to_file(framebuffer: &[Vec3f], dimensions: &Vec2f, filename: &str) {
-    let file = std::fs::File::create(filename).unwrap_or_else(|err| { panic!("Could not create file {} due to {}", filename, err) });
-
-    // definitely want buffered writer; we're talking about height*width writes of single pixels
-    let mut stream = BufWriter::new(file);
+fn save(image: &Image, filename: &str) {
+    write_replacing(filename, |path| image.save(path));
+}
 
-    let width = dimensions[0];
-    let height = dimensions[1];
+// written next to the file first and then moved over it, so a render stopped halfway through a checkpoint doesn't leave a broken file behind
+fn write_replacing<F>(filename: &str, write: F) where F: FnOnce(&str) -> io::Result<()> {
+    let path = Path::new(filename);
+    let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+    let partial = path.with_extension(format!("partial.{}", extension)).to_string_lossy().into_owned();
+    write(&partial).unwrap_or_else(|err| { panic!("Could not write {} due to {}", partial, err) });
+    std::fs::rename(&partial, filename).unwrap_or_else(|err| { panic!("Could not write {} due to {}", filename, err) });
+}
 
-    // ppm file header
-    stream.write_all(format!("P6\n{} {}\n255\n", width, height).as_bytes()).unwrap_or_else(|err| { panic!("Failed to write header to file {} due to {}", filename, err) });
+// an equirectangular .hdr or .pfm, or a procedural sky, replaces the flat background color and lights the scene too
+// None if none of the flags are there
+fn background_from_args(args: &[String]) -> Option<Background> {
+    if let Some(path) = arg_value(args, "--envmap") {
+        let image = Image::load(&path).unwrap_or_else(|err| { panic!("Could not load environment map {} due to {}", path, err) }).without_alpha();
+        let rotation = arg_value(args, "--envmap-rotation").map_or(0.0, |deg| parse_arg::<f32>("--envmap-rotation", &deg).to_radians());
+        let intensity = arg_value(args, "--envmap-intensity").map_or(1.0, |i| parse_arg("--envmap-intensity", &i));
 
-    for px in framebuffer {
-        for channel in 0..3 {
-            // TODO gamma/color correction
-            // this may be done by some magical process we don't know about; figure it out
-            // whatever you do for this keep in mind we're looking to support other filetypes, which may have their own approach
-            // we do some math here to confine the rbg color value to the range [0, 255]
-            // basically multiply 255 by some value clamped to range [0, 1]
-            let byte = (255f32 * geometry::max(0f32, geometry::min(1f32, px[channel]))) as u8;
-            stream.write_all(&[byte]).unwrap_or_else(|err| { panic!("Failed to write pixel {:?} to file {} due to {}", px, filename, err) });
-        }
+        return Some(Background::Environment(EnvironmentMap::new(image, rotation, intensity)));
     }
 
-    stream.flush().unwrap();
+    let model = match arg_value(args, "--sky").as_deref() {
+        Some("gradient") => SkyModel::Gradient { zenith: Vec3f::new3f(0.25, 0.45, 0.9), horizon: Vec3f::new3f(0.8, 0.85, 0.9) },
+        Some("preetham") => SkyModel::Preetham { turbidity: arg_value(args, "--turbidity").map_or(3.0, |t| parse_arg("--turbidity", &t)) },
+        Some(other) => panic!("Unknown sky model {}, expected gradient or preetham", other),
+        None => return None,
+    };
+    let elevation = arg_value(args, "--sun-elevation").map_or(35.0, |deg| parse_arg::<f32>("--sun-elevation", &deg));
+    let azimuth = arg_value(args, "--sun-azimuth").map_or(-40.0, |deg| parse_arg::<f32>("--sun-azimuth", &deg));
+    let sun_irradiance = arg_value(args, "--sun-intensity").map_or(2.0, |i| parse_arg("--sun-intensity", &i));
+    let intensity = arg_value(args, "--sky-intensity").map_or(1.0, |i| parse_arg("--sky-intensity", &i));
 
-    // files are automatically closed when they go out of scope
-    // consider using sync_all if we want to catch any issues with closing
+    // the real sun is about a quarter of a degree across, making it a bit bigger softens the shadows and makes it easier to sample
+    Some(Background::sky(Sky::new(model,
+                                  &sky::sun_direction(elevation.to_radians(), azimuth.to_radians()),
+                                  1f32.to_radians(),
+                                  sun_irradiance,
+                                  intensity)))
+}
+
+// value following flag on the command line, e.g. arg_value(args, "--envmap") for `--envmap sky.hdr`
+fn arg_value(args: &[String], flag: &str) -> Option<String> {
+    args.iter().position(|arg| arg == flag)
+        .map(|i| args.get(i + 1).unwrap_or_else(|| { panic!("Missing value after {}", flag) }).clone())
+}
+
+fn parse_arg<F: std::str::FromStr>(flag: &str, value: &str) -> F {
+    value.parse().unwrap_or_else(|_| { panic!("Invalid value {} for {}", value, flag) })
 }
 
 /* TODO for the whole project:
@@ -248,27 +718,311 @@ fn write_buf_to_file(framebuffer: &[Vec3f], dimensions: &Vec2f, filename: &str)
  *  - we have to implement everything ourselves
  *  - miss out on opportunity to learn about rust libs for computer graphics and file parsing
  */
-fn main() {
-    // TODO import materials/objects so we don't have to do these monstrosities
-    // no need to do any fancy .obj or .mtl or PBR materials parsing; just a yaml for now is ok
-    let ivory = Material::new(&Vec3f::new3f(0.4, 0.4, 0.3), &Vec4f::from(&[0.6, 0.3, 0.1, 0.0]), 50.0, 1.0);
-    let glass = Material::new(&Vec3f::new3f(0.6, 0.7, 0.8), &Vec4f::from(&[0.0, 0.5, 0.1, 0.8]), 125.0, 1.5);
-    let rubber = Material::new(&Vec3f::new3f(0.3, 0.1, 0.1), &Vec4f::from(&[0.9, 0.1, 0.0, 0.0]), 10.0, 1.0);
+// the scene we render when not given a scene file
+// scene files are much nicer for anything else than these monstrosities, see scene_file.rs
+fn default_scene() -> Scene<dyn Object> {
+    let ivory = Material::new(&Vec3f::new3f(0.4, 0.4, 0.3), &Vec4f::from(&[0.6, 0.3, 0.1, 0.0]), 50.0, 1.0)
+        .with_id(1);
+    let glass = Material::new(&Vec3f::new3f(0.6, 0.7, 0.8), &Vec4f::from(&[0.0, 0.5, 0.1, 0.8]), 125.0, 1.5)
+        .with_id(2);
+    let rubber = Material::new(&Vec3f::new3f(0.3, 0.1, 0.1), &Vec4f::from(&[0.9, 0.1, 0.0, 0.0]), 10.0, 1.0)
+        .with_id(3);
     // mirror glass is slightly slightly green irl
-    let mirror = Material::new(&Vec3f::new3f(0.9, 1.0, 0.9), &Vec4f::from(&[0.0, 1.0, 0.8, 0.0]), 1425.0, 1.0);
+    let mirror = Material::new(&Vec3f::new3f(0.9, 1.0, 0.9), &Vec4f::from(&[0.0, 1.0, 0.8, 0.0]), 1425.0, 1.0)
+        .with_id(4);
 
-    let lights = [Light::new(&Vec3f::new3f(-20.0, 20.0, 20.0), 1.5),
+    let lights = vec![Light::new(&Vec3f::new3f(-20.0, 20.0, 20.0), 1.5),
         Light::new(&Vec3f::new3f(30.0, 50.0, -25.0), 1.8),
         Light::new(&Vec3f::new3f(30.0, 20.0, 30.0), 1.7)];
 
-    let spheres = [
+    let spheres: Vec<Box<dyn Object>> = vec![
         //Box::new(Sphere::new(Vec3f::new3f(0.0, 0.0, -5.0), 1.0, &ivory)),
         Box::new(Sphere::new(Vec3f::new3f(-3.0, 0.0, -16.0), 2.0, &ivory)),
         Box::new(Sphere::new(Vec3f::new3f(-1.0, -1.5, -12.0), 2.0, &glass)),
         Box::new(Sphere::new(Vec3f::new3f(1.5, -0.5, -18.0), 3.0, &rubber)),
         Box::new(Sphere::new(Vec3f::new3f(7.0, 5.0, -18.0), 4.0, &mirror))];
 
-    // TODO render more than one frame
-    // TODO support non-PPM files
-    render(&lights, &spheres[..], "./out.ppm");
+    let checkerboard = Texture::Checker { even: Vec3f::new3f(0.3, 0.21, 0.09), odd: Vec3f::new3f(0.3, 0.3, 0.3), scale: 0.5 };
+    let floor = Material::new(&Vec3f::new3f(1.0, 1.0, 1.0), &Vec4f::from(&[1.0, 0.0, 0.0, 0.0]), 0.0, 1.0)
+        .with_diffuse_map(Arc::new(checkerboard))
+        .with_id(5);
+
+    Scene::new(lights, spheres, Background::Color(Vec3f::from(&BG_COLOR)), Some(floor))
+}
+
+// how to go about rendering, as opposed to what
+struct RenderOptions {
+    // how often to write out the picture so far
+    checkpoint: Option<Duration>,
+    // carry on from the state the last checkpoint left behind
+    resume: bool,
+    // stop sampling pixels once their standard error drops below this
+    max_error: Option<f32>,
+    // write how many rays each pixel got next to the picture
+    sample_map: bool,
+    // only render these pixels
+    crop: Option<Window>,
+    // paste them into the picture already there, rather than writing them out on their own
+    composite: bool,
+    // extra images to write alongside the picture
+    aovs: Vec<Aov>,
+    denoiser: Option<Denoiser>,
+    // what the random numbers are seeded with, the same seed giving the exact same picture
+    seed: u64,
+    sampler: Sampler,
+    // leave the background out of the picture, so it can be composited over something else
+    transparent: bool,
+}
+
+// a rectangle of pixels, counting from the top left of the picture
+#[derive(Debug, Clone, Copy)]
+struct Window {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+impl Window {
+    // margin more pixels on every side, as far as the picture goes
+    fn padded(&self, margin: u32) -> Window {
+        let (x, y) = (self.x.saturating_sub(margin), self.y.saturating_sub(margin));
+        let (right, bottom) = ((self.x + self.width + margin).min(WIDTH), (self.y + self.height + margin).min(HEIGHT));
+        Window { x, y, width: right - x, height: bottom - y }
+    }
+}
+
+fn crop_from_args(args: &[String]) -> Option<Window> {
+    let text = arg_value(args, "--crop")?;
+    let values: Vec<u32> = text.split(',').map(|v| parse_arg("--crop", v)).collect();
+    if values.len() != 4 {
+        panic!("Invalid value {} for --crop, expected x,y,width,height", text);
+    }
+
+    let window = Window { x: values[0], y: values[1], width: values[2], height: values[3] };
+    if window.width == 0 || window.height == 0 || window.x + window.width > WIDTH || window.y + window.height > HEIGHT {
+        panic!("Invalid crop window {}, it should be inside the {}x{} picture", text, WIDTH, HEIGHT);
+    }
+
+    Some(window)
+}
+
+fn render_options_from_args(args: &[String]) -> RenderOptions {
+    RenderOptions {
+        checkpoint: arg_value(args, "--checkpoint").map(|seconds| Duration::from_secs_f32(parse_arg("--checkpoint", &seconds))),
+        resume: args.iter().any(|arg| arg == "--resume"),
+        max_error: arg_value(args, "--adaptive").map(|error| parse_arg("--adaptive", &error)),
+        sample_map: args.iter().any(|arg| arg == "--sample-map"),
+        crop: crop_from_args(args),
+        composite: args.iter().any(|arg| arg == "--composite"),
+        aovs: aovs_from_args(args),
+        denoiser: arg_value(args, "--denoise").map(|strength| {
+            let radius = arg_value(args, "--denoise-radius").map_or(3, |r| parse_arg("--denoise-radius", &r));
+            Denoiser::new(parse_arg("--denoise", &strength)).with_radius(radius)
+        }),
+        seed: arg_value(args, "--seed").map_or(1, |seed| parse_arg("--seed", &seed)),
+        sampler: arg_value(args, "--sampler").map_or(Sampler::Random, |name| {
+            Sampler::parse(&name).unwrap_or_else(|| { panic!("Unknown sampler {}, expected random, halton or sobol", name) })
+        }),
+        transparent: alpha_format_from_args(args).is_some(),
+    }
+}
+
+// file format to write the picture out in with an alpha channel, for `--alpha png` or `--alpha exr`
+fn alpha_format_from_args(args: &[String]) -> Option<&'static str> {
+    arg_value(args, "--alpha").map(|format| match format.as_str() {
+        "png" => "png",
+        "exr" => "exr",
+        _ => panic!("Invalid value {} for --alpha, expected png or exr", format),
+    })
+}
+
+// e.g. depth,normal for `--aov depth,normal`, or every one there is for `--aov all`
+fn aovs_from_args(args: &[String]) -> Vec<Aov> {
+    match arg_value(args, "--aov").as_deref() {
+        None => Vec::new(),
+        Some("all") => Aov::ALL.to_vec(),
+        Some(names) => names.split(',')
+            .map(|name| Aov::parse(name).unwrap_or_else(|| { panic!("Unknown aov {}, expected one of {}", name, Aov::ALL.map(|aov| aov.name()).join(", ")) }))
+            .collect(),
+    }
+}
+
+// frames of the animation to render, e.g. 1-48 for `--frames 1-48`, or just the one for `--frames 12`
+fn frames_from_args(args: &[String]) -> Option<(u32, u32)> {
+    let range = arg_value(args, "--frames")?;
+    let (first, last) = match range.split_once('-') {
+        Some((first, last)) => (parse_arg("--frames", first), parse_arg("--frames", last)),
+        None => {
+            let frame = parse_arg("--frames", &range);
+            (frame, frame)
+        }
+    };
+    if last < first {
+        panic!("Invalid frame range {}, the last frame comes before the first", range);
+    }
+
+    Some((first, last))
+}
+
+// `diff <reference> <test>` prints how far apart two images are, see compare.rs
+// --heatmap writes out where they differ, and --tolerance fails with exit code 1 if the flip error goes over it
+fn diff(args: &[String]) {
+    let (reference_path, test_path) = match args {
+        [reference, test, ..] => (reference, test),
+        _ => panic!("Usage: diff <reference> <test> [--heatmap <file>] [--tolerance <flip error>]"),
+    };
+    let load = |path: &str| Image::load(path).unwrap_or_else(|err| { panic!("Could not load {} due to {}", path, err) });
+    let (reference, test) = (load(reference_path), load(test_path));
+    if (reference.width(), reference.height()) != (test.width(), test.height()) {
+        panic!("Could not compare {} to {} due to them being {}x{} and {}x{}", reference_path, test_path, reference.width(), reference.height(), test.width(), test.height());
+    }
+
+    let comparison = compare::compare(&reference, &test);
+    println!("rmse {}", comparison.rmse);
+    println!("psnr {} dB", comparison.psnr);
+    println!("flip {}", comparison.flip);
+
+    if let Some(path) = arg_value(args, "--heatmap") {
+        save(&comparison.heatmap, &path);
+    }
+    let tolerance: Option<f32> = arg_value(args, "--tolerance").map(|tolerance| parse_arg("--tolerance", &tolerance));
+    if tolerance.is_some_and(|tolerance| comparison.flip > tolerance) {
+        std::process::exit(1);
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("diff") {
+        diff(&args[2..]);
+        return;
+    }
+    let scene_path = arg_value(&args, "--scene");
+
+    // the scene file gets read again for every frame, with whatever is keyframed in it where it is on that frame
+    let load_scene = |frame: u32| {
+        let mut scene = match &scene_path {
+            Some(path) => scene_file::load(path, Background::Color(Vec3f::from(&BG_COLOR)), frame as f32)
+                .unwrap_or_else(|err| { panic!("Could not load scene {} due to {}", path, err) }),
+            None => default_scene(),
+        };
+
+        if let Some(background) = background_from_args(&args) {
+            scene.set_background(background);
+        }
+
+        scene
+    };
+
+    let options = render_options_from_args(&args);
+    // ppm can't have an alpha channel
+    let alpha_format = alpha_format_from_args(&args);
+
+    match frames_from_args(&args) {
+        Some((first, last)) => {
+            for frame in first..=last {
+                render(&load_scene(frame), &format!("./out_{:04}.{}", frame, alpha_format.unwrap_or("png")), &options);
+            }
+        }
+        None => render(&load_scene(1), &format!("./out.{}", alpha_format.unwrap_or("ppm")), &options),
+    }
+}
+
+// golden image tests: the default scene rendered and compared to how it looked when tests/golden/default.png was made
+// run with UPDATE_GOLDEN=1 to make it again, after changing how the scene is meant to look
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static GOLDEN: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden/default.png");
+    // how far a render can stray from the golden image before it counts as having changed
+    static MAX_FLIP: f32 = 0.01;
+    static MIN_PSNR: f32 = 40.0;
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir().join(format!("tinyrt_{}_{}", std::process::id(), name)).to_string_lossy().into_owned()
+    }
+
+    fn render_default_scene(name: &str, options: &RenderOptions) -> Image {
+        // saved the same way as the golden image, so an unchanged render comes back exactly the same
+        let path = temp_path(&format!("{}.png", name));
+        render(&default_scene(), &path, options);
+        let image = Image::load(&path).unwrap_or_else(|err| { panic!("Could not load {} due to {}", path, err) });
+        std::fs::remove_file(&path).unwrap_or_else(|err| { panic!("Could not remove {} due to {}", path, err) });
+        image
+    }
+
+    fn assert_matches(name: &str, golden: &Image, image: &Image) {
+        let comparison = compare::compare(golden, image);
+        if comparison.flip > MAX_FLIP || comparison.psnr < MIN_PSNR {
+            let heatmap = temp_path(&format!("{}_heatmap.png", name));
+            save(&comparison.heatmap, &heatmap);
+            panic!("{} drifted from the golden image, flip {} (at most {}) and psnr {} dB (at least {}), see {} for where",
+                   name, comparison.flip, MAX_FLIP, comparison.psnr, MIN_PSNR, heatmap);
+        }
+    }
+
+    fn golden() -> Image {
+        Image::load(GOLDEN).unwrap_or_else(|err| { panic!("Could not load {} due to {}", GOLDEN, err) })
+    }
+
+    #[test]
+    fn default_scene_matches_golden() {
+        let image = render_default_scene("full", &render_options_from_args(&[]));
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            save(&image, GOLDEN);
+            return;
+        }
+
+        assert_matches("full", &golden(), &image);
+    }
+
+    // the random numbers only depend on which pixel and pass they're for, so this should come out exactly the same too
+    #[test]
+    fn cropped_default_scene_matches_golden() {
+        let window = Window { x: 384, y: 352, width: 320, height: 256 };
+        let image = render_default_scene("crop", &RenderOptions { crop: Some(window), ..render_options_from_args(&[]) });
+
+        let region = golden().region(window.x as usize, window.y as usize, window.width as usize, window.height as usize);
+        assert_matches("crop", &region, &image);
+    }
+
+    #[test]
+    fn padded_windows() {
+        let sides = |w: Window| (w.x, w.y, w.width, w.height);
+        let args: Vec<String> = vec!["--crop".into(), "10,20,30,40".into()];
+        let window = crop_from_args(&args).unwrap();
+        assert_eq!(sides(window), (10, 20, 30, 40));
+        assert_eq!(sides(window.padded(5)), (5, 15, 40, 50));
+
+        // padding stops at the edges of the picture
+        assert_eq!(sides(window.padded(100)), (0, 0, 140, 160));
+        let corner = Window { x: WIDTH - 8, y: HEIGHT - 4, width: 8, height: 4 };
+        assert_eq!(sides(corner.padded(10)), (WIDTH - 18, HEIGHT - 14, 18, 14));
+        assert!(crop_from_args(&[]).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid crop window")]
+    fn crop_outside_the_picture() {
+        crop_from_args(&["--crop".into(), format!("{},0,1,1", WIDTH)]);
+    }
+
+    // with a transparent background, the sky in the top left corner should come out clear and the red sphere solid
+    #[test]
+    fn alpha_coverage() {
+        let args: Vec<String> = vec!["--alpha".into(), "png".into()];
+        for &(name, x, y, alpha) in &[("sky", 0, 0, 0.0), ("sphere", 570, 380, 1.0)] {
+            let window = Window { x, y, width: 16, height: 16 };
+            let image = render_default_scene(name, &RenderOptions { crop: Some(window), ..render_options_from_args(&args) });
+            for i in 0..16 * 16 {
+                let (px, py) = (i % 16, i / 16);
+                assert_eq!(image.alpha(px, py), alpha, "alpha of {} at {},{}", name, px, py);
+                if alpha == 0.0 {
+                    assert_eq!(image.get(px, py), &Vec3f::zero(), "color of {} at {},{}", name, px, py);
+                }
+            }
+        }
+    }
 }
@@ -1,4 +1,9 @@
-use crate::geometry::{Vec3f, Vec4f};
+use std::f32::consts::PI;
+use std::sync::Arc;
+use crate::geometry::{self, Vec2f, Vec3f, Vec4f};
+use crate::object::SurfaceHit;
+use crate::sampling;
+use crate::texture::Texture;
 
 #[derive(Debug)]
 pub struct Material {
@@ -12,11 +17,141 @@ pub struct Material {
     albedo: Vec4f,
     specular_exponent: f32,
     refractive_index: f32,
+    // radiance given off by the surface itself, zero for anything that isn't a light
+    emission: Vec3f,
+    // optional textures varying the above over the surface, see resolve()
+    diffuse_map: Option<Arc<Texture>>,
+    specular_map: Option<Arc<Texture>>,
+    roughness_map: Option<Arc<Texture>>,
+    // these two bend the shading normal instead, see shading_normal()
+    normal_map: Option<Arc<Texture>>,
+    bump_map: Option<Arc<Texture>>,
+    bump_strength: f32,
+    // tells materials apart in the material id aov, 0 for ones that were never given one
+    id: u32,
+}
+
+fn luminance(c: &Vec3f) -> f32 {
+    0.2126 * c[0] + 0.7152 * c[1] + 0.0722 * c[2]
 }
 
 impl Material {
     pub fn new(base_color: &Vec3f, albedo: &Vec3f, specular_exponent: f32, refractive_index: f32) -> Self {
-        Material { base_color: base_color.clone(), albedo: albedo.clone(), specular_exponent, refractive_index }
+        Material {
+            base_color: base_color.clone(),
+            albedo: albedo.clone(),
+            specular_exponent,
+            refractive_index,
+            emission: Vec3f::zero(),
+            diffuse_map: None,
+            specular_map: None,
+            roughness_map: None,
+            normal_map: None,
+            bump_map: None,
+            bump_strength: 0.0,
+            id: 0,
+        }
+    }
+
+    /// make any object wearing this material glow with the given color scaled by strength
+    pub fn with_emission(mut self, color: &Vec3f, strength: f32) -> Self {
+        self.emission = color * strength;
+        self
+    }
+
+    pub fn with_id(mut self, id: u32) -> Self {
+        self.id = id;
+        self
+    }
+
+    /// tints the base color
+    pub fn with_diffuse_map(mut self, texture: Arc<Texture>) -> Self {
+        self.diffuse_map = Some(texture);
+        self
+    }
+
+    /// scales the specular albedo by the brightness of the texture
+    pub fn with_specular_map(mut self, texture: Arc<Texture>) -> Self {
+        self.specular_map = Some(texture);
+        self
+    }
+
+    /// replaces the specular exponent, black being mirror smooth and white completely rough
+    pub fn with_roughness_map(mut self, texture: Arc<Texture>) -> Self {
+        self.roughness_map = Some(texture);
+        self
+    }
+
+    /// tangent space normal map, red/green/blue being the tangent/bitangent/normal components scaled from [-1, 1] to [0, 1]
+    pub fn with_normal_map(mut self, texture: Arc<Texture>) -> Self {
+        self.normal_map = Some(texture);
+        self
+    }
+
+    /// treats the brightness of the texture as a height above the surface, strength scaling how steep it gets
+    pub fn with_bump_map(mut self, texture: Arc<Texture>, strength: f32) -> Self {
+        self.bump_map = Some(texture);
+        self.bump_strength = strength;
+        self
+    }
+
+    /// normal to shade the hit with, which is the geometric one unless there's a normal or bump map
+    pub fn shading_normal(&self, hit: &SurfaceHit, point: &Vec3f) -> Vec3f {
+        let mut normal = hit.normal.clone();
+        // re-orthogonalize in case the tangent isn't quite perpendicular to the normal
+        let tangent = (&hit.tangent - &(&normal * normal.dot(&hit.tangent))).normalize();
+        let bitangent = normal.cross(&tangent);
+
+        if let Some(map) = &self.normal_map {
+            let texel = map.sample(&hit.uv, point);
+            let (x, y, z) = (2.0 * texel[0] - 1.0, 2.0 * texel[1] - 1.0, 2.0 * texel[2] - 1.0);
+            normal = (&(&(&tangent * x) + &(&bitangent * y)) + &(&normal * z)).normalize();
+        }
+
+        if let Some(map) = &self.bump_map {
+            // finite differences of the height along the tangent and bitangent
+            // images vary with uv and solid textures with position, so step both at once
+            let step = 0.001;
+            let height = |du: f32, dv: f32| {
+                let uv = Vec2f::from(&[hit.uv[0] + du, hit.uv[1] + dv]);
+                let p = &(point + &(&tangent * du)) + &(&bitangent * dv);
+                luminance(&map.sample(&uv, &p))
+            };
+            let h = height(0.0, 0.0);
+            let slope_u = (height(step, 0.0) - h) / step;
+            let slope_v = (height(0.0, step) - h) / step;
+
+            // tilting away from uphill, like the surface of an actual bump would
+            let tilt = &(&tangent * slope_u) + &(&bitangent * slope_v);
+            normal = (&normal - &(&tilt * self.bump_strength)).normalize();
+        }
+
+        normal
+    }
+
+    /// the plain, untextured material at one spot of the surface
+    pub fn resolve(&self, uv: &Vec2f, point: &Vec3f) -> Material {
+        let mut resolved = Material::new(&self.base_color, &self.albedo, self.specular_exponent, self.refractive_index)
+            .with_emission(&self.emission, 1.0)
+            .with_id(self.id);
+
+        if let Some(map) = &self.diffuse_map {
+            resolved.base_color = &resolved.base_color * &map.sample(uv, point);
+        }
+        if let Some(map) = &self.specular_map {
+            resolved.albedo[1] *= luminance(&map.sample(uv, point));
+        }
+        if let Some(map) = &self.roughness_map {
+            // the usual beckmann roughness to phong exponent conversion, see walter et al. 2007 section 5.2
+            let roughness = luminance(&map.sample(uv, point)).clamp(0.01, 1.0);
+            resolved.specular_exponent = 2.0 / (roughness * roughness) - 2.0;
+        }
+
+        resolved
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
     }
 
     pub fn color(&self) -> &Vec3f {
@@ -34,11 +169,81 @@ impl Material {
     pub fn refractive_index(&self) -> f32 {
         self.refractive_index
     }
+
+    pub fn emission(&self) -> &Vec3f {
+        &self.emission
+    }
+
+    pub fn is_emissive(&self) -> bool {
+        self.emission[0] > 0.0 || self.emission[1] > 0.0 || self.emission[2] > 0.0
+    }
+
+    // the point lights in cast_ray use a hand-tuned shading formula, but area lights have to be integrated,
+    // so for those the diffuse and specular albedos are treated as a lambert lobe plus a normalized phong lobe
+    // light_dir points from the surface towards the light, view_dir is the direction the ray came in with
+    /// how much light arriving along light_dir gets scattered back along the incoming ray
+    pub fn eval(&self, normal: &Vec3f, light_dir: &Vec3f, view_dir: &Vec3f) -> Vec3f {
+        if normal.dot(light_dir) <= 0.0 {
+            return Vec3f::zero();
+        }
+
+        let diffuse = &self.base_color * (self.albedo[0] / PI);
+        let cos_alpha = geometry::max(0.0, geometry::reflect(light_dir, normal).dot(view_dir));
+        let specular = self.albedo[1] * (self.specular_exponent + 2.0) / (2.0 * PI) * cos_alpha.powf(self.specular_exponent);
+
+        &diffuse + &Vec3f::new3f(specular, specular, specular)
+    }
+
+    // pick between the two lobes in proportion to how much each contributes
+    fn specular_weight(&self) -> f32 {
+        let total = self.albedo[0] + self.albedo[1];
+
+        if total > 0.0 { self.albedo[1] / total } else { 0.0 }
+    }
+
+    /// whether sample() can produce anything at all
+    pub fn scatters(&self) -> bool {
+        self.albedo[0] + self.albedo[1] > 0.0
+    }
+
+    /// direction towards which to look for incoming light, distributed roughly like eval()
+    pub fn sample(&self, normal: &Vec3f, view_dir: &Vec3f, u: f32, v: f32) -> Vec3f {
+        let spec_weight = self.specular_weight();
+
+        if u < spec_weight {
+            let mirror = geometry::reflect(view_dir, normal);
+            sampling::phong_lobe(&mirror, self.specular_exponent, u / spec_weight, v)
+        } else {
+            sampling::cosine_hemisphere(normal, (u - spec_weight) / (1.0 - spec_weight), v)
+        }
+    }
+
+    /// probability density (per solid angle) of sample() returning light_dir
+    pub fn pdf(&self, normal: &Vec3f, light_dir: &Vec3f, view_dir: &Vec3f) -> f32 {
+        let spec_weight = self.specular_weight();
+        let mirror = geometry::reflect(view_dir, normal);
+
+        spec_weight * sampling::phong_lobe_pdf(&mirror, self.specular_exponent, light_dir)
+            + (1.0 - spec_weight) * sampling::cosine_hemisphere_pdf(normal, light_dir)
+    }
 }
 
 impl Clone for Material {
     fn clone(&self) -> Self {
-        Material::new(&self.base_color.clone(), &self.albedo.clone(), self.specular_exponent, self.refractive_index)
+        Material {
+            base_color: self.base_color.clone(),
+            albedo: self.albedo.clone(),
+            specular_exponent: self.specular_exponent,
+            refractive_index: self.refractive_index,
+            emission: self.emission.clone(),
+            diffuse_map: self.diffuse_map.clone(),
+            specular_map: self.specular_map.clone(),
+            roughness_map: self.roughness_map.clone(),
+            normal_map: self.normal_map.clone(),
+            bump_map: self.bump_map.clone(),
+            bump_strength: self.bump_strength,
+            id: self.id,
+        }
     }
 
     fn clone_from(&mut self, source: &Self) {
@@ -46,5 +251,84 @@ impl Clone for Material {
         self.albedo = source.albedo.clone();
         self.specular_exponent = source.specular_exponent;
         self.refractive_index = source.refractive_index;
+        self.emission = source.emission.clone();
+        self.diffuse_map = source.diffuse_map.clone();
+        self.specular_map = source.specular_map.clone();
+        self.roughness_map = source.roughness_map.clone();
+        self.normal_map = source.normal_map.clone();
+        self.bump_map = source.bump_map.clone();
+        self.bump_strength = source.bump_strength;
+        self.id = source.id;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::Image;
+    use crate::texture::WrapMode;
+
+    // the same everywhere
+    fn flat(value: &Vec3f) -> Arc<Texture> {
+        Arc::new(Texture::Checker { even: value.clone(), odd: value.clone(), scale: 1.0 })
+    }
+
+    fn plain() -> Material {
+        Material::new(&Vec3f::new3f(0.8, 0.6, 0.4), &Vec4f::from(&[0.6, 0.5, 0.1, 0.0]), 50.0, 1.0).with_id(3)
+    }
+
+    #[test]
+    fn resolved_maps() {
+        let (uv, point) = (Vec2f::from(&[0.5, 0.5]), Vec3f::zero());
+        let untextured = plain().resolve(&uv, &point);
+        assert_eq!((untextured.color(), untextured.albedo(), untextured.specular_exponent(), untextured.id()),
+                   (plain().color(), plain().albedo(), 50.0, 3));
+
+        let material = plain()
+            .with_diffuse_map(flat(&Vec3f::new3f(0.5, 1.0, 0.0)))
+            .with_specular_map(flat(&Vec3f::new3f(0.5, 0.5, 0.5)))
+            .with_roughness_map(flat(&Vec3f::new3f(0.5, 0.5, 0.5)))
+            .resolve(&uv, &point);
+        assert_eq!(material.color(), &Vec3f::new3f(0.4, 0.6, 0.0));
+        assert!((material.albedo()[1] - 0.25).abs() < 1e-6, "{:?}", material.albedo());
+        assert_eq!((material.albedo()[0], material.albedo()[2]), (0.6, 0.1));
+        // beckmann roughness of a half is a phong exponent of 6
+        assert!((material.specular_exponent() - 6.0).abs() < 1e-3, "{}", material.specular_exponent());
+    }
+
+    // facing up, with u increasing along x (and so v along -z)
+    fn hit(u: f32, v: f32) -> SurfaceHit {
+        SurfaceHit { distance: 1.0, normal: Vec3f::new3f(0.0, 1.0, 0.0), uv: Vec2f::from(&[u, v]), tangent: Vec3f::new3f(1.0, 0.0, 0.0) }
+    }
+
+    fn assert_close(a: &Vec3f, b: &Vec3f) {
+        assert!((a - b).magnitude() < 1e-3, "{:?} isn't {:?}", a, b);
+    }
+
+    #[test]
+    fn normal_maps() {
+        let point = Vec3f::zero();
+        assert_close(&plain().shading_normal(&hit(0.3, 0.3), &point), &Vec3f::new3f(0.0, 1.0, 0.0));
+        // straight out of the surface, as flat normal maps are
+        let flat_map = plain().with_normal_map(flat(&Vec3f::new3f(0.5, 0.5, 1.0)));
+        assert_close(&flat_map.shading_normal(&hit(0.3, 0.3), &point), &Vec3f::new3f(0.0, 1.0, 0.0));
+        // leaning over towards u, and towards v
+        let tilted = plain().with_normal_map(flat(&Vec3f::new3f(1.0, 0.5, 0.5)));
+        assert_close(&tilted.shading_normal(&hit(0.3, 0.3), &point), &Vec3f::new3f(1.0, 0.0, 0.0));
+        let tilted = plain().with_normal_map(flat(&Vec3f::new3f(0.5, 1.0, 1.0)));
+        assert_close(&tilted.shading_normal(&hit(0.3, 0.3), &point), &Vec3f::new3f(0.0, 1.0, -1.0).normalize());
+    }
+
+    #[test]
+    fn bump_maps() {
+        // black to white along u, which between the two texel centers is a slope of 2
+        let ramp = Image::new(2, 1, vec![Vec3f::zero(), Vec3f::new3f(1.0, 1.0, 1.0)]);
+        let material = plain().with_bump_map(Arc::new(Texture::image(Arc::new(ramp), WrapMode::Clamp, 1.0)), 0.5);
+        // which tilts the normal away from uphill, by 45 degrees at this strength
+        assert_close(&material.shading_normal(&hit(0.5, 0.5), &Vec3f::zero()), &Vec3f::new3f(-1.0, 1.0, 0.0).normalize());
+
+        // but not where the surface is flat
+        let level = plain().with_bump_map(flat(&Vec3f::new3f(0.7, 0.7, 0.7)), 5.0);
+        assert_close(&level.shading_normal(&hit(0.5, 0.5), &Vec3f::zero()), &Vec3f::new3f(0.0, 1.0, 0.0));
     }
 }
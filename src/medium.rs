@@ -0,0 +1,211 @@
+// participating media: fog, smoke and anything else that light doesn't pass through untouched
+// every medium here is homogeneous, so how much light gets through a stretch of it only depends on how long the stretch is
+
+use std::f32::consts::PI;
+use crate::geometry::Vec3f;
+use crate::object::{Object, SurfaceHit};
+
+/// how much light a medium swallows and bounces around per unit of distance travelled, per color channel
+#[derive(Debug, Clone)]
+pub struct Medium {
+    absorption: Vec3f,
+    scattering: Vec3f,
+    // henyey-greenstein asymmetry: positive scatters mostly forwards, negative mostly backwards, 0 evenly in all directions
+    g: f32,
+}
+
+impl Medium {
+    pub fn new(absorption: &Vec3f, scattering: &Vec3f, g: f32) -> Self {
+        // at exactly 1 or -1 the phase function turns into a spike
+        Medium { absorption: absorption.clone(), scattering: scattering.clone(), g: g.clamp(-0.99, 0.99) }
+    }
+
+    pub fn scattering(&self) -> &Vec3f {
+        &self.scattering
+    }
+
+    /// light lost per unit distance, whether absorbed or scattered off somewhere else
+    pub fn extinction(&self) -> Vec3f {
+        &self.absorption + &self.scattering
+    }
+
+    pub fn scatters(&self) -> bool {
+        self.scattering.dot(&self.scattering) > 0.0
+    }
+
+    /// fraction of light travelling along dir that gets scattered into the direction at cos_theta to it, per steradian
+    pub fn phase(&self, cos_theta: f32) -> f32 {
+        let g = self.g;
+        let denominator = 1.0 + g * g - 2.0 * g * cos_theta;
+
+        (1.0 - g * g) / (4.0 * PI * denominator * denominator.sqrt())
+    }
+
+    // two media in the same place act like one with both their particles
+    // the phase function becomes the average of both, weighted by how much each one scatters
+    fn mix(&self, other: &Medium) -> Medium {
+        let weight = self.scattering[0] + self.scattering[1] + self.scattering[2];
+        let other_weight = other.scattering[0] + other.scattering[1] + other.scattering[2];
+        let g = if weight + other_weight > 0.0 { (self.g * weight + other.g * other_weight) / (weight + other_weight) } else { 0.0 };
+
+        Medium::new(&(&self.absorption + &other.absorption), &(&self.scattering + &other.scattering), g)
+    }
+}
+
+/// fraction of light making it through distance worth of a medium with the given extinction, per color channel
+pub fn attenuation(extinction: &Vec3f, distance: f32) -> Vec3f {
+    Vec3f::new3f((-extinction[0] * distance).exp(), (-extinction[1] * distance).exp(), (-extinction[2] * distance).exp())
+}
+
+enum Region {
+    // fills the whole scene
+    // reach is how far away the background counts as being, since anything infinitely deep in fog would be black
+    Everywhere { reach: f32 },
+    // the inside of a closed object, which itself is never drawn
+    Inside(Box<dyn Object>),
+}
+
+/// a medium and the part of the scene it fills
+pub struct Volume {
+    medium: Medium,
+    region: Region,
+}
+
+impl Volume {
+    pub fn global(medium: Medium, reach: f32) -> Self {
+        Volume { medium, region: Region::Everywhere { reach } }
+    }
+
+    pub fn bounded(medium: Medium, boundary: Box<dyn Object>) -> Self {
+        Volume { medium, region: Region::Inside(boundary) }
+    }
+
+    // the stretches of the ray between 0 and max_distance that are inside the volume
+    fn spans(&self, orig: &Vec3f, dir: &Vec3f, time: f32, max_distance: f32) -> Vec<(f32, f32)> {
+        match &self.region {
+            Region::Everywhere { reach } => vec![(0.0, if max_distance.is_finite() { max_distance } else { *reach })],
+            Region::Inside(boundary) => {
+                let crossings = boundary.crossings(orig, dir, time);
+                let entering = |hit: &SurfaceHit| hit.normal.dot(dir) < 0.0;
+
+                // same as with csg, leaving the boundary first means we started out inside
+                let mut start = if crossings.first().is_some_and(|hit| !entering(hit)) { Some(0.0) } else { None };
+                let mut spans = Vec::new();
+                for hit in &crossings {
+                    match start {
+                        None if entering(hit) => start = Some(hit.distance),
+                        Some(from) if !entering(hit) => {
+                            spans.push((from, hit.distance.min(max_distance)));
+                            start = None;
+                        }
+                        _ => {}
+                    }
+                }
+                // a boundary that never closes again (a disk seen edge on, a mesh with holes) just gets ignored past its last crossing
+
+                spans.into_iter().filter(|(from, to)| from < to).collect()
+            }
+        }
+    }
+}
+
+/// a stretch of a ray through unchanging media
+pub struct Segment {
+    pub start: f32,
+    pub end: f32,
+    pub medium: Medium,
+}
+
+/// the stretches of the ray from orig up to max_distance (which can be infinite, for rays that escape the scene) that pass through any of the volumes, nearest first
+/// wherever volumes overlap their media are mixed
+pub fn segments(volumes: &[Volume], orig: &Vec3f, dir: &Vec3f, time: f32, max_distance: f32) -> Vec<Segment> {
+    let spans: Vec<Vec<(f32, f32)>> = volumes.iter().map(|volume| volume.spans(orig, dir, time, max_distance)).collect();
+
+    let mut bounds: Vec<f32> = spans.iter().flatten().flat_map(|&(from, to)| [from, to]).collect();
+    bounds.sort_by(f32::total_cmp);
+    bounds.dedup();
+
+    bounds.windows(2).filter_map(|pair| {
+        let middle = (pair[0] + pair[1]) / 2.0;
+        let medium = volumes.iter().zip(&spans)
+            .filter(|(_, spans)| spans.iter().any(|&(from, to)| from <= middle && middle <= to))
+            .fold(None, |mixed: Option<Medium>, (volume, _)| Some(mixed.map_or_else(|| volume.medium.clone(), |m| m.mix(&volume.medium))));
+
+        medium.map(|medium| Segment { start: pair[0], end: pair[1], medium })
+    }).collect()
+}
+
+/// fraction of light making it from orig to distance along dir through all the volumes in the way
+pub fn transmittance(volumes: &[Volume], orig: &Vec3f, dir: &Vec3f, time: f32, distance: f32) -> Vec3f {
+    segments(volumes, orig, dir, time, distance).iter().fold(Vec3f::new3f(1.0, 1.0, 1.0), |total, segment| {
+        &total * &attenuation(&segment.medium.extinction(), segment.end - segment.start)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Vec4f;
+    use crate::material::Material;
+    use crate::object::Sphere;
+
+    fn assert_close(a: &Vec3f, b: &Vec3f) {
+        assert!((a - b).magnitude() < 1e-5, "{:?} isn't {:?}", a, b);
+    }
+
+    // a ball of radius 1 at 5 along -z, so a ray down the z axis spends 2 units inside it
+    fn ball(medium: Medium) -> Volume {
+        let material = Material::new(&Vec3f::new3f(1.0, 1.0, 1.0), &Vec4f::from(&[1.0, 0.0, 0.0, 0.0]), 1.0, 1.0);
+        Volume::bounded(medium, Box::new(Sphere::new(Vec3f::new3f(0.0, 0.0, -5.0), 1.0, &material)))
+    }
+
+    // beer-lambert: e^(-extinction * distance inside), whatever the ray does outside the volume
+    #[test]
+    fn bounded_transmittance() {
+        let medium = Medium::new(&Vec3f::new3f(0.1, 0.2, 0.3), &Vec3f::new3f(0.2, 0.0, 0.1), 0.3);
+        let volumes = [ball(medium)];
+        let (orig, dir) = (Vec3f::zero(), Vec3f::new3f(0.0, 0.0, -1.0));
+        let through = Vec3f::new3f((-0.6f32).exp(), (-0.4f32).exp(), (-0.8f32).exp());
+        assert_close(&transmittance(&volumes, &orig, &dir, 0.0, 100.0), &through);
+        assert_close(&transmittance(&volumes, &orig, &dir, 0.0, f32::INFINITY), &through);
+        // stopping halfway through
+        assert_close(&transmittance(&volumes, &orig, &dir, 0.0, 5.0), &Vec3f::new3f((-0.3f32).exp(), (-0.2f32).exp(), (-0.4f32).exp()));
+        // missing it altogether, or stopping before it
+        assert_close(&transmittance(&volumes, &orig, &Vec3f::new3f(0.0, 1.0, 0.0), 0.0, 100.0), &Vec3f::new3f(1.0, 1.0, 1.0));
+        assert_close(&transmittance(&volumes, &orig, &dir, 0.0, 3.0), &Vec3f::new3f(1.0, 1.0, 1.0));
+        // starting inside
+        assert_close(&transmittance(&volumes, &Vec3f::new3f(0.0, 0.0, -5.0), &dir, 0.0, 100.0), &Vec3f::new3f((-0.3f32).exp(), (-0.2f32).exp(), (-0.4f32).exp()));
+    }
+
+    // overlapping volumes add up, and global fog stops at its reach when nothing's hit
+    #[test]
+    fn overlapping_volumes() {
+        let fog = Medium::new(&Vec3f::new3f(0.01, 0.01, 0.01), &Vec3f::zero(), 0.0);
+        let smoke = Medium::new(&Vec3f::zero(), &Vec3f::new3f(0.5, 0.5, 0.5), 0.0);
+        let volumes = [Volume::global(fog, 50.0), ball(smoke)];
+        let (orig, dir) = (Vec3f::zero(), Vec3f::new3f(0.0, 0.0, -1.0));
+
+        let segments = segments(&volumes, &orig, &dir, 0.0, f32::INFINITY);
+        let stretches: Vec<(f32, f32)> = segments.iter().map(|segment| (segment.start, segment.end)).collect();
+        assert_eq!(stretches.len(), 3, "{:?}", stretches);
+        assert!((stretches[1].0 - 4.0).abs() < 1e-5 && (stretches[1].1 - 6.0).abs() < 1e-5 && stretches[2].1 == 50.0, "{:?}", stretches);
+        assert_close(&segments[1].medium.extinction(), &Vec3f::new3f(0.51, 0.51, 0.51));
+
+        let total = (-0.01f32 * 50.0 - 0.5 * 2.0).exp();
+        assert_close(&transmittance(&volumes, &orig, &dir, 0.0, f32::INFINITY), &Vec3f::new3f(total, total, total));
+    }
+
+    // the phase function spreads all the light scattered somewhere over the sphere of directions
+    #[test]
+    fn phase_integrates_to_one() {
+        for g in [-0.7, 0.0, 0.3, 0.9] {
+            let medium = Medium::new(&Vec3f::zero(), &Vec3f::new3f(1.0, 1.0, 1.0), g);
+            let steps = 20000;
+            let total: f32 = (0..steps).map(|i| {
+                let cos_theta = -1.0 + 2.0 * (i as f32 + 0.5) / steps as f32;
+                medium.phase(cos_theta) * 2.0 * PI * 2.0 / steps as f32
+            }).sum();
+            assert!((total - 1.0).abs() < 1e-3, "g = {} integrates to {}", g, total);
+        }
+    }
+}
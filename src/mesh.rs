@@ -0,0 +1,446 @@
+// triangle meshes, loaded from wavefront .obj files
+// a mesh is only geometry, so one of them can be shared by any number of instances, each with its own material and transform
+
+use std::io::{self, ErrorKind};
+use std::sync::Arc;
+use crate::geometry::{Aabb, Vec2f, Vec3f};
+use crate::material::Material;
+use crate::object::{Object, SurfaceHit};
+
+// leaves with this many triangles or fewer aren't worth splitting further
+static MAX_LEAF_TRIANGLES: usize = 4;
+
+#[derive(Debug)]
+struct Triangle {
+    // indices into the mesh's positions, normals and uvs
+    // faces without normals get the flat face normal, faces without uvs get their barycentric coordinates
+    positions: [usize; 3],
+    normals: Option<[usize; 3]>,
+    uvs: Option<[usize; 3]>,
+}
+
+// bounding volume hierarchy, flattened into a vec with the root first
+#[derive(Debug)]
+enum BvhNode {
+    // triangles[start..start + count] are inside
+    Leaf { bounds: Aabb, start: usize, count: usize },
+    Interior { bounds: Aabb, left: usize, right: usize },
+}
+
+impl BvhNode {
+    fn bounds(&self) -> &Aabb {
+        match self {
+            BvhNode::Leaf { bounds, .. } | BvhNode::Interior { bounds, .. } => bounds,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Mesh {
+    positions: Vec<Vec3f>,
+    normals: Vec<Vec3f>,
+    uvs: Vec<Vec2f>,
+    // reordered while building the bvh so every leaf's triangles sit next to each other
+    triangles: Vec<Triangle>,
+    nodes: Vec<BvhNode>,
+    // running total of the triangle areas, for picking triangles in proportion to their size
+    area_cdf: Vec<f32>,
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg)
+}
+
+// obj indices start at 1, negative ones count back from the last vertex read so far
+fn obj_index(token: &str, count: usize) -> Result<usize, String> {
+    let index: i64 = token.parse().map_err(|_| format!("bad index {}", token))?;
+    let resolved = if index < 0 { count as i64 + index } else { index - 1 };
+    if resolved < 0 || resolved >= count as i64 {
+        return Err(format!("index {} out of range", token));
+    }
+    Ok(resolved as usize)
+}
+
+fn obj_floats(tokens: &[&str], count: usize) -> Result<Vec<f32>, String> {
+    let values = tokens.iter().take(count)
+        .map(|v| v.parse::<f32>().map_err(|_| format!("expected a number, got {}", v)))
+        .collect::<Result<Vec<f32>, String>>()?;
+    if values.len() < count {
+        return Err(format!("expected {} numbers", count));
+    }
+    Ok(values)
+}
+
+impl Mesh {
+    /// read the vertices and faces of an .obj file, ignoring materials, groups and anything else in it
+    pub fn load_obj(path: &str) -> io::Result<Self> {
+        let source = std::fs::read_to_string(path)?;
+        let mut positions = Vec::new();
+        let mut normals = Vec::new();
+        let mut uvs = Vec::new();
+        let mut triangles = Vec::new();
+
+        for (number, line) in source.lines().enumerate() {
+            let tokens: Vec<&str> = line.split('#').next().unwrap_or("").split_whitespace().collect();
+            let parsed: Result<(), String> = match tokens.first() {
+                Some(&"v") => obj_floats(&tokens[1..], 3).map(|v| positions.push(Vec3f::from(&v))),
+                Some(&"vn") => obj_floats(&tokens[1..], 3).map(|v| normals.push(Vec3f::from(&v).normalize())),
+                Some(&"vt") => obj_floats(&tokens[1..], 2).map(|v| uvs.push(Vec2f::from(&v))),
+                Some(&"f") => Self::parse_face(&tokens[1..], positions.len(), uvs.len(), normals.len())
+                    .map(|face| triangles.extend(face)),
+                _ => Ok(()),
+            };
+            parsed.map_err(|msg| invalid(format!("{}:{}: {}", path, number + 1, msg)))?;
+        }
+
+        if triangles.is_empty() {
+            return Err(invalid(format!("{} has no faces", path)));
+        }
+
+        Ok(Mesh::new(positions, normals, uvs, triangles))
+    }
+
+    // corners are v, v/vt, v//vn or v/vt/vn, and polygons with more than three get split into a fan of triangles
+    fn parse_face(corners: &[&str], position_count: usize, uv_count: usize, normal_count: usize) -> Result<Vec<Triangle>, String> {
+        if corners.len() < 3 {
+            return Err("faces need at least 3 corners".to_string());
+        }
+
+        let mut parsed = Vec::with_capacity(corners.len());
+        for corner in corners {
+            let mut parts = corner.split('/');
+            let position = obj_index(parts.next().unwrap_or(""), position_count)?;
+            let uv = match parts.next() {
+                Some("") | None => None,
+                Some(token) => Some(obj_index(token, uv_count)?),
+            };
+            let normal = match parts.next() {
+                Some("") | None => None,
+                Some(token) => Some(obj_index(token, normal_count)?),
+            };
+            parsed.push((position, uv, normal));
+        }
+
+        Ok((1..parsed.len() - 1).map(|i| {
+            let corners = [&parsed[0], &parsed[i], &parsed[i + 1]];
+            Triangle {
+                positions: [corners[0].0, corners[1].0, corners[2].0],
+                uvs: match (corners[0].1, corners[1].1, corners[2].1) {
+                    (Some(a), Some(b), Some(c)) => Some([a, b, c]),
+                    _ => None,
+                },
+                normals: match (corners[0].2, corners[1].2, corners[2].2) {
+                    (Some(a), Some(b), Some(c)) => Some([a, b, c]),
+                    _ => None,
+                },
+            }
+        }).collect())
+    }
+
+    fn new(positions: Vec<Vec3f>, normals: Vec<Vec3f>, uvs: Vec<Vec2f>, triangles: Vec<Triangle>) -> Self {
+        let mut mesh = Mesh { positions, normals, uvs, triangles, nodes: Vec::new(), area_cdf: Vec::new() };
+
+        let mut order: Vec<usize> = (0..mesh.triangles.len()).collect();
+        let centers: Vec<Vec3f> = mesh.triangles.iter().map(|t| mesh.triangle_bounds(t).center()).collect();
+        mesh.build_node(&mut order, 0, &centers);
+
+        // put the triangles in the order the leaves expect them
+        let mut triangles: Vec<Option<Triangle>> = mesh.triangles.drain(..).map(Some).collect();
+        mesh.triangles = order.iter().map(|&i| triangles[i].take().unwrap()).collect();
+
+        let mut total = 0.0;
+        mesh.area_cdf = mesh.triangles.iter().map(|t| {
+            total += mesh.triangle_area(t);
+            total
+        }).collect();
+
+        mesh
+    }
+
+    fn corners(&self, triangle: &Triangle) -> [&Vec3f; 3] {
+        [&self.positions[triangle.positions[0]], &self.positions[triangle.positions[1]], &self.positions[triangle.positions[2]]]
+    }
+
+    fn triangle_bounds(&self, triangle: &Triangle) -> Aabb {
+        let mut bounds = Aabb::empty();
+        for corner in self.corners(triangle) {
+            bounds.grow(corner);
+        }
+        bounds
+    }
+
+    fn triangle_area(&self, triangle: &Triangle) -> f32 {
+        let [a, b, c] = self.corners(triangle);
+        (b - a).cross(&(c - a)).magnitude() * 0.5
+    }
+
+    // splits order[..] at the median center along the longest axis until the pieces are small, returns the node's index
+    // offset is where order starts within the whole mesh, so leaves know where their triangles will end up
+    fn build_node(&mut self, order: &mut [usize], offset: usize, centers: &[Vec3f]) -> usize {
+        let bounds = order.iter().fold(Aabb::empty(), |b, &i| b.union(&self.triangle_bounds(&self.triangles[i])));
+        let index = self.nodes.len();
+
+        if order.len() <= MAX_LEAF_TRIANGLES {
+            self.nodes.push(BvhNode::Leaf { bounds, start: offset, count: order.len() });
+            return index;
+        }
+
+        let center_bounds = order.iter().fold(Aabb::empty(), |mut b, &i| {
+            b.grow(&centers[i]);
+            b
+        });
+        let axis = center_bounds.longest_axis();
+        order.sort_by(|&a, &b| centers[a][axis].total_cmp(&centers[b][axis]));
+
+        // children get filled in once they exist
+        self.nodes.push(BvhNode::Interior { bounds, left: 0, right: 0 });
+        let middle = order.len() / 2;
+        let (left_order, right_order) = order.split_at_mut(middle);
+        let left = self.build_node(left_order, offset, centers);
+        let right = self.build_node(right_order, offset + middle, centers);
+        if let BvhNode::Interior { left: l, right: r, .. } = &mut self.nodes[index] {
+            *l = left;
+            *r = right;
+        }
+
+        index
+    }
+
+    // möller-trumbore, gives the distance and the barycentric coordinates of the hit
+    fn intersect_triangle(&self, triangle: &Triangle, orig: &Vec3f, dir: &Vec3f) -> Option<(f32, f32, f32)> {
+        let [a, b, c] = self.corners(triangle);
+        let edge1 = b - a;
+        let edge2 = c - a;
+        let p = dir.cross(&edge2);
+        let det = edge1.dot(&p);
+        // the ray runs along the triangle's plane
+        if det.abs() < 1e-9 {
+            return None;
+        }
+
+        let inv_det = 1.0 / det;
+        let s = orig - a;
+        let u = s.dot(&p) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = s.cross(&edge1);
+        let v = dir.dot(&q) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let t = edge2.dot(&q) * inv_det;
+        if t > 0.0 { Some((t, u, v)) } else { None }
+    }
+
+    // triangles the ray hits as (distance, index, barycentric u, v), either just the nearest or all of them
+    fn triangle_hits(&self, orig: &Vec3f, dir: &Vec3f, nearest_only: bool) -> Vec<(f32, usize, f32, f32)> {
+        let inv_dir = Vec3f::new3f(1.0 / dir[0], 1.0 / dir[1], 1.0 / dir[2]);
+        let mut hits: Vec<(f32, usize, f32, f32)> = Vec::new();
+        let mut stack = vec![0];
+
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            // once we have a hit, anything further away can't be the nearest
+            let limit = if nearest_only { hits.first().map_or(f32::MAX, |h| h.0) } else { f32::MAX };
+            match node.bounds().ray_range(orig, &inv_dir) {
+                Some((near, _)) if near < limit => {}
+                _ => continue,
+            }
+
+            match node {
+                BvhNode::Leaf { start, count, .. } => {
+                    for i in *start..start + count {
+                        if let Some((t, u, v)) = self.intersect_triangle(&self.triangles[i], orig, dir) {
+                            if !nearest_only {
+                                hits.push((t, i, u, v));
+                            } else if hits.first().is_none_or(|h| t < h.0) {
+                                hits = vec![(t, i, u, v)];
+                            }
+                        }
+                    }
+                }
+                BvhNode::Interior { left, right, .. } => {
+                    stack.push(*left);
+                    stack.push(*right);
+                }
+            }
+        }
+
+        hits
+    }
+
+    /// the nearest triangle the ray hits, if any
+    pub fn ray_intersect(&self, orig: &Vec3f, dir: &Vec3f) -> Option<SurfaceHit> {
+        let (distance, i, u, v) = *self.triangle_hits(orig, dir, true).first()?;
+        Some(self.surface_hit(&self.triangles[i], distance, u, v))
+    }
+
+    /// every triangle the ray hits, nearest first
+    pub fn crossings(&self, orig: &Vec3f, dir: &Vec3f) -> Vec<SurfaceHit> {
+        let mut hits = self.triangle_hits(orig, dir, false);
+        hits.sort_by(|a, b| a.0.total_cmp(&b.0));
+        hits.iter().map(|&(distance, i, u, v)| self.surface_hit(&self.triangles[i], distance, u, v)).collect()
+    }
+
+    fn surface_hit(&self, triangle: &Triangle, distance: f32, u: f32, v: f32) -> SurfaceHit {
+        let [a, b, c] = self.corners(triangle);
+        let edge1 = b - a;
+        let edge2 = c - a;
+        let weights = [1.0 - u - v, u, v];
+        let blend = |values: [&Vec3f; 3]| (0..3).fold(Vec3f::zero(), |sum, k| &sum + &(values[k] * weights[k]));
+
+        let normal = match triangle.normals {
+            Some(n) => blend([&self.normals[n[0]], &self.normals[n[1]], &self.normals[n[2]]]).normalize(),
+            None => edge1.cross(&edge2).normalize(),
+        };
+
+        let (uv, tangent) = match triangle.uvs {
+            Some(t) => {
+                let (uv0, uv1, uv2) = (&self.uvs[t[0]], &self.uvs[t[1]], &self.uvs[t[2]]);
+                let uv = Vec2f::from(&[uv0[0] * weights[0] + uv1[0] * weights[1] + uv2[0] * weights[2],
+                                       uv0[1] * weights[0] + uv1[1] * weights[1] + uv2[1] * weights[2]]);
+                // the direction in space that u grows along, from how u changes along the two edges
+                let (du1, dv1) = (uv1[0] - uv0[0], uv1[1] - uv0[1]);
+                let (du2, dv2) = (uv2[0] - uv0[0], uv2[1] - uv0[1]);
+                let det = du1 * dv2 - du2 * dv1;
+                let tangent = if det.abs() > 1e-12 {
+                    &(&(&edge1 * dv2) - &(&edge2 * dv1)) * (1.0 / det)
+                } else {
+                    edge1.clone()
+                };
+                (uv, tangent)
+            }
+            None => (Vec2f::from(&[u, v]), edge1.clone()),
+        };
+        // keep the tangent in the surface, smooth normals don't lie in the triangle's plane
+        let tangent = (&tangent - &(&normal * normal.dot(&tangent))).normalize();
+
+        SurfaceHit { distance, normal, uv, tangent }
+    }
+
+    pub fn area(&self) -> f32 {
+        *self.area_cdf.last().unwrap_or(&0.0)
+    }
+
+    /// a uniformly distributed point on the whole mesh and the normal there
+    pub fn sample_surface(&self, u: f32, v: f32) -> (Vec3f, Vec3f) {
+        // u picks the triangle, and what's left of it after that is still uniform within the triangle's share
+        let target = u * self.area();
+        let i = self.area_cdf.partition_point(|&total| total < target).min(self.triangles.len() - 1);
+        let before = if i == 0 { 0.0 } else { self.area_cdf[i - 1] };
+        let share = self.area_cdf[i] - before;
+        let u = if share > 0.0 { ((target - before) / share).clamp(0.0, 1.0) } else { 0.5 };
+
+        // folding the unit square in half onto the triangle
+        let (b1, b2) = if u + v > 1.0 { (1.0 - u, 1.0 - v) } else { (u, v) };
+        let [a, b, c] = self.corners(&self.triangles[i]);
+        let point = &(a + &(&(b - a) * b1)) + &(&(c - a) * b2);
+        let hit = self.surface_hit(&self.triangles[i], 0.0, b1, b2);
+
+        (point, hit.normal)
+    }
+}
+
+/// one placement of a shared mesh with its own material
+/// move it around by wrapping it in a Transformed, so a thousand trees are a thousand of these and a single mesh
+#[derive(Debug)]
+pub struct Instance {
+    mesh: Arc<Mesh>,
+    material: Arc<Material>,
+}
+
+impl Instance {
+    pub fn new(mesh: Arc<Mesh>, material: Arc<Material>) -> Self {
+        Instance { mesh, material }
+    }
+}
+
+impl Object for Instance {
+    fn ray_intersect(&self, orig: &Vec3f, dir: &Vec3f, _time: f32) -> Option<SurfaceHit> {
+        self.mesh.ray_intersect(orig, dir)
+    }
+
+    // only makes sense for closed meshes, where every way in has a way out
+    fn crossings(&self, orig: &Vec3f, dir: &Vec3f, _time: f32) -> Vec<SurfaceHit> {
+        self.mesh.crossings(orig, dir)
+    }
+
+    fn get_material(&self) -> &Material {
+        &self.material
+    }
+
+    fn area(&self) -> f32 {
+        self.mesh.area()
+    }
+
+    fn sample_surface(&self, u: f32, v: f32, _time: f32) -> Option<(Vec3f, Vec3f)> {
+        Some(self.mesh.sample_surface(u, v))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampling::Rng;
+
+    // a cloud of small triangles scattered through a box, enough for a few levels of bvh
+    fn scattered(count: usize, rng: &mut Rng) -> Mesh {
+        let mut random_point = |spread: f32| &Vec3f::new3f(rng.next_f32() - 0.5, rng.next_f32() - 0.5, rng.next_f32() - 0.5) * spread;
+        let mut positions = Vec::new();
+        for _ in 0..count {
+            let center = random_point(10.0);
+            for _ in 0..3 {
+                positions.push(&center + &random_point(1.0));
+            }
+        }
+        let triangles = (0..count).map(|i| Triangle { positions: [3 * i, 3 * i + 1, 3 * i + 2], normals: None, uvs: None }).collect();
+        Mesh::new(positions, Vec::new(), Vec::new(), triangles)
+    }
+
+    // the bvh should never lose a hit that checking every triangle would find
+    #[test]
+    fn bvh_matches_linear_scan() {
+        let mut rng = Rng::new(11, 0);
+        let mesh = scattered(500, &mut rng);
+        assert!(mesh.nodes.len() > 100, "only {} nodes", mesh.nodes.len());
+
+        let mut hit_count = 0;
+        for _ in 0..2000 {
+            let orig = &Vec3f::new3f(rng.next_f32() - 0.5, rng.next_f32() - 0.5, rng.next_f32() - 0.5) * 20.0;
+            // aimed somewhere into the cloud, so most of them go through it
+            let target = &Vec3f::new3f(rng.next_f32() - 0.5, rng.next_f32() - 0.5, rng.next_f32() - 0.5) * 8.0;
+            let dir = (&target - &orig).normalize();
+
+            let mut linear: Vec<f32> = mesh.triangles.iter().filter_map(|t| mesh.intersect_triangle(t, &orig, &dir)).map(|hit| hit.0).collect();
+            linear.sort_by(f32::total_cmp);
+            let crossings: Vec<f32> = mesh.crossings(&orig, &dir).iter().map(|hit| hit.distance).collect();
+            assert_eq!(crossings, linear, "from {:?} along {:?}", orig, dir);
+            assert_eq!(mesh.ray_intersect(&orig, &dir).map(|hit| hit.distance), linear.first().copied());
+            hit_count += linear.len();
+        }
+        assert!(hit_count > 500, "only {} hits", hit_count);
+    }
+
+    #[test]
+    fn obj_faces() {
+        let path = std::env::temp_dir().join(format!("tinyrt_{}_quad.obj", std::process::id()));
+        std::fs::write(&path, "# a unit square facing +z\nv 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3 -1\n").unwrap();
+        let mesh = Mesh::load_obj(&path.to_string_lossy());
+        std::fs::write(&path, "v 0 0 0\nv 1 0 0\nf 1 2 3\n").unwrap();
+        let out_of_range = Mesh::load_obj(&path.to_string_lossy());
+        std::fs::remove_file(&path).unwrap();
+
+        // split into two triangles
+        let mesh = mesh.unwrap();
+        assert_eq!(mesh.triangles.len(), 2);
+        assert!((mesh.area() - 1.0).abs() < 1e-6);
+        let hit = mesh.ray_intersect(&Vec3f::new3f(0.25, 0.75, 1.0), &Vec3f::new3f(0.0, 0.0, -1.0)).unwrap();
+        assert_eq!((hit.distance, hit.normal), (1.0, Vec3f::new3f(0.0, 0.0, 1.0)));
+
+        let err = out_of_range.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().ends_with(":3: index 3 out of range"), "{}", err);
+    }
+}
@@ -0,0 +1,111 @@
+// ken perlin's "improved noise" (2002), see https://mrl.cs.nyu.edu/~perlin/noise/
+// smooth pseudo-random values in [-1, 1] that vary over roughly a unit distance, the same for the same point every time
+
+use crate::geometry::Vec3f;
+
+// perlin's own permutation of 0..256, any shuffle would do but this keeps us matching the reference implementation
+static PERMUTATION: [u8; 256] = [
+    151, 160, 137, 91, 90, 15, 131, 13, 201, 95, 96, 53, 194, 233, 7, 225, 140, 36, 103, 30, 69, 142, 8, 99, 37, 240, 21, 10,
+    23, 190, 6, 148, 247, 120, 234, 75, 0, 26, 197, 62, 94, 252, 219, 203, 117, 35, 11, 32, 57, 177, 33, 88, 237, 149, 56, 87,
+    174, 20, 125, 136, 171, 168, 68, 175, 74, 165, 71, 134, 139, 48, 27, 166, 77, 146, 158, 231, 83, 111, 229, 122, 60, 211,
+    133, 230, 220, 105, 92, 41, 55, 46, 245, 40, 244, 102, 143, 54, 65, 25, 63, 161, 1, 216, 80, 73, 209, 76, 132, 187, 208,
+    89, 18, 169, 200, 196, 135, 130, 116, 188, 159, 86, 164, 100, 109, 198, 173, 186, 3, 64, 52, 217, 226, 250, 124, 123, 5,
+    202, 38, 147, 118, 126, 255, 82, 85, 212, 207, 206, 59, 227, 47, 16, 58, 17, 182, 189, 28, 42, 223, 183, 170, 213, 119,
+    248, 152, 2, 44, 154, 163, 70, 221, 153, 101, 155, 167, 43, 172, 9, 129, 22, 39, 253, 19, 98, 108, 110, 79, 113, 224, 232,
+    178, 185, 112, 104, 218, 246, 97, 228, 251, 34, 242, 193, 238, 210, 144, 12, 191, 179, 162, 241, 81, 51, 145, 235, 249, 14,
+    239, 107, 49, 192, 214, 31, 181, 199, 106, 157, 184, 84, 204, 176, 115, 121, 50, 45, 127, 4, 150, 254, 138, 236, 205, 93,
+    222, 114, 67, 29, 24, 72, 243, 141, 128, 195, 78, 66, 215, 61, 156, 180,
+];
+
+fn perm(i: usize) -> usize {
+    PERMUTATION[i & 255] as usize
+}
+
+// 6t^5 - 15t^4 + 10t^3, eases in and out so the noise has continuous second derivatives across cell borders
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(t: f32, a: f32, b: f32) -> f32 {
+    a + t * (b - a)
+}
+
+// dot product of (x, y, z) with one of 12 gradient directions picked by the hash
+fn grad(hash: usize, x: f32, y: f32, z: f32) -> f32 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 { y } else if h == 12 || h == 14 { x } else { z };
+
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+/// perlin noise at point p, in [-1, 1]
+pub fn perlin(p: &Vec3f) -> f32 {
+    let (fx, fy, fz) = (p[0].floor(), p[1].floor(), p[2].floor());
+    // wrapping into 0..256 is fine, the pattern repeats every 256 units anyway
+    let (xi, yi, zi) = ((fx as i64 & 255) as usize, (fy as i64 & 255) as usize, (fz as i64 & 255) as usize);
+    let (x, y, z) = (p[0] - fx, p[1] - fy, p[2] - fz);
+    let (u, v, w) = (fade(x), fade(y), fade(z));
+
+    // hash the 8 corners of the unit cube around p
+    let a = perm(xi) + yi;
+    let aa = perm(a) + zi;
+    let ab = perm(a + 1) + zi;
+    let b = perm(xi + 1) + yi;
+    let ba = perm(b) + zi;
+    let bb = perm(b + 1) + zi;
+
+    lerp(w,
+         lerp(v,
+              lerp(u, grad(perm(aa), x, y, z), grad(perm(ba), x - 1.0, y, z)),
+              lerp(u, grad(perm(ab), x, y - 1.0, z), grad(perm(bb), x - 1.0, y - 1.0, z))),
+         lerp(v,
+              lerp(u, grad(perm(aa + 1), x, y, z - 1.0), grad(perm(ba + 1), x - 1.0, y, z - 1.0)),
+              lerp(u, grad(perm(ab + 1), x, y - 1.0, z - 1.0), grad(perm(bb + 1), x - 1.0, y - 1.0, z - 1.0))))
+}
+
+/// sum of octaves of the absolute value of noise, each twice the frequency and half the amplitude of the last
+/// gives the billowy, creased look of smoke and marble veins, roughly in [0, 1]
+pub fn turbulence(p: &Vec3f, octaves: u32) -> f32 {
+    let mut sum = 0.0;
+    let mut frequency = 1.0;
+    let mut amplitude = 1.0;
+    let mut total_amplitude = 0.0;
+
+    for _ in 0..octaves.max(1) {
+        sum += perlin(&(p * frequency)).abs() * amplitude;
+        total_amplitude += amplitude;
+        frequency *= 2.0;
+        amplitude *= 0.5;
+    }
+
+    sum / total_amplitude
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // what perlin's java reference gives, at the point from his own example
+    #[test]
+    #[allow(clippy::approx_constant)]
+    fn reference_value() {
+        let value = perlin(&Vec3f::new3f(3.14, 42.0, 7.0));
+        assert!((value - 0.136_919_96).abs() < 1e-5, "{}", value);
+    }
+
+    #[test]
+    fn smooth_and_in_range() {
+        for i in 0..1000 {
+            let p = Vec3f::new3f(i as f32 * 0.137 - 60.0, (i % 37) as f32 * 0.71, (i % 11) as f32 * -1.3);
+            let value = perlin(&p);
+            assert!((-1.0..=1.0).contains(&value), "{} at {:?}", value, p);
+            // nearby points have nearby values
+            assert!((perlin(&(&p + &Vec3f::new3f(0.001, 0.001, 0.001))) - value).abs() < 0.01, "jumps at {:?}", p);
+            assert!((0.0..=1.0).contains(&turbulence(&p, 4)), "{:?}", p);
+        }
+
+        // the lattice points are all zero
+        assert_eq!(perlin(&Vec3f::new3f(3.0, -7.0, 12.0)), 0.0);
+    }
+}
@@ -1,36 +1,98 @@
-use crate::geometry::Vec3f;
+use std::cell::OnceCell;
+use std::f32::consts::PI;
+use crate::geometry::{Mat4, Placement, Vec2f, Vec3f};
 use crate::material::Material;
+use crate::sampling::{self, Rng};
+
+// how many points Transformed::sample_surface tries before giving up on finding one
+static MAX_TRIES: u32 = 64;
+// how many points on an unevenly stretched object Transformed::area looks at, to tell how much it grew
+static AREA_SAMPLES: u32 = 4096;
+
+/// where a ray first meets an object's surface
+#[derive(Debug, Clone)]
+pub struct SurfaceHit {
+    // distance along the ray
+    pub distance: f32,
+    // outward facing unit normal at the hit point
+    pub normal: Vec3f,
+    // surface coordinates for texturing, each in [0, 1], v running upwards
+    pub uv: Vec2f,
+    // unit vector along the surface in the direction u increases, perpendicular to normal
+    // normal x tangent is then the direction v increases, which orients normal and bump maps
+    pub tangent: Vec3f,
+}
 
 pub trait Object {
-    // returns tuple (b, dist)
-    // b is true if ray from orig in direction of vector dir intersects with this object
-    // dist is the distance along the ray that the first intersection occurs
-    fn ray_intersect(&self, orig: &Vec3f, dir: &Vec3f) -> (bool, f32);
+    // None if the ray from orig in direction of vector dir misses this object
+    // otherwise the first intersection in front of orig
+    // time is when the ray is cast, which only matters to objects that move while the shutter is open
+    fn ray_intersect(&self, orig: &Vec3f, dir: &Vec3f, time: f32) -> Option<SurfaceHit>;
 
-    fn get_position(&self) -> &Vec3f;
+    // every time the ray crosses the surface in front of orig, nearest first
+    // a crossing enters the object if its normal faces against the ray and leaves it otherwise, which is what Csg needs
+    // only closed objects can do that, so the rest just give their nearest hit
+    fn crossings(&self, orig: &Vec3f, dir: &Vec3f, time: f32) -> Vec<SurfaceHit> {
+        self.ray_intersect(orig, dir, time).into_iter().collect()
+    }
 
     fn get_material(&self) -> &Material;
+
+    // total surface area, needed to turn a point sampled on the surface into a probability density
+    fn area(&self) -> f32;
+
+    // returns tuple (point, normal)
+    // u and v are uniform random numbers in [0, 1), the resulting points must be spread uniformly over the surface
+    // this is what lets any object with an emissive material be sampled as a light source
+    // objects that have to search for their points give None if they didn't find one, which makes the sample count for nothing
+    fn sample_surface(&self, u: f32, v: f32, time: f32) -> Option<(Vec3f, Vec3f)>;
 }
 
 #[derive(Debug)]
 pub struct Sphere {
+    // where it is at time 0
     center: Vec3f,
+    // how far it moves from there by time 1, at a constant speed
+    motion: Vec3f,
     radius: f32,
     material: Material,
 }
 
 impl Sphere {
     pub fn new(c: Vec3f, r: f32, material: &Material) -> Self {
-        Sphere { center: c, radius: r, material: material.clone() }
+        Sphere { center: c, motion: Vec3f::zero(), radius: r, material: material.clone() }
+    }
+
+    /// a sphere at c at time 0 and at end by time 1
+    pub fn moving(c: Vec3f, end: &Vec3f, r: f32, material: &Material) -> Self {
+        let motion = end - &c;
+        Sphere { center: c, motion, radius: r, material: material.clone() }
+    }
+
+    fn center_at(&self, time: f32) -> Vec3f {
+        &self.center + &(&self.motion * time)
+    }
+
+    fn hit_at(&self, center: &Vec3f, orig: &Vec3f, dir: &Vec3f, t0: f32) -> SurfaceHit {
+        let normal = (&(orig + &(dir * t0)) - center).normalize();
+        // longitude and latitude, with the seam facing away from the camera
+        let uv = Vec2f::from(&[0.5 + normal[0].atan2(normal[2]) / (2.0 * PI),
+                               0.5 + normal[1].asin() / PI]);
+        // east along the line of latitude, which is undefined right at the poles so pick anything there
+        let east = Vec3f::new3f(normal[2], 0.0, -normal[0]);
+        let tangent = if east.magnitude() > 1e-6 { east.normalize() } else { sampling::basis(&normal).0 };
+
+        SurfaceHit { distance: t0, normal, uv, tangent }
     }
 }
 
 impl Object for Sphere {
-    fn ray_intersect(&self, orig: &Vec3f, dir: &Vec3f) -> (bool, f32) {
-        let l = &self.center - orig;
+    fn ray_intersect(&self, orig: &Vec3f, dir: &Vec3f, time: f32) -> Option<SurfaceHit> {
+        let center = self.center_at(time);
+        let l = &center - orig;
         let tca = &l.dot(dir);
         let d2 = l.dot(&l) - (tca * tca);
-        if d2 > (self.radius * self.radius) { return (false, 0f32); }
+        if d2 > (self.radius * self.radius) { return None; }
 
         let thc = (self.radius * self.radius - d2).sqrt();
 
@@ -38,15 +100,896 @@ impl Object for Sphere {
         let t1 = tca + thc;
 
         if t0 < 0.0 { t0 = t1; }
+        if t0 < 0.0 { return None; }
+
+        Some(self.hit_at(&center, orig, dir, t0))
+    }
+
+    fn crossings(&self, orig: &Vec3f, dir: &Vec3f, time: f32) -> Vec<SurfaceHit> {
+        let center = self.center_at(time);
+        let l = &center - orig;
+        let tca = l.dot(dir);
+        let d2 = l.dot(&l) - (tca * tca);
+        if d2 > (self.radius * self.radius) { return Vec::new(); }
+
+        let thc = (self.radius * self.radius - d2).sqrt();
+        [tca - thc, tca + thc].iter()
+            .filter(|&&t| t > 0.0)
+            .map(|&t| self.hit_at(&center, orig, dir, t))
+            .collect()
+    }
+
+    fn get_material(&self) -> &Material {
+        &self.material
+    }
+
+    fn area(&self) -> f32 {
+        4.0 * PI * self.radius * self.radius
+    }
+
+    fn sample_surface(&self, u: f32, v: f32, time: f32) -> Option<(Vec3f, Vec3f)> {
+        // uniform z slices of a sphere have equal area (archimedes' hat-box theorem)
+        let z = 1.0 - 2.0 * u;
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * PI * v;
+        let normal = Vec3f::new3f(r * phi.cos(), r * phi.sin(), z);
+
+        Some((&self.center_at(time) + &(&normal * self.radius), normal))
+    }
+}
+
+/// any object moved, rotated and stretched by an affine transform
+/// rays are taken into the object's own space to be intersected there, and the hit brought back out
+/// so a sphere scaled unevenly becomes an ellipsoid without Sphere knowing anything about it
+#[derive(Debug)]
+pub struct Transformed<O: Object> {
+    object: O,
+    to_world: Mat4,
+    to_object: Mat4,
+    // where it is at time 0 and time 1, for objects that move
+    motion: Option<(Placement, Placement)>,
+    // worked out the first time it's asked for, since only glowing objects need it
+    area: OnceCell<f32>,
+}
+
+impl<O: Object> Transformed<O> {
+    // None if the transform can't be undone, which would leave the object flattened to nothing anyway
+    pub fn new(object: O, transform: Mat4) -> Option<Self> {
+        let to_object = transform.inverse()?;
+        Some(Transformed { object, to_world: transform, to_object, motion: None, area: OnceCell::new() })
+    }
+
+    /// an object going from start at time 0 to end at time 1
+    pub fn moving(object: O, start: Placement, end: Placement) -> Option<Self> {
+        let mut transformed = Transformed::new(object, start.matrix())?;
+        transformed.motion = Some((start, end));
+        Some(transformed)
+    }
+
+    // to_world and to_object at the given time
+    // None if the object happens to be squashed flat right then, like a scale going from 1 to -1 passing through 0
+    fn frame(&self, time: f32) -> Option<(Mat4, Mat4)> {
+        match &self.motion {
+            None => Some((self.to_world, self.to_object)),
+            Some((start, end)) => {
+                let to_world = start.lerp(end, time).matrix();
+                Some((to_world, to_world.inverse()?))
+            }
+        }
+    }
+}
+
+// normals stay perpendicular to the surface by going through the inverse transpose instead of the transform itself
+fn normal_to_world(to_object: &Mat4, normal: &Vec3f) -> Vec3f {
+    to_object.transpose().transform_vector(normal).normalize()
+}
+
+// how many times bigger a bit of surface with the given (unit, object space) normal comes out in world space
+// the volume it's scaled by, over how much the direction along the normal got stretched
+fn surface_stretch(to_world: &Mat4, to_object: &Mat4, normal: &Vec3f) -> f32 {
+    to_world.determinant().abs() * to_object.transpose().transform_vector(normal).magnitude()
+}
+
+// whether the transform grows every bit of surface by the same amount, like rotations and uniform scales do
+fn stretches_evenly(to_object: &Mat4) -> bool {
+    let (least, most) = to_object.stretch_range();
+    most - least <= 1e-4 * most
+}
+
+// objects expect unit directions, and distances along the stretched direction come out stretched too
+fn ray_to_object(to_object: &Mat4, orig: &Vec3f, dir: &Vec3f) -> (Vec3f, Vec3f, f32) {
+    let local_dir = to_object.transform_vector(dir);
+    let stretch = local_dir.magnitude();
+    (to_object.transform_point(orig), local_dir.normalize(), stretch)
+}
+
+fn hit_to_world(to_world: &Mat4, to_object: &Mat4, hit: SurfaceHit, stretch: f32) -> SurfaceHit {
+    SurfaceHit {
+        distance: hit.distance / stretch,
+        normal: normal_to_world(to_object, &hit.normal),
+        uv: hit.uv,
+        tangent: to_world.transform_vector(&hit.tangent).normalize(),
+    }
+}
+
+impl<O: Object> Object for Transformed<O> {
+    fn ray_intersect(&self, orig: &Vec3f, dir: &Vec3f, time: f32) -> Option<SurfaceHit> {
+        let (to_world, to_object) = self.frame(time)?;
+        let (local_orig, local_dir, stretch) = ray_to_object(&to_object, orig, dir);
+        let hit = self.object.ray_intersect(&local_orig, &local_dir, time)?;
+        Some(hit_to_world(&to_world, &to_object, hit, stretch))
+    }
+
+    fn crossings(&self, orig: &Vec3f, dir: &Vec3f, time: f32) -> Vec<SurfaceHit> {
+        let (to_world, to_object) = match self.frame(time) {
+            Some(frame) => frame,
+            None => return Vec::new(),
+        };
+        let (local_orig, local_dir, stretch) = ray_to_object(&to_object, orig, dir);
+        self.object.crossings(&local_orig, &local_dir, time).into_iter()
+            .map(|hit| hit_to_world(&to_world, &to_object, hit, stretch))
+            .collect()
+    }
+
+    fn get_material(&self) -> &Material {
+        self.object.get_material()
+    }
+
+    // the object's area times how much the transform grows its surface, on average over it unless that's the same everywhere
+    // for moving objects it's the area at time 0
+    fn area(&self) -> f32 {
+        *self.area.get_or_init(|| {
+            if stretches_evenly(&self.to_object) {
+                return self.object.area() * self.to_world.determinant().abs() * self.to_object.stretch_range().1;
+            }
+            let stretches: Vec<f32> = (0..AREA_SAMPLES)
+                .filter_map(|i| self.object.sample_surface(sampling::halton(i, 0), sampling::halton(i, 1), 0.0))
+                .map(|(_, normal)| surface_stretch(&self.to_world, &self.to_object, &normal))
+                .collect();
+            self.object.area() * stretches.iter().sum::<f32>() / stretches.len().max(1) as f32
+        })
+    }
+
+    // an uneven stretch leaves the object's evenly spread points bunched up where its surface grew the least
+    // so they're thrown back in proportion to that, leaving the rest spread evenly over the stretched surface
+    fn sample_surface(&self, u: f32, v: f32, time: f32) -> Option<(Vec3f, Vec3f)> {
+        let (to_world, to_object) = self.frame(time).unwrap_or((self.to_world, self.to_object));
+        if stretches_evenly(&to_object) {
+            let (point, normal) = self.object.sample_surface(u, v, time)?;
+            return Some((to_world.transform_point(&point), normal_to_world(&to_object, &normal)));
+        }
+
+        let most = to_world.determinant().abs() * to_object.stretch_range().1;
+        // after the first try, the random numbers come from a hash of the ones given
+        let mut rng = Rng::new(sampling::hash(&[u.to_bits() as u64, v.to_bits() as u64]), 0);
+        let (mut u, mut v) = (u, v);
+        for _ in 0..MAX_TRIES {
+            if let Some((point, normal)) = self.object.sample_surface(u, v, time) {
+                if rng.next_f32() * most < surface_stretch(&to_world, &to_object, &normal) {
+                    return Some((to_world.transform_point(&point), normal_to_world(&to_object, &normal)));
+                }
+            }
+            u = rng.next_f32();
+            v = rng.next_f32();
+        }
+
+        None
+    }
+}
+
+// u going once around the y axis like on the sphere, with the tangent pointing the way u increases
+fn around_y(offset: &Vec3f) -> (f32, Vec3f) {
+    let u = 0.5 + offset[0].atan2(offset[2]) / (2.0 * PI);
+    let east = Vec3f::new3f(offset[2], 0.0, -offset[0]);
+    let tangent = if east.magnitude() > 1e-6 { east.normalize() } else { Vec3f::new3f(1.0, 0.0, 0.0) };
+    (u, tangent)
+}
+
+// flat circular cap at height y of a shape standing along the y axis, local being relative to the middle of its base
+// uvs are laid over the cap like on the floor, top caps looking like it from above and bottom ones from below
+fn cap_crossing(local_orig: &Vec3f, dir: &Vec3f, y: f32, radius: f32, facing_up: bool) -> Option<SurfaceHit> {
+    if dir[1].abs() < 1e-9 {
+        return None;
+    }
+    let t = (y - local_orig[1]) / dir[1];
+    let p = local_orig + &(dir * t);
+    if p[0] * p[0] + p[2] * p[2] > radius * radius {
+        return None;
+    }
+
+    let v_sign = if facing_up { -1.0 } else { 1.0 };
+    Some(SurfaceHit {
+        distance: t,
+        normal: Vec3f::new3f(0.0, if facing_up { 1.0 } else { -1.0 }, 0.0),
+        uv: Vec2f::from(&[0.5 + p[0] / (2.0 * radius), 0.5 + v_sign * p[2] / (2.0 * radius)]),
+        tangent: Vec3f::new3f(1.0, 0.0, 0.0),
+    })
+}
+
+// just the crossings in front of the ray's origin, nearest first
+fn in_front(mut crossings: Vec<SurfaceHit>) -> Vec<SurfaceHit> {
+    crossings.retain(|hit| hit.distance > 0.0);
+    crossings.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+    crossings
+}
 
-        (t0 >= 0.0, t0)
+/// box with faces along the axes, wrap it in a Transformed to turn it
+/// each face gets the whole uv square
+#[derive(Debug)]
+pub struct Cuboid {
+    min: Vec3f,
+    max: Vec3f,
+    material: Material,
+}
+
+impl Cuboid {
+    // corners can be given in any order
+    pub fn new(a: &Vec3f, b: &Vec3f, material: &Material) -> Self {
+        let min = Vec3f::new3f(a[0].min(b[0]), a[1].min(b[1]), a[2].min(b[2]));
+        let max = Vec3f::new3f(a[0].max(b[0]), a[1].max(b[1]), a[2].max(b[2]));
+        Cuboid { min, max, material: material.clone() }
     }
 
-    fn get_position(&self) -> &Vec3f {
-        &self.center
+    // face with its normal along axis, on the max side if positive
+    // tangents are picked so that normal x tangent always has v running up the sides, and away from the camera on top
+    fn face_hit(&self, distance: f32, point: &Vec3f, axis: i32, positive: bool) -> SurfaceHit {
+        let sign = if positive { 1.0 } else { -1.0 };
+        let mut normal = Vec3f::zero();
+        normal[axis] = sign;
+        let tangent = match axis {
+            0 => Vec3f::new3f(0.0, 0.0, -sign),
+            1 => Vec3f::new3f(1.0, 0.0, 0.0),
+            _ => Vec3f::new3f(sign, 0.0, 0.0),
+        };
+        let bitangent = normal.cross(&tangent);
+
+        let size = &self.max - &self.min;
+        let offset = point - &(&(&self.min + &self.max) * 0.5);
+        let extent = |direction: &Vec3f| (0..3).map(|i| (direction[i] * size[i]).abs()).sum::<f32>();
+        let uv = Vec2f::from(&[0.5 + offset.dot(&tangent) / extent(&tangent), 0.5 + offset.dot(&bitangent) / extent(&bitangent)]);
+
+        SurfaceHit { distance, normal, uv, tangent }
+    }
+}
+
+impl Object for Cuboid {
+    fn ray_intersect(&self, orig: &Vec3f, dir: &Vec3f, time: f32) -> Option<SurfaceHit> {
+        self.crossings(orig, dir, time).into_iter().next()
+    }
+
+    fn crossings(&self, orig: &Vec3f, dir: &Vec3f, _time: f32) -> Vec<SurfaceHit> {
+        // slab test, remembering which slab the ray enters and leaves last and first
+        let (mut near, mut far) = (f32::MIN, f32::MAX);
+        let (mut near_axis, mut far_axis) = (0, 0);
+        for i in 0..3 {
+            if dir[i].abs() < 1e-9 {
+                if orig[i] < self.min[i] || orig[i] > self.max[i] {
+                    return Vec::new();
+                }
+                continue;
+            }
+            let t0 = (self.min[i] - orig[i]) / dir[i];
+            let t1 = (self.max[i] - orig[i]) / dir[i];
+            if t0.min(t1) > near {
+                near = t0.min(t1);
+                near_axis = i;
+            }
+            if t0.max(t1) < far {
+                far = t0.max(t1);
+                far_axis = i;
+            }
+        }
+        if near > far {
+            return Vec::new();
+        }
+
+        // coming in through the face looking against the ray, leaving through the one looking along it
+        in_front(vec![self.face_hit(near, &(orig + &(dir * near)), near_axis, dir[near_axis] < 0.0),
+                      self.face_hit(far, &(orig + &(dir * far)), far_axis, dir[far_axis] > 0.0)])
     }
 
     fn get_material(&self) -> &Material {
         &self.material
     }
+
+    fn area(&self) -> f32 {
+        let size = &self.max - &self.min;
+        2.0 * (size[0] * size[1] + size[1] * size[2] + size[2] * size[0])
+    }
+
+    fn sample_surface(&self, u: f32, v: f32, _time: f32) -> Option<(Vec3f, Vec3f)> {
+        // u picks one of the six faces by its area first, what's left of it is the position across the face
+        let size = &self.max - &self.min;
+        let face_areas = [size[1] * size[2], size[2] * size[0], size[0] * size[1]];
+        let mut target = u * self.area();
+        let mut face = 5;
+        for i in 0..6 {
+            let face_area = face_areas[i / 2];
+            if target < face_area || i == 5 {
+                face = i;
+                break;
+            }
+            target -= face_area;
+        }
+        let axis = (face / 2) as i32;
+        let s = (target / face_areas[face / 2]).clamp(0.0, 1.0);
+
+        let (a, b) = ((axis + 1) % 3, (axis + 2) % 3);
+        let mut point = Vec3f::zero();
+        point[axis] = if face % 2 == 0 { self.min[axis] } else { self.max[axis] };
+        point[a] = self.min[a] + s * size[a];
+        point[b] = self.min[b] + v * size[b];
+        let mut normal = Vec3f::zero();
+        normal[axis] = if face % 2 == 0 { -1.0 } else { 1.0 };
+
+        Some((point, normal))
+    }
+}
+
+/// cylinder standing on the middle of its base, with flat caps at both ends
+/// turn it with a Transformed to point it elsewhere
+#[derive(Debug)]
+pub struct Cylinder {
+    base: Vec3f,
+    radius: f32,
+    height: f32,
+    material: Material,
+}
+
+impl Cylinder {
+    pub fn new(base: Vec3f, radius: f32, height: f32, material: &Material) -> Self {
+        Cylinder { base, radius, height, material: material.clone() }
+    }
+}
+
+impl Object for Cylinder {
+    fn ray_intersect(&self, orig: &Vec3f, dir: &Vec3f, time: f32) -> Option<SurfaceHit> {
+        self.crossings(orig, dir, time).into_iter().next()
+    }
+
+    fn crossings(&self, orig: &Vec3f, dir: &Vec3f, _time: f32) -> Vec<SurfaceHit> {
+        let o = orig - &self.base;
+        let mut crossings = Vec::new();
+
+        // the side, an infinite tube cut off at both ends
+        let a = dir[0] * dir[0] + dir[2] * dir[2];
+        let b = 2.0 * (o[0] * dir[0] + o[2] * dir[2]);
+        let c = o[0] * o[0] + o[2] * o[2] - self.radius * self.radius;
+        let discriminant = b * b - 4.0 * a * c;
+        if a > 1e-9 && discriminant >= 0.0 {
+            for t in [(-b - discriminant.sqrt()) / (2.0 * a), (-b + discriminant.sqrt()) / (2.0 * a)] {
+                let p = &o + &(dir * t);
+                if p[1] >= 0.0 && p[1] <= self.height {
+                    let (u, tangent) = around_y(&p);
+                    let normal = Vec3f::new3f(p[0], 0.0, p[2]).normalize();
+                    crossings.push(SurfaceHit { distance: t, normal, uv: Vec2f::from(&[u, p[1] / self.height]), tangent });
+                }
+            }
+        }
+
+        crossings.extend(cap_crossing(&o, dir, 0.0, self.radius, false));
+        crossings.extend(cap_crossing(&o, dir, self.height, self.radius, true));
+        in_front(crossings)
+    }
+
+    fn get_material(&self) -> &Material {
+        &self.material
+    }
+
+    fn area(&self) -> f32 {
+        2.0 * PI * self.radius * (self.radius + self.height)
+    }
+
+    fn sample_surface(&self, u: f32, v: f32, _time: f32) -> Option<(Vec3f, Vec3f)> {
+        let cap_area = PI * self.radius * self.radius;
+        let side_area = 2.0 * PI * self.radius * self.height;
+        let target = u * self.area();
+        let phi = 2.0 * PI * v;
+
+        if target < side_area {
+            let y = target / side_area * self.height;
+            let normal = Vec3f::new3f(phi.sin(), 0.0, phi.cos());
+            Some((&self.base + &Vec3f::new3f(normal[0] * self.radius, y, normal[2] * self.radius), normal))
+        } else {
+            // either cap, uniformly over the disk
+            let s = (target - side_area) / cap_area;
+            let top = s >= 1.0;
+            let r = self.radius * (s - s.floor()).sqrt();
+            let y = if top { self.height } else { 0.0 };
+            Some((&self.base + &Vec3f::new3f(r * phi.sin(), y, r * phi.cos()), Vec3f::new3f(0.0, if top { 1.0 } else { -1.0 }, 0.0)))
+        }
+    }
+}
+
+/// cone standing on the middle of its base, narrowing to a point height above it
+#[derive(Debug)]
+pub struct Cone {
+    base: Vec3f,
+    radius: f32,
+    height: f32,
+    material: Material,
+}
+
+impl Cone {
+    pub fn new(base: Vec3f, radius: f32, height: f32, material: &Material) -> Self {
+        Cone { base, radius, height, material: material.clone() }
+    }
+}
+
+impl Object for Cone {
+    fn ray_intersect(&self, orig: &Vec3f, dir: &Vec3f, time: f32) -> Option<SurfaceHit> {
+        self.crossings(orig, dir, time).into_iter().next()
+    }
+
+    fn crossings(&self, orig: &Vec3f, dir: &Vec3f, _time: f32) -> Vec<SurfaceHit> {
+        let o = orig - &self.base;
+        let mut crossings = Vec::new();
+
+        // x^2 + z^2 = (k * distance below the apex)^2, both halves of the double cone, keeping the one below the apex
+        let k = self.radius / self.height;
+        let below_apex = self.height - o[1];
+        let a = dir[0] * dir[0] + dir[2] * dir[2] - k * k * dir[1] * dir[1];
+        let b = 2.0 * (o[0] * dir[0] + o[2] * dir[2] + k * k * below_apex * dir[1]);
+        let c = o[0] * o[0] + o[2] * o[2] - k * k * below_apex * below_apex;
+        let roots = if a.abs() < 1e-9 {
+            // parallel to the side, so it only crosses the cone once
+            if b.abs() < 1e-9 { Vec::new() } else { vec![-c / b] }
+        } else {
+            let discriminant = b * b - 4.0 * a * c;
+            if discriminant < 0.0 { Vec::new() } else { vec![(-b - discriminant.sqrt()) / (2.0 * a), (-b + discriminant.sqrt()) / (2.0 * a)] }
+        };
+
+        for t in roots {
+            let p = &o + &(dir * t);
+            if p[1] >= 0.0 && p[1] <= self.height {
+                let (u, tangent) = around_y(&p);
+                let rho = (p[0] * p[0] + p[2] * p[2]).sqrt();
+                // the gradient of the cone's equation, tilted up by the slope of the side
+                let normal = if rho > 1e-6 { Vec3f::new3f(p[0], k * rho, p[2]).normalize() } else { Vec3f::new3f(0.0, 1.0, 0.0) };
+                crossings.push(SurfaceHit { distance: t, normal, uv: Vec2f::from(&[u, p[1] / self.height]), tangent });
+            }
+        }
+
+        crossings.extend(cap_crossing(&o, dir, 0.0, self.radius, false));
+        in_front(crossings)
+    }
+
+    fn get_material(&self) -> &Material {
+        &self.material
+    }
+
+    fn area(&self) -> f32 {
+        let slant = (self.radius * self.radius + self.height * self.height).sqrt();
+        PI * self.radius * (self.radius + slant)
+    }
+
+    fn sample_surface(&self, u: f32, v: f32, _time: f32) -> Option<(Vec3f, Vec3f)> {
+        let cap_area = PI * self.radius * self.radius;
+        let side_area = self.area() - cap_area;
+        let target = u * self.area();
+        let phi = 2.0 * PI * v;
+
+        if target < side_area {
+            // the side widens linearly, so there's more of it further from the apex
+            let fraction = (target / side_area).sqrt();
+            let r = self.radius * fraction;
+            let k = self.radius / self.height;
+            let normal = Vec3f::new3f(phi.sin(), k, phi.cos()).normalize();
+            Some((&self.base + &Vec3f::new3f(r * phi.sin(), self.height * (1.0 - fraction), r * phi.cos()), normal))
+        } else {
+            let r = self.radius * ((target - side_area) / cap_area).clamp(0.0, 1.0).sqrt();
+            Some((&self.base + &Vec3f::new3f(r * phi.sin(), 0.0, r * phi.cos()), Vec3f::new3f(0.0, -1.0, 0.0)))
+        }
+    }
+}
+
+/// flat round disk facing along normal, visible from both sides
+/// hits report the normal on whichever side the ray came from, since it has no inside
+#[derive(Debug)]
+pub struct Disk {
+    center: Vec3f,
+    normal: Vec3f,
+    radius: f32,
+    material: Material,
+}
+
+impl Disk {
+    pub fn new(center: Vec3f, normal: &Vec3f, radius: f32, material: &Material) -> Self {
+        Disk { center, normal: normal.normalize(), radius, material: material.clone() }
+    }
+}
+
+impl Object for Disk {
+    fn ray_intersect(&self, orig: &Vec3f, dir: &Vec3f, _time: f32) -> Option<SurfaceHit> {
+        let facing = dir.dot(&self.normal);
+        if facing.abs() < 1e-9 {
+            return None;
+        }
+        let t = (&self.center - orig).dot(&self.normal) / facing;
+        let offset = &(orig + &(dir * t)) - &self.center;
+        if t <= 0.0 || offset.dot(&offset) > self.radius * self.radius {
+            return None;
+        }
+
+        // laid flat over the disk like a sticker
+        let (tangent, bitangent) = {
+            let (t, _) = sampling::basis(&self.normal);
+            let b = self.normal.cross(&t);
+            (t, b)
+        };
+        let uv = Vec2f::from(&[0.5 + offset.dot(&tangent) / (2.0 * self.radius), 0.5 + offset.dot(&bitangent) / (2.0 * self.radius)]);
+        let normal = if facing > 0.0 { &Vec3f::zero() - &self.normal } else { self.normal.clone() };
+
+        Some(SurfaceHit { distance: t, normal, uv, tangent })
+    }
+
+    fn get_material(&self) -> &Material {
+        &self.material
+    }
+
+    // both sides, as rays hit (and glowing disks light) either one
+    fn area(&self) -> f32 {
+        2.0 * PI * self.radius * self.radius
+    }
+
+    // u picks a side first, what's left of it is the distance out from the center
+    fn sample_surface(&self, u: f32, v: f32, _time: f32) -> Option<(Vec3f, Vec3f)> {
+        let (tangent, bitangent) = sampling::basis(&self.normal);
+        let (u, normal) = if u < 0.5 { (2.0 * u, self.normal.clone()) } else { (2.0 * u - 1.0, &Vec3f::zero() - &self.normal) };
+        let r = self.radius * u.sqrt();
+        let phi = 2.0 * PI * v;
+        let offset = &(&tangent * (r * phi.cos())) + &(&bitangent * (r * phi.sin()));
+        Some((&self.center + &offset, normal))
+    }
+}
+
+/// ring doughnut lying flat around the y axis, major being the distance from its center to the middle of the tube
+/// u runs around the ring, v around the tube
+#[derive(Debug)]
+pub struct Torus {
+    center: Vec3f,
+    major: f32,
+    minor: f32,
+    material: Material,
+}
+
+impl Torus {
+    pub fn new(center: Vec3f, major: f32, minor: f32, material: &Material) -> Self {
+        Torus { center, major, minor, material: material.clone() }
+    }
+
+    fn hit_at(&self, distance: f32, p: &Vec3f) -> SurfaceHit {
+        let (u, tangent) = around_y(p);
+        let rho = (p[0] * p[0] + p[2] * p[2]).sqrt();
+        let ring = if rho > 1e-6 { Vec3f::new3f(p[0] / rho, 0.0, p[2] / rho) } else { Vec3f::new3f(1.0, 0.0, 0.0) };
+        // away from the nearest point on the circle through the middle of the tube
+        let normal = (p - &(&ring * self.major)).normalize();
+        let v = 0.5 + p[1].atan2(rho - self.major) / (2.0 * PI);
+
+        SurfaceHit { distance, normal, uv: Vec2f::from(&[u, v]), tangent }
+    }
+}
+
+impl Object for Torus {
+    fn ray_intersect(&self, orig: &Vec3f, dir: &Vec3f, time: f32) -> Option<SurfaceHit> {
+        self.crossings(orig, dir, time).into_iter().next()
+    }
+
+    fn crossings(&self, orig: &Vec3f, dir: &Vec3f, _time: f32) -> Vec<SurfaceHit> {
+        // the quartic gets badly conditioned far away, so start from where the ray meets the bounding sphere
+        let o = orig - &self.center;
+        let bound = self.major + self.minor;
+        let tca = -o.dot(dir);
+        let d2 = o.dot(&o) - tca * tca;
+        if d2 > bound * bound {
+            return Vec::new();
+        }
+        let start = (tca - (bound * bound - d2).sqrt()).max(0.0).min(tca);
+        let s = &o + &(dir * start);
+
+        // (|p|^2 + R^2 - r^2)^2 = 4 R^2 (x^2 + z^2) for p = s + t * dir, worked out in doubles
+        let (sx, sy, sz) = (s[0] as f64, s[1] as f64, s[2] as f64);
+        let (dx, dy, dz) = (dir[0] as f64, dir[1] as f64, dir[2] as f64);
+        let (big, small) = (self.major as f64, self.minor as f64);
+        let f = sx * dx + sy * dy + sz * dz;
+        let k = sx * sx + sy * sy + sz * sz + big * big - small * small;
+        let four_r2 = 4.0 * big * big;
+        let coefficients = [k * k - four_r2 * (sx * sx + sz * sz),
+                            4.0 * f * k - 2.0 * four_r2 * (sx * dx + sz * dz),
+                            4.0 * f * f + 2.0 * k - four_r2 * (dx * dx + dz * dz),
+                            4.0 * f,
+                            1.0];
+
+        let crossings = solve_quartic(&coefficients).into_iter()
+            .map(|t| {
+                let distance = start + t as f32;
+                self.hit_at(distance, &(&o + &(dir * distance)))
+            })
+            .collect();
+        in_front(crossings)
+    }
+
+    fn get_material(&self) -> &Material {
+        &self.material
+    }
+
+    fn area(&self) -> f32 {
+        4.0 * PI * PI * self.major * self.minor
+    }
+
+    fn sample_surface(&self, u: f32, v: f32, _time: f32) -> Option<(Vec3f, Vec3f)> {
+        // the outside of the ring has more surface than the inside, in proportion to R + r cos(theta)
+        // so theta comes from inverting that distribution numerically, which converges quickly since it's so smooth
+        let target = u * 2.0 * PI * self.major;
+        let mut theta = 2.0 * PI * u;
+        for _ in 0..8 {
+            let error = self.major * theta + self.minor * theta.sin() - target;
+            theta -= error / (self.major + self.minor * theta.cos());
+        }
+        let phi = 2.0 * PI * v;
+
+        let normal = Vec3f::new3f(theta.cos() * phi.sin(), theta.sin(), theta.cos() * phi.cos());
+        let rho = self.major + self.minor * theta.cos();
+        let point = Vec3f::new3f(rho * phi.sin(), self.minor * theta.sin(), rho * phi.cos());
+        Some((&self.center + &point, normal))
+    }
+}
+
+fn is_zero(x: f64) -> bool {
+    x.abs() < 1e-9
+}
+
+// real roots of c[0] + c[1] x + c[2] x^2, after jochen schwarze's solvers in graphics gems i
+fn solve_quadratic(c: &[f64; 3]) -> Vec<f64> {
+    let p = c[1] / (2.0 * c[2]);
+    let q = c[0] / c[2];
+    let discriminant = p * p - q;
+
+    if is_zero(discriminant) {
+        vec![-p]
+    } else if discriminant < 0.0 {
+        Vec::new()
+    } else {
+        let root = discriminant.sqrt();
+        vec![root - p, -root - p]
+    }
+}
+
+// real roots of c[0] + c[1] x + c[2] x^2 + c[3] x^3
+fn solve_cubic(c: &[f64; 4]) -> Vec<f64> {
+    let (a, b, cc) = (c[2] / c[3], c[1] / c[3], c[0] / c[3]);
+
+    // x = y - a/3 gets rid of the square term, leaving y^3 + 3p y + 2q = 0
+    let sq_a = a * a;
+    let p = (-sq_a / 3.0 + b) / 3.0;
+    let q = (2.0 / 27.0 * a * sq_a - a * b / 3.0 + cc) / 2.0;
+    let cb_p = p * p * p;
+    let discriminant = q * q + cb_p;
+
+    let roots = if is_zero(discriminant) {
+        if is_zero(q) {
+            vec![0.0]
+        } else {
+            let u = (-q).cbrt();
+            vec![2.0 * u, -u]
+        }
+    } else if discriminant < 0.0 {
+        // three real roots, found with trigonometry
+        let phi = (-q / (-cb_p).sqrt()).clamp(-1.0, 1.0).acos() / 3.0;
+        let t = 2.0 * (-p).sqrt();
+        vec![t * phi.cos(), -t * (phi + PI as f64 / 3.0).cos(), -t * (phi - PI as f64 / 3.0).cos()]
+    } else {
+        let root = discriminant.sqrt();
+        vec![(root - q).cbrt() - (root + q).cbrt()]
+    };
+
+    roots.into_iter().map(|y| y - a / 3.0).collect()
+}
+
+// real roots of c[0] + c[1] x + ... + c[4] x^4, by ferrari's method
+fn solve_quartic(c: &[f64; 5]) -> Vec<f64> {
+    let (a, b, cc, d) = (c[3] / c[4], c[2] / c[4], c[1] / c[4], c[0] / c[4]);
+
+    // x = y - a/4 gets rid of the cubic term, leaving y^4 + p y^2 + q y + r = 0
+    let sq_a = a * a;
+    let p = -3.0 / 8.0 * sq_a + b;
+    let q = sq_a * a / 8.0 - a * b / 2.0 + cc;
+    let r = -3.0 / 256.0 * sq_a * sq_a + sq_a * b / 16.0 - a * cc / 4.0 + d;
+
+    let roots = if is_zero(r) {
+        // y (y^3 + p y + q) = 0
+        let mut roots = solve_cubic(&[q, p, 0.0, 1.0]);
+        roots.push(0.0);
+        roots
+    } else {
+        // any real root of the resolvent cubic splits the quartic into two quadratics
+        let z = solve_cubic(&[r * p / 2.0 - q * q / 8.0, -r, -p / 2.0, 1.0])[0];
+        let mut u = z * z - r;
+        let mut v = 2.0 * z - p;
+        if is_zero(u) { u = 0.0 } else if u > 0.0 { u = u.sqrt() } else { return Vec::new() }
+        if is_zero(v) { v = 0.0 } else if v > 0.0 { v = v.sqrt() } else { return Vec::new() }
+
+        let mut roots = solve_quadratic(&[z - u, if q < 0.0 { -v } else { v }, 1.0]);
+        roots.extend(solve_quadratic(&[z + u, if q < 0.0 { v } else { -v }, 1.0]));
+        roots
+    };
+
+    // a couple of newton steps on the original polynomial clean up what precision ferrari's method loses
+    roots.into_iter().map(|y| {
+        let mut x = y - a / 4.0;
+        for _ in 0..2 {
+            let value = (((c[4] * x + c[3]) * x + c[2]) * x + c[1]) * x + c[0];
+            let slope = ((4.0 * c[4] * x + 3.0 * c[3]) * x + 2.0 * c[2]) * x + c[1];
+            if slope.abs() > 1e-12 {
+                x -= value / slope;
+            }
+        }
+        x
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Vec4f;
+    use crate::sampling::Rng;
+
+    fn material() -> Material {
+        Material::new(&Vec3f::new3f(1.0, 1.0, 1.0), &Vec4f::from(&[1.0, 0.0, 0.0, 0.0]), 1.0, 1.0)
+    }
+
+    #[test]
+    fn stretched_box() {
+        // a unit cube stretched into a 1 x 2 x 4 box, with sides of area 8, 4 and 2
+        let cube = Cuboid::new(&Vec3f::new3f(-0.5, -0.5, -0.5), &Vec3f::new3f(0.5, 0.5, 0.5), &material());
+        let stretched = Transformed::new(cube, Mat4::scaling(&Vec3f::new3f(1.0, 2.0, 4.0))).unwrap();
+        assert!((stretched.area() - 28.0).abs() < 0.28, "area {}", stretched.area());
+
+        let mut rng = Rng::new(9, 0);
+        let mut sides = [0; 3];
+        for _ in 0..2800 {
+            let (point, normal) = stretched.sample_surface(rng.next_f32(), rng.next_f32(), 0.0).unwrap();
+            let axis = (0..3).find(|&axis| normal[axis].abs() > 0.999).unwrap();
+            assert!((point[axis].abs() - [0.5, 1.0, 2.0][axis as usize]).abs() < 1e-4, "{:?} with normal {:?}", point, normal);
+            sides[axis as usize] += 1;
+        }
+        // spread by area, about 1600, 800 and 400
+        assert!((sides[0] as f32 - 1600.0).abs() < 120.0 && (sides[1] as f32 - 800.0).abs() < 90.0 && (sides[2] as f32 - 400.0).abs() < 70.0, "{:?}", sides);
+    }
+
+    #[test]
+    fn rotated_and_scaled_evenly() {
+        let ball = Sphere::new(Vec3f::zero(), 1.0, &material());
+        let transform = &Mat4::rotation(&Vec3f::new3f(1.0, 2.0, 3.0), 0.7) * &Mat4::scaling(&Vec3f::new3f(3.0, 3.0, 3.0));
+        let (least, most) = transform.stretch_range();
+        assert!((least - 3.0).abs() < 1e-4 && (most - 3.0).abs() < 1e-4, "{} {}", least, most);
+        let scaled = Transformed::new(ball, transform).unwrap();
+        assert!((scaled.area() - 36.0 * PI).abs() < 1e-2, "area {}", scaled.area());
+    }
+
+    #[test]
+    fn disk_sampled_on_both_sides() {
+        let disk = Disk::new(Vec3f::new3f(0.0, 1.0, 0.0), &Vec3f::new3f(0.0, 1.0, 0.0), 2.0, &material());
+        assert!((disk.area() - 8.0 * PI).abs() < 1e-4);
+
+        let mut rng = Rng::new(5, 0);
+        let mut up = 0;
+        for _ in 0..1000 {
+            let (point, normal) = disk.sample_surface(rng.next_f32(), rng.next_f32(), 0.0).unwrap();
+            assert!((point[1] - 1.0).abs() < 1e-5 && (point[0] * point[0] + point[2] * point[2]) <= 4.0 + 1e-4, "{:?}", point);
+            assert!(normal[1].abs() > 0.999, "{:?}", normal);
+            up += (normal[1] > 0.0) as u32;
+        }
+        // half on either side, like ray_intersect's normals facing whichever way the ray came from
+        assert!(up > 430 && up < 570, "{} of 1000 facing up", up);
+    }
+
+    // every root the quartic solver finds should be on the torus, and marching along the ray shouldn't turn up any it missed
+    #[test]
+    fn torus_roots_match_marching() {
+        let (major, minor) = (2.0, 0.5);
+        let torus = Torus::new(Vec3f::new3f(1.0, -1.0, -3.0), major, minor, &material());
+        // positive outside, negative inside the tube
+        let implicit = |p: &Vec3f| {
+            let o = p - &torus.center;
+            let k = o.dot(&o) + major * major - minor * minor;
+            k * k - 4.0 * major * major * (o[0] * o[0] + o[2] * o[2])
+        };
+
+        let mut rng = Rng::new(13, 0);
+        let mut root_count = 0;
+        for _ in 0..300 {
+            let orig = &torus.center + &Vec3f::new3f(rng.next_f32() * 16.0 - 8.0, rng.next_f32() * 8.0 - 4.0, rng.next_f32() * 16.0 - 8.0);
+            let target = &torus.center + &Vec3f::new3f(rng.next_f32() * 5.0 - 2.5, rng.next_f32() - 0.5, rng.next_f32() * 5.0 - 2.5);
+            let dir = (&target - &orig).normalize();
+
+            let crossings = torus.crossings(&orig, &dir, 0.0);
+            for hit in &crossings {
+                let p = &orig + &(&dir * hit.distance);
+                let o = &p - &torus.center;
+                let rho = (o[0] * o[0] + o[2] * o[2]).sqrt();
+                let off_surface = ((rho - major).powi(2) + o[1] * o[1]).sqrt() - minor;
+                assert!(off_surface.abs() < 1e-3, "crossing at {} is {} off the surface", hit.distance, off_surface);
+            }
+
+            let step = 0.01;
+            let mut previous = implicit(&orig);
+            for i in 1..3000 {
+                let t = i as f32 * step;
+                let value = implicit(&(&orig + &(&dir * t)));
+                if (value > 0.0) != (previous > 0.0) {
+                    assert!(crossings.iter().any(|hit| (hit.distance - t).abs() < 2.0 * step), "missed a root near {} from {:?} along {:?}", t, orig, dir);
+                    root_count += 1;
+                }
+                previous = value;
+            }
+        }
+        assert!(root_count > 200, "only {} roots", root_count);
+    }
+
+    // points sampled on a shape should be right where a ray coming in along their normal hits it, facing the same way
+    #[test]
+    fn samples_on_the_surface() {
+        let shapes: Vec<Box<dyn Object>> = vec![
+            Box::new(Sphere::new(Vec3f::new3f(1.0, 2.0, 3.0), 1.5, &material())),
+            Box::new(Cuboid::new(&Vec3f::new3f(-1.0, 0.0, 2.0), &Vec3f::new3f(0.5, 3.0, 2.5), &material())),
+            Box::new(Cylinder::new(Vec3f::new3f(0.0, -1.0, 0.0), 0.7, 2.0, &material())),
+            Box::new(Cone::new(Vec3f::new3f(2.0, 0.0, -1.0), 1.0, 3.0, &material())),
+            Box::new(Torus::new(Vec3f::new3f(0.0, 0.0, -5.0), 1.5, 0.4, &material())),
+        ];
+
+        let mut rng = Rng::new(17, 0);
+        for (i, shape) in shapes.iter().enumerate() {
+            for _ in 0..500 {
+                let (point, normal) = shape.sample_surface(rng.next_f32(), rng.next_f32(), 0.0).unwrap();
+                assert!((normal.magnitude() - 1.0).abs() < 1e-4, "shape {}: normal {:?}", i, normal);
+                let orig = &point + &(&normal * 0.01);
+                let hit = shape.ray_intersect(&orig, &(&Vec3f::zero() - &normal), 0.0)
+                    .unwrap_or_else(|| panic!("shape {}: {:?} isn't on it", i, point));
+                assert!((hit.distance - 0.01).abs() < 1e-3, "shape {}: {:?} is {} from the surface", i, point, hit.distance - 0.01);
+                assert!(hit.normal.dot(&normal) > 0.99, "shape {}: sampled normal {:?}, hit normal {:?}", i, normal, hit.normal);
+            }
+        }
+    }
+
+    // the side of a cylinder of radius 1 and height 2 is two thirds of it
+    #[test]
+    fn cylinder_sampled_by_area() {
+        let cylinder = Cylinder::new(Vec3f::zero(), 1.0, 2.0, &material());
+        assert!((cylinder.area() - 6.0 * PI).abs() < 1e-4);
+        let side = (0..3000).filter(|&i| cylinder.sample_surface(sampling::halton(i, 0), sampling::halton(i, 1), 0.0).unwrap().1[1] == 0.0).count();
+        assert!((side as i32 - 2000).abs() < 10, "{} of 3000 on the side", side);
+    }
+
+    #[test]
+    fn moving_sphere() {
+        let ball = Sphere::moving(Vec3f::new3f(0.0, 0.0, -5.0), &Vec3f::new3f(4.0, 0.0, -5.0), 1.0, &material());
+        let (orig, dir) = (Vec3f::new3f(2.0, 0.0, 0.0), Vec3f::new3f(0.0, 0.0, -1.0));
+        // only there halfway through
+        assert!(ball.ray_intersect(&orig, &dir, 0.0).is_none());
+        assert!(ball.ray_intersect(&orig, &dir, 1.0).is_none());
+        let hit = ball.ray_intersect(&orig, &dir, 0.5).unwrap();
+        assert!((hit.distance - 4.0).abs() < 1e-5 && hit.normal == Vec3f::new3f(0.0, 0.0, 1.0), "{:?}", hit);
+
+        let (point, _) = ball.sample_surface(0.3, 0.6, 1.0).unwrap();
+        assert!(((&point - &Vec3f::new3f(4.0, 0.0, -5.0)).magnitude() - 1.0).abs() < 1e-5, "{:?}", point);
+    }
+
+    // a box turning a quarter turn is turned an eighth of one halfway through, rather than squashed like blending the matrices would
+    #[test]
+    fn turning_box() {
+        let cube = Cuboid::new(&Vec3f::new3f(-0.5, -0.5, -0.5), &Vec3f::new3f(0.5, 0.5, 0.5), &material());
+        let start = Placement { pivot: Vec3f::zero(), scale: Vec3f::new3f(1.0, 1.0, 1.0), rotation: Vec3f::zero(), translation: Vec3f::new3f(0.0, 0.0, -5.0) };
+        let end = Placement { rotation: Vec3f::new3f(0.0, 90.0, 0.0), ..start.clone() };
+        let turning = Transformed::moving(cube, start, end).unwrap();
+
+        // just past the side of the box, but inside its corner once that's turned towards us
+        let (orig, dir) = (Vec3f::new3f(0.6, 0.0, 0.0), Vec3f::new3f(0.0, 0.0, -1.0));
+        assert!(turning.ray_intersect(&orig, &dir, 0.0).is_none());
+        assert!(turning.ray_intersect(&orig, &dir, 1.0).is_none());
+        let hit = turning.ray_intersect(&orig, &dir, 0.5).unwrap();
+        let corner_distance = 5.0 - (0.5f32.sqrt() - 0.6);
+        assert!((hit.distance - corner_distance).abs() < 1e-4, "{} isn't {}", hit.distance, corner_distance);
+        assert!((hit.normal[0] - 0.5f32.sqrt()).abs() < 1e-4, "{:?}", hit.normal);
+    }
 }
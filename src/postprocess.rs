@@ -0,0 +1,195 @@
+// effects applied to the finished picture rather than traced, the way a camera lens and film would change it
+// a scene lists any number of them and they're applied one after the other, in that order
+
+use crate::geometry::Vec3f;
+use crate::image::Image;
+use crate::sampling;
+
+#[derive(Debug, Clone)]
+pub enum Effect {
+    // light brighter than threshold spills over onto its surroundings, like it would scattering around inside a lens
+    // spread over about radius pixels, and added back on top scaled by strength
+    Bloom { threshold: f32, strength: f32, radius: f32 },
+    // darkening towards the edges, reaching 1 - strength in the corners
+    Vignette { strength: f32 },
+    // red and blue coming into focus slightly bigger and smaller than green, which puts colored fringes on edges away from the middle
+    // amount is how far off they are in the corners, as a fraction of the distance to the middle
+    ChromaticAberration { amount: f32 },
+    // random speckles of up to amount brighter or darker, the same in every channel, and the same every time for the same seed
+    FilmGrain { amount: f32, seed: u64 },
+}
+
+/// where an image sits in the whole picture, so a cropped render gets exactly what its part of the whole one would
+/// as long as it's been rendered with reach() more pixels around it (or up to the edge of the picture), and then trimmed
+#[derive(Debug, Clone, Copy)]
+pub struct Frame {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Frame {
+    // position of the middle of pixel (x, y) of the image relative to the middle of the picture, -1 to 1 from edge to edge
+    fn centered(&self, x: f32, y: f32) -> (f32, f32) {
+        let half_width = self.width as f32 / 2.0;
+        let half_height = self.height as f32 / 2.0;
+        ((self.x as f32 + x + 0.5 - half_width) / half_width, (self.y as f32 + y + 0.5 - half_height) / half_height)
+    }
+
+    // the other way around, back to (fractional) pixel coordinates within the image
+    fn uncentered(&self, u: f32, v: f32) -> (f32, f32) {
+        let half_width = self.width as f32 / 2.0;
+        let half_height = self.height as f32 / 2.0;
+        (u * half_width + half_width - 0.5 - self.x as f32, v * half_height + half_height - 0.5 - self.y as f32)
+    }
+}
+
+impl Effect {
+    pub fn apply(&self, image: &Image, frame: &Frame) -> Image {
+        match self {
+            Effect::Bloom { threshold, strength, radius } => bloom(image, *threshold, *strength, *radius),
+            Effect::Vignette { strength } => map_pixels(image, |x, y, color| {
+                let (u, v) = frame.centered(x as f32, y as f32);
+                // 0 in the middle and 1 in the corners
+                let r2 = (u * u + v * v) / 2.0;
+                color * (1.0 - strength * r2).max(0.0)
+            }),
+            Effect::ChromaticAberration { amount } => map_pixels(image, |x, y, color| {
+                let (u, v) = frame.centered(x as f32, y as f32);
+                let channel = |c: i32, scale: f32| {
+                    let (sx, sy) = frame.uncentered(u * scale, v * scale);
+                    bilinear(image, sx, sy)[c]
+                };
+                Vec3f::new3f(channel(0, 1.0 - amount), color[1], channel(2, 1.0 + amount))
+            }),
+            Effect::FilmGrain { amount, seed } => map_pixels(image, |x, y, color| {
+                // two uniform numbers added up cluster around the middle a little, more like real grain than plain uniform noise
+                let (a, b) = grain(*seed, frame.x + x, frame.y + y);
+                let offset = amount * (a + b - 1.0);
+                Vec3f::new3f((color[0] + offset).max(0.0), (color[1] + offset).max(0.0), (color[2] + offset).max(0.0))
+            }),
+        }
+    }
+
+    /// how many pixels away from each one it looks, in any direction
+    pub fn reach(&self, frame: &Frame) -> usize {
+        match self {
+            Effect::Bloom { radius, .. } => radius.ceil() as usize,
+            // red and blue move furthest in the corners, then bilinear takes the next pixel over too
+            Effect::ChromaticAberration { amount } => (amount.abs() * frame.width.max(frame.height) as f32 / 2.0).ceil() as usize + 1,
+            Effect::Vignette { .. } | Effect::FilmGrain { .. } => 0,
+        }
+    }
+}
+
+/// every effect in turn
+pub fn apply_all(effects: &[Effect], image: Image, frame: &Frame) -> Image {
+    effects.iter().fold(image, |image, effect| effect.apply(&image, frame))
+}
+
+/// how far every effect in turn reaches, each one needing the last one's result that much further out
+pub fn reach_all(effects: &[Effect], frame: &Frame) -> usize {
+    effects.iter().map(|effect| effect.reach(frame)).sum()
+}
+
+fn map_pixels<F>(image: &Image, f: F) -> Image where F: Fn(usize, usize, &Vec3f) -> Vec3f {
+    let pixels = (0..image.height())
+        .flat_map(|y| (0..image.width()).map(move |x| (x, y)))
+        .map(|(x, y)| f(x, y, image.get(x, y)))
+        .collect();
+
+    Image::new(image.width(), image.height(), pixels)
+}
+
+// the image at a fractional pixel position, edges repeating outwards
+fn bilinear(image: &Image, x: f32, y: f32) -> Vec3f {
+    let max_x = image.width() as f32 - 1.0;
+    let max_y = image.height() as f32 - 1.0;
+    let (x, y) = (x.clamp(0.0, max_x), y.clamp(0.0, max_y));
+    let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+    let (x1, y1) = ((x0 + 1).min(max_x as usize), (y0 + 1).min(max_y as usize));
+    let (tx, ty) = (x - x0 as f32, y - y0 as f32);
+
+    let top = &(image.get(x0, y0) * (1.0 - tx)) + &(image.get(x1, y0) * tx);
+    let bottom = &(image.get(x0, y1) * (1.0 - tx)) + &(image.get(x1, y1) * tx);
+    &(&top * (1.0 - ty)) + &(&bottom * ty)
+}
+
+fn bloom(image: &Image, threshold: f32, strength: f32, radius: f32) -> Image {
+    let bright = map_pixels(image, |_, _, color| {
+        Vec3f::new3f((color[0] - threshold).max(0.0), (color[1] - threshold).max(0.0), (color[2] - threshold).max(0.0))
+    });
+    let glow = blur(&blur(&bright, radius, true), radius, false);
+
+    map_pixels(image, |x, y, color| color + &(glow.get(x, y) * strength))
+}
+
+// gaussian blur along one axis, with radius being three standard deviations
+fn blur(image: &Image, radius: f32, horizontal: bool) -> Image {
+    let sigma = (radius / 3.0).max(0.01);
+    let reach = radius.ceil() as isize;
+    let weights: Vec<f32> = (-reach..=reach).map(|i| (-((i * i) as f32) / (2.0 * sigma * sigma)).exp()).collect();
+    let (width, height) = (image.width() as isize, image.height() as isize);
+
+    map_pixels(image, |x, y, _| {
+        let mut total = Vec3f::zero();
+        let mut total_weight = 0.0;
+        for (i, weight) in (-reach..=reach).zip(&weights) {
+            let (nx, ny) = if horizontal { (x as isize + i, y as isize) } else { (x as isize, y as isize + i) };
+            if nx < 0 || ny < 0 || nx >= width || ny >= height {
+                continue;
+            }
+            total = &total + &(image.get(nx as usize, ny as usize) * *weight);
+            total_weight += weight;
+        }
+        &total * (1.0 / total_weight)
+    })
+}
+
+// two numbers in [0, 1) for pixel (x, y) of the whole picture, from a hash of where it is
+// so the grain stays put from one render to the next, and lines up across crops
+fn grain(seed: u64, x: usize, y: usize) -> (f32, f32) {
+    let h = sampling::hash(&[seed, x as u64, y as u64]);
+
+    ((h >> 40) as f32 / (1u64 << 24) as f32, ((h >> 16) & 0xFF_FFFF) as f32 / (1u64 << 24) as f32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a crop with reach_all more pixels around it should come out exactly like the same bit of the whole picture
+    #[test]
+    fn crops_match_the_whole_picture() {
+        let (width, height) = (96, 64);
+        let pixels = (0..width * height).map(|i| {
+            let (x, y) = ((i % width) as f32, (i / width) as f32);
+            Vec3f::new3f((x * 0.37).sin().abs() * 3.0, (y * 0.21).cos().abs(), ((x + y) * 0.05).fract())
+        }).collect();
+        let picture = Image::new(width, height, pixels);
+        let effects = [
+            Effect::ChromaticAberration { amount: 0.05 },
+            Effect::Bloom { threshold: 1.0, strength: 0.5, radius: 4.0 },
+            Effect::Vignette { strength: 0.5 },
+            Effect::FilmGrain { amount: 0.1, seed: 3 },
+        ];
+        let whole = Frame { x: 0, y: 0, width, height };
+        let expected = apply_all(&effects, picture.clone(), &whole);
+
+        let reach = reach_all(&effects, &whole);
+        for (x, y, w, h) in [(30, 20, 16, 12), (0, 0, 20, 10), (80, 50, 16, 14)] {
+            let (px, py) = (x - reach.min(x), y - reach.min(y));
+            let (right, bottom) = ((x + w + reach).min(width), (y + h + reach).min(height));
+            let padded = picture.region(px, py, right - px, bottom - py);
+            let image = apply_all(&effects, padded, &Frame { x: px, y: py, ..whole }).region(x - px, y - py, w, h);
+
+            let expected = expected.region(x, y, w, h);
+            for j in 0..h {
+                for i in 0..w {
+                    assert_eq!(image.get(i, j), expected.get(i, j), "crop at {},{} pixel {},{}", x, y, i, j);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,368 @@
+// rendering in passes of one ray per pixel, so there's a whole (if noisy) picture to look at long before the last pass is done
+// pixels whose average has settled down can be left out of later passes, see Accumulator::converged
+// along with the picture go the sums for any aovs asked for, see aov.rs
+// the running totals can also be written out and read back in, so a render that got stopped can carry on where it left off
+
+use std::fs;
+use std::io::{self, ErrorKind};
+use crate::aov::{Aov, AovSample};
+use crate::geometry::Vec3f;
+use crate::image::{self, luminance, Image};
+use crate::sampling::Sampler;
+
+static STATE_MAGIC: &str = "tinyrt-state";
+
+/// what a render got started with, besides the size, aovs and alpha the accumulator already knows about
+/// resuming has to carry on with the same ones for the picture to come out as if it had never been stopped
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Settings {
+    // what the random numbers were seeded with
+    pub seed: u64,
+    // top left corner of the crop window, in the whole picture
+    pub x: u32,
+    pub y: u32,
+    // the adaptive sampling error, if there is one
+    pub max_error: Option<f32>,
+    // how each pixel's rays were spread, which the rest have to be spread the same way as to fill in between them
+    pub sampler: Sampler,
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg.to_string())
+}
+
+/// running totals of every pass rendered so far, the picture being their average
+pub struct Accumulator {
+    width: usize,
+    height: usize,
+    sums: Vec<Vec3f>,
+    // rays that went into each pixel's sum
+    counts: Vec<u32>,
+    // sum of the squares of each ray's luminance, for telling how noisy the pixel is
+    squares: Vec<f32>,
+    // per pixel sums for each aov, or just the first ray's value for the ones that aren't averaged
+    aovs: Vec<(Aov, Vec<Vec3f>)>,
+    // sum of each ray's coverage, for pictures with a transparent background
+    alpha: Option<Vec<f32>>,
+    passes: u32,
+}
+
+impl Accumulator {
+    pub fn new(width: usize, height: usize, aovs: &[Aov]) -> Self {
+        let size = width * height;
+        let aovs = aovs.iter().map(|&aov| (aov, vec![Vec3f::zero(); size])).collect();
+        Accumulator { width, height, sums: vec![Vec3f::zero(); size], counts: vec![0; size], squares: vec![0.0; size], aovs, alpha: None, passes: 0 }
+    }
+
+    /// keep track of how much of each pixel is covered too, see image()
+    pub fn with_alpha(mut self) -> Self {
+        self.alpha = Some(vec![0.0; self.width * self.height]);
+        self
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn passes(&self) -> u32 {
+        self.passes
+    }
+
+    pub fn aovs(&self) -> Vec<Aov> {
+        self.aovs.iter().map(|(aov, _)| *aov).collect()
+    }
+
+    /// add one ray's worth of color to pixel (x, y) for the current pass, along with what it saw for the aovs if there are any
+    /// coverage is how much of the ray hit something rather than a transparent background, ignored unless there's alpha
+    pub fn add(&mut self, x: usize, y: usize, color: &Vec3f, coverage: f32, sample: Option<&AovSample>) {
+        let i = y * self.width + x;
+        if let Some(alpha) = &mut self.alpha {
+            alpha[i] += coverage;
+        }
+        if let Some(sample) = sample {
+            for (aov, values) in &mut self.aovs {
+                if aov.averaged() {
+                    values[i] = &values[i] + &sample.get(*aov);
+                } else if self.counts[i] == 0 {
+                    values[i] = sample.get(*aov);
+                }
+            }
+        }
+        self.sums[i] = &self.sums[i] + color;
+        self.counts[i] += 1;
+        self.squares[i] += luminance(color).powi(2);
+    }
+
+    /// whether pixel (x, y)'s average has most likely landed within max_error of where more rays would take it
+    /// going by the standard error of its mean luminance, which needs a few rays before it means much
+    pub fn converged(&self, x: usize, y: usize, max_error: f32) -> bool {
+        let i = y * self.width + x;
+        let n = self.counts[i] as f32;
+        if n < 2.0 {
+            return false;
+        }
+
+        let mean = luminance(&self.sums[i]) / n;
+        let variance = ((self.squares[i] / n - mean * mean) * n / (n - 1.0)).max(0.0);
+        (variance / n).sqrt() <= max_error
+    }
+
+    /// call once every pixel has had its ray for this pass
+    pub fn finish_pass(&mut self) {
+        self.passes += 1;
+    }
+
+    /// the average of all the rays so far, without any alpha, so it can be filtered before alpha() gets put back on
+    pub fn image(&self) -> Image {
+        let pixels = self.sums.iter().zip(&self.counts)
+            .map(|(sum, &count)| sum * (1.0 / count.max(1) as f32))
+            .collect();
+
+        Image::new(self.width, self.height, pixels)
+    }
+
+    pub fn has_alpha(&self) -> bool {
+        self.alpha.is_some()
+    }
+
+    /// the average coverage of every pixel, if it's being kept track of
+    pub fn alpha(&self) -> Option<Vec<f32>> {
+        let alpha = self.alpha.as_ref()?;
+        Some(alpha.iter().zip(&self.counts).map(|(sum, &count)| sum / count.max(1) as f32).collect())
+    }
+
+    /// one of the aovs as an image, if it's being kept track of
+    pub fn aov_image(&self, aov: Aov) -> Option<Image> {
+        let (_, values) = self.aovs.iter().find(|(a, _)| *a == aov)?;
+        let pixels = values.iter().zip(&self.counts)
+            .map(|(value, &count)| if aov.averaged() { value * (1.0 / count.max(1) as f32) } else { value.clone() })
+            .collect();
+
+        Some(Image::new(self.width, self.height, pixels))
+    }
+
+    /// how many rays went into each pixel, from black for none through red and yellow to white for the most any pixel got
+    pub fn sample_map(&self) -> Image {
+        let most = self.counts.iter().copied().max().unwrap_or(0).max(1) as f32;
+        let pixels = self.counts.iter()
+            .map(|&count| image::heat(count as f32 / most))
+            .collect();
+
+        Image::new(self.width, self.height, pixels)
+    }
+
+    /// write everything needed to pick the render back up to path, along with the settings it was started with
+    /// a header line, then for every pixel its count, sum, sum of squares, coverage sum (if there is one) and aov sums
+    /// as little endian u32 and f32s, so nothing gets rounded
+    pub fn save_state(&self, settings: &Settings, path: &str) -> io::Result<()> {
+        let aovs = if self.aovs.is_empty() { "-".to_string() } else { self.aovs().iter().map(|aov| aov.name()).collect::<Vec<_>>().join(",") };
+        let alpha = if self.alpha.is_some() { "alpha" } else { "-" };
+        // f32's display is the shortest text that parses back to exactly the same number
+        let max_error = settings.max_error.map_or("-".to_string(), |error| error.to_string());
+        let mut out = format!("{}\n{} {} {} {} {} {} {} {} {} {}\n", STATE_MAGIC, self.width, self.height, self.passes, settings.seed, aovs, alpha,
+                              settings.x, settings.y, max_error, settings.sampler.name()).into_bytes();
+        let vector = |out: &mut Vec<u8>, v: &Vec3f| (0..3).for_each(|c| out.extend_from_slice(&v[c].to_le_bytes()));
+        for i in 0..self.sums.len() {
+            out.extend_from_slice(&self.counts[i].to_le_bytes());
+            vector(&mut out, &self.sums[i]);
+            out.extend_from_slice(&self.squares[i].to_le_bytes());
+            if let Some(alpha) = &self.alpha {
+                out.extend_from_slice(&alpha[i].to_le_bytes());
+            }
+            for (_, values) in &self.aovs {
+                vector(&mut out, &values[i]);
+            }
+        }
+
+        fs::write(path, out)
+    }
+
+    /// the other half of save_state, giving back the totals and the settings
+    pub fn load_state(path: &str) -> io::Result<(Self, Settings)> {
+        let bytes = fs::read(path)?;
+
+        let mut lines = bytes.splitn(3, |&b| b == b'\n');
+        if lines.next() != Some(STATE_MAGIC.as_bytes()) {
+            return Err(invalid("not a render state file"));
+        }
+        let header = String::from_utf8_lossy(lines.next().ok_or_else(|| invalid("truncated render state header"))?).into_owned();
+        let data = lines.next().ok_or_else(|| invalid("truncated render state header"))?;
+
+        let fields: Vec<&str> = header.split_whitespace().collect();
+        if fields.len() != 10 {
+            return Err(invalid("bad render state header"));
+        }
+        let width: usize = fields[0].parse().map_err(|_| invalid("bad render state width"))?;
+        let height: usize = fields[1].parse().map_err(|_| invalid("bad render state height"))?;
+        let passes: u32 = fields[2].parse().map_err(|_| invalid("bad render state pass count"))?;
+        let seed: u64 = fields[3].parse().map_err(|_| invalid("bad render state seed"))?;
+        let aovs = match fields[4] {
+            "-" => Vec::new(),
+            names => names.split(',').map(|name| Aov::parse(name).ok_or_else(|| invalid("bad render state aov"))).collect::<io::Result<Vec<Aov>>>()?,
+        };
+        let has_alpha = match fields[5] {
+            "-" => false,
+            "alpha" => true,
+            _ => return Err(invalid("bad render state alpha")),
+        };
+        let x: u32 = fields[6].parse().map_err(|_| invalid("bad render state crop x"))?;
+        let y: u32 = fields[7].parse().map_err(|_| invalid("bad render state crop y"))?;
+        let max_error = match fields[8] {
+            "-" => None,
+            error => Some(error.parse().map_err(|_| invalid("bad render state adaptive error"))?),
+        };
+        let sampler = Sampler::parse(fields[9]).ok_or_else(|| invalid("bad render state sampler"))?;
+
+        let aovs_start = if has_alpha { 24 } else { 20 };
+        let pixel_size = aovs_start + 12 * aovs.len();
+        if data.len() != width * height * pixel_size {
+            return Err(invalid("render state data doesn't match its size"));
+        }
+        let float = |b: &[u8]| f32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+        let vector = |b: &[u8]| Vec3f::new3f(float(&b[0..4]), float(&b[4..8]), float(&b[8..12]));
+        let mut accumulator = Accumulator::new(width, height, &aovs);
+        if has_alpha {
+            accumulator = accumulator.with_alpha();
+        }
+        for (i, pixel) in data.chunks_exact(pixel_size).enumerate() {
+            accumulator.counts[i] = u32::from_le_bytes([pixel[0], pixel[1], pixel[2], pixel[3]]);
+            accumulator.sums[i] = vector(&pixel[4..16]);
+            accumulator.squares[i] = float(&pixel[16..20]);
+            if let Some(alpha) = &mut accumulator.alpha {
+                alpha[i] = float(&pixel[20..24]);
+            }
+            for (k, (_, values)) in accumulator.aovs.iter_mut().enumerate() {
+                values[i] = vector(&pixel[aovs_start + 12 * k..aovs_start + 12 + 12 * k]);
+            }
+        }
+        accumulator.passes = passes;
+
+        Ok((accumulator, Settings { seed, x, y, max_error, sampler }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir().join(format!("tinyrt_{}_{}", std::process::id(), name)).to_string_lossy().into_owned()
+    }
+
+    fn grey(value: f32) -> Vec3f {
+        Vec3f::new3f(value, value, value)
+    }
+
+    // the picture after any number of passes is the average of every ray each pixel got, however many that was
+    #[test]
+    fn averages_passes() {
+        let mut accumulator = Accumulator::new(2, 2, &[]);
+        for pass in 0..4 {
+            accumulator.add(0, 0, &Vec3f::new3f(pass as f32, 1.0, 0.0), 1.0, None);
+            // this one only gets rays in the first two passes
+            if pass < 2 {
+                accumulator.add(1, 0, &grey(pass as f32 * 3.0), 1.0, None);
+            }
+            accumulator.add(0, 1, &grey(0.5), 1.0, None);
+            accumulator.finish_pass();
+        }
+
+        let image = accumulator.image();
+        assert_eq!(accumulator.passes(), 4);
+        assert_eq!(image.get(0, 0), &Vec3f::new3f(1.5, 1.0, 0.0));
+        assert_eq!(image.get(1, 0), &grey(1.5));
+        assert_eq!(image.get(0, 1), &grey(0.5));
+        // never got a ray at all
+        assert_eq!(image.get(1, 1), &Vec3f::zero());
+    }
+
+    // standard error of the mean luminance, which for n rays alternating between black and white is 0.5 / sqrt(n - 1)
+    #[test]
+    fn converges_by_standard_error() {
+        let mut accumulator = Accumulator::new(2, 1, &[]);
+        accumulator.add(1, 0, &grey(0.7), 1.0, None);
+        // one ray says nothing about how noisy a pixel is
+        assert!(!accumulator.converged(1, 0, 1.0));
+        accumulator.add(1, 0, &grey(0.7), 1.0, None);
+        assert!(accumulator.converged(1, 0, 0.0));
+
+        let mut n = 0;
+        for rays in [4, 16, 64] {
+            while n < rays {
+                accumulator.add(0, 0, &grey((n % 2) as f32), 1.0, None);
+                n += 1;
+            }
+            let error = 0.5 / (rays as f32 - 1.0).sqrt();
+            assert!(accumulator.converged(0, 0, error + 1e-4), "{} rays", rays);
+            assert!(!accumulator.converged(0, 0, error - 1e-4), "{} rays", rays);
+        }
+    }
+
+    // the pixel with the most rays is white, and one with none black
+    #[test]
+    fn sample_map() {
+        let mut accumulator = Accumulator::new(3, 1, &[]);
+        for _ in 0..4 {
+            accumulator.add(0, 0, &grey(1.0), 1.0, None);
+        }
+        accumulator.add(1, 0, &grey(1.0), 1.0, None);
+        let map = accumulator.sample_map();
+        assert_eq!([map.get(0, 0), map.get(1, 0), map.get(2, 0)], [&grey(1.0), &image::heat(0.25), &Vec3f::zero()]);
+    }
+
+    #[test]
+    fn state_round_trip() {
+        let mut accumulator = Accumulator::new(3, 2, &[Aov::Depth, Aov::ObjectId]).with_alpha();
+        for pass in 0..3 {
+            for i in 0..6 {
+                let value = (pass * 6 + i) as f32 * 0.1;
+                let sample = AovSample { depth: value, object_id: i as u32 + 1, ..AovSample::miss() };
+                accumulator.add(i % 3, i / 3, &Vec3f::new3f(value, 1.0 - value, 1e-7), value / 2.0, Some(&sample));
+            }
+            accumulator.finish_pass();
+        }
+        let settings = Settings { seed: u64::MAX, x: 10, y: 20, max_error: Some(0.1 + 0.2), sampler: Sampler::Sobol };
+
+        let path = temp_path("state_round_trip.state");
+        accumulator.save_state(&settings, &path).unwrap();
+        let (loaded, loaded_settings) = Accumulator::load_state(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded_settings, settings);
+        assert_eq!((loaded.width(), loaded.height(), loaded.passes()), (3, 2, 3));
+        assert_eq!(loaded.aovs(), accumulator.aovs());
+        assert_eq!((&loaded.sums, &loaded.counts, &loaded.squares), (&accumulator.sums, &accumulator.counts, &accumulator.squares));
+        assert_eq!(loaded.alpha, accumulator.alpha);
+        assert_eq!(loaded.aovs, accumulator.aovs);
+    }
+
+    #[test]
+    fn state_without_extras() {
+        let mut accumulator = Accumulator::new(2, 1, &[]);
+        accumulator.add(1, 0, &Vec3f::new3f(1.0, 2.0, 3.0), 1.0, None);
+        accumulator.finish_pass();
+        let settings = Settings { seed: 1, x: 0, y: 0, max_error: None, sampler: Sampler::Random };
+
+        let path = temp_path("state_without_extras.state");
+        accumulator.save_state(&settings, &path).unwrap();
+        let (loaded, loaded_settings) = Accumulator::load_state(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded_settings, settings);
+        assert!(!loaded.has_alpha() && loaded.aovs().is_empty());
+        assert_eq!(loaded.sums, accumulator.sums);
+    }
+
+    #[test]
+    fn not_a_state() {
+        let path = temp_path("not_a_state.state");
+        fs::write(&path, "P6\n1 1\n255\n\0\0\0").unwrap();
+        let result = Accumulator::load_state(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+    }
+}
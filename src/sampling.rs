@@ -0,0 +1,337 @@
+use std::f32::consts::PI;
+use crate::geometry::Vec3f;
+
+// pcg32, see https://www.pcg-random.org/
+// not cryptographically anything, but plenty for picking sample positions
+// every seed can be combined with any of 2^63 streams, which are different sequences altogether rather than one sequence started somewhere else
+pub struct Rng {
+    state: u64,
+    increment: u64,
+}
+
+static PCG_MULTIPLIER: u64 = 6_364_136_223_846_793_005;
+
+impl Rng {
+    pub fn new(seed: u64, stream: u64) -> Self {
+        // the increment has to be odd, and the first step mixes the seed in
+        let mut rng = Rng { state: 0, increment: (stream << 1) | 1 };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old.wrapping_mul(PCG_MULTIPLIER).wrapping_add(self.increment);
+        // xorshift the high bits down, then rotate by the top five
+        ((((old >> 18) ^ old) >> 27) as u32).rotate_right((old >> 59) as u32)
+    }
+
+    /// uniformly distributed in [0, 1)
+    pub fn next_f32(&mut self) -> f32 {
+        // the top 24 bits are exactly representable in an f32 mantissa
+        (self.next_u32() >> 8) as f32 / (1u32 << 24) as f32
+    }
+}
+
+/// a well mixed number made from the given ones, the same every time, for seeding an Rng with e.g. a pixel's position
+pub fn hash(values: &[u64]) -> u64 {
+    // the splitmix64 finalizer, see https://prng.di.unimi.it/splitmix64.c
+    let mix = |mut z: u64| {
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    };
+    values.iter().fold(0x9E37_79B9_7F4A_7C15, |h, &value| mix(h.wrapping_add(mix(value))))
+}
+
+/// how the rays through a pixel get spread over it and over the exposure
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sampler {
+    // independent random positions in the pixel, and a random moment in each of the exposure's strata, see spread_stratum
+    Random,
+    // low discrepancy sequences, which fill in the gaps left by the samples before, so a pixel converges faster
+    // each pixel gets its own randomly shifted copy, or every pixel's noise would line up into a pattern
+    Halton,
+    Sobol,
+}
+
+impl Sampler {
+    pub fn parse(name: &str) -> Option<Sampler> {
+        match name {
+            "random" => Some(Sampler::Random),
+            "halton" => Some(Sampler::Halton),
+            "sobol" => Some(Sampler::Sobol),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Sampler::Random => "random",
+            Sampler::Halton => "halton",
+            Sampler::Sobol => "sobol",
+        }
+    }
+
+    /// where ray i of n for a pixel goes, as an offset into it and a moment in the exposure, all in [0, 1)
+    /// scramble should be the same for every ray of the pixel, and different from one pixel to the next
+    pub fn camera_sample(&self, i: u32, n: u32, scramble: u64, rng: &mut Rng) -> (f32, f32, f32) {
+        // a random number for each dimension to shift the pixel's points along it by
+        let shift = |dimension: u64| hash(&[scramble, dimension]);
+        match self {
+            Sampler::Random => (rng.next_f32(), rng.next_f32(), (spread_stratum(i, n) as f32 + rng.next_f32()) / n as f32),
+            // adding on a shift and wrapping around keeps the points as evenly spread as they were
+            // the moment gets base 2, which spreads the first few the most evenly, as motion blur is what's most often left noisy
+            Sampler::Halton => {
+                let shifted = |dimension: usize| (halton(i, dimension) + to_unit(shift(dimension as u64) as u32)).fract();
+                (shifted(1), shifted(2), shifted(0))
+            }
+            // while sobol points keep their stratification under an xor of their bits instead
+            Sampler::Sobol => {
+                let shifted = |dimension: usize| to_unit(sobol(i, dimension) ^ shift(dimension as u64) as u32);
+                (shifted(0), shifted(1), shifted(2))
+            }
+        }
+    }
+}
+
+// a 32 bit fixed point fraction as an f32 in [0, 1), rounding down so 1 itself never comes out
+fn to_unit(bits: u32) -> f32 {
+    (bits >> 8) as f32 / (1u32 << 24) as f32
+}
+
+static HALTON_BASES: [u32; 8] = [2, 3, 5, 7, 11, 13, 17, 19];
+
+/// point i of the halton sequence, in one of the first eight dimensions
+/// i written out in the dimension's prime base and mirrored around the decimal point
+pub fn halton(i: u32, dimension: usize) -> f32 {
+    let base = HALTON_BASES[dimension];
+    let (mut i, mut result, mut scale) = (i, 0.0f64, 1.0f64);
+    while i > 0 {
+        scale /= base as f64;
+        result += (i % base) as f64 * scale;
+        i /= base;
+    }
+
+    // anything close enough to 1 would round up to it
+    (result as f32).min(1.0 - f32::EPSILON / 2.0)
+}
+
+// primitive polynomials and initial direction numbers for the sobol dimensions after the first
+// from joe and kuo's new-joe-kuo-6.21201, see https://web.maths.unsw.edu.au/~fkuo/sobol/
+// as (degree, coefficients bar the first and last, initial numbers)
+static SOBOL_POLYNOMIALS: [(u32, u32, &[u32]); 7] = [
+    (1, 0, &[1]),
+    (2, 1, &[1, 3]),
+    (3, 1, &[1, 3, 1]),
+    (3, 2, &[1, 1, 1]),
+    (4, 1, &[1, 1, 3, 3]),
+    (4, 4, &[1, 3, 5, 13]),
+    (5, 2, &[1, 1, 5, 5, 17]),
+];
+
+/// point i of the sobol sequence in one of the first eight dimensions, as a 32 bit fixed point fraction
+pub fn sobol(i: u32, dimension: usize) -> u32 {
+    let mut directions = [0u32; 32];
+    if dimension == 0 {
+        // the first dimension is just the van der corput sequence
+        (0..32).for_each(|k| directions[k] = 1 << (31 - k));
+    } else {
+        let (degree, coefficients, initial) = SOBOL_POLYNOMIALS[dimension - 1];
+        let degree = degree as usize;
+        for k in 0..32 {
+            directions[k] = if k < degree {
+                initial[k] << (31 - k)
+            } else {
+                // the recurrence from bratley and fox's algorithm 659
+                let mut v = directions[k - degree] ^ (directions[k - degree] >> degree);
+                for j in 1..degree {
+                    if (coefficients >> (degree - 1 - j)) & 1 == 1 {
+                        v ^= directions[k - j];
+                    }
+                }
+                v
+            };
+        }
+    }
+
+    // xor together the direction numbers for i's set bits
+    (0..32).filter(|k| (i >> k) & 1 == 1).fold(0, |result, k| result ^ directions[k])
+}
+
+/// two unit vectors which together with n form an orthonormal basis
+pub fn basis(n: &Vec3f) -> (Vec3f, Vec3f) {
+    // pick whichever axis is least parallel to n so the cross product doesn't degenerate
+    let helper = if n[0].abs() > 0.9 { Vec3f::new3f(0.0, 1.0, 0.0) } else { Vec3f::new3f(1.0, 0.0, 0.0) };
+    let t = n.cross(&helper).normalize();
+    let b = n.cross(&t);
+
+    (t, b)
+}
+
+// express a direction given in the local frame (z up) in terms of the basis around axis
+fn from_local(axis: &Vec3f, x: f32, y: f32, z: f32) -> Vec3f {
+    let (t, b) = basis(axis);
+
+    (&(&(&t * x) + &(&b * y)) + &(axis * z)).normalize()
+}
+
+/// direction on the hemisphere around normal, with probability proportional to the cosine with the normal
+pub fn cosine_hemisphere(normal: &Vec3f, u: f32, v: f32) -> Vec3f {
+    let r = u.sqrt();
+    let phi = 2.0 * PI * v;
+
+    from_local(normal, r * phi.cos(), r * phi.sin(), (1.0 - u).sqrt())
+}
+
+pub fn cosine_hemisphere_pdf(normal: &Vec3f, dir: &Vec3f) -> f32 {
+    normal.dot(dir).max(0.0) / PI
+}
+
+/// direction around axis distributed like cos^exponent of the angle to it, i.e. the phong specular lobe
+pub fn phong_lobe(axis: &Vec3f, exponent: f32, u: f32, v: f32) -> Vec3f {
+    let cos_theta = u.powf(1.0 / (exponent + 1.0));
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * v;
+
+    from_local(axis, sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+pub fn phong_lobe_pdf(axis: &Vec3f, exponent: f32, dir: &Vec3f) -> f32 {
+    (exponent + 1.0) / (2.0 * PI) * axis.dot(dir).max(0.0).powf(exponent)
+}
+
+// veach's power heuristic with beta = 2
+// weight for a sample taken with pdf_a when the same direction could also have come from strategy b
+pub fn power_heuristic(pdf_a: f32, pdf_b: f32) -> f32 {
+    let a2 = pdf_a * pdf_a;
+    let b2 = pdf_b * pdf_b;
+
+    if a2 + b2 == 0.0 { 0.0 } else { a2 / (a2 + b2) }
+}
+
+/// which of n strata sample i of n should go in, every stratum getting exactly one sample
+/// stepping through them by a stride of roughly n / golden ratio rather than in order, so the first few samples are already spread over all of them
+pub fn spread_stratum(i: u32, n: u32) -> u32 {
+    let gcd = |mut a: u32, mut b: u32| {
+        while b != 0 {
+            (a, b) = (b, a % b);
+        }
+        a
+    };
+    let mut stride = ((n as f32 * 0.618_034).round() as u32).max(1);
+    while gcd(stride, n) != 1 {
+        stride += 1;
+    }
+
+    (i as u64 * stride as u64 % n as u64) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // what the pcg reference implementation's pcg32-demo prints for seed 42 on stream 54
+    #[test]
+    fn pcg_reference() {
+        let mut rng = Rng::new(42, 54);
+        let numbers: Vec<u32> = (0..6).map(|_| rng.next_u32()).collect();
+        assert_eq!(numbers, [0xa15c02b7, 0x7b47f409, 0xba1d3330, 0x83d2f293, 0xbfa4784b, 0xcbed606e]);
+    }
+
+    #[test]
+    fn halton_first_points() {
+        let base_2: Vec<f32> = (0..6).map(|i| halton(i, 0)).collect();
+        assert_eq!(base_2, [0.0, 0.5, 0.25, 0.75, 0.125, 0.625]);
+        let base_3: Vec<f32> = (0..6).map(|i| halton(i, 1)).collect();
+        let expected = [0.0, 1.0 / 3.0, 2.0 / 3.0, 1.0 / 9.0, 4.0 / 9.0, 7.0 / 9.0];
+        assert!(base_3.iter().zip(&expected).all(|(a, b)| (a - b).abs() < 1e-6), "{:?}", base_3);
+    }
+
+    // the first points joe and kuo list for their direction numbers, which go in gray code order
+    #[test]
+    fn sobol_first_points() {
+        let expected = [
+            [0.0, 0.0, 0.0],
+            [0.5, 0.5, 0.5],
+            [0.75, 0.25, 0.25],
+            [0.25, 0.75, 0.75],
+            [0.375, 0.375, 0.625],
+            [0.875, 0.875, 0.125],
+            [0.625, 0.125, 0.875],
+            [0.125, 0.625, 0.375],
+        ];
+        for (i, point) in expected.iter().enumerate() {
+            let gray = i as u32 ^ (i as u32 >> 1);
+            let actual: Vec<f32> = (0..3).map(|dimension| to_unit(sobol(gray, dimension))).collect();
+            assert_eq!(&actual, point, "point {}", i);
+        }
+    }
+
+    // every dimension should be a (0, m, 1) sequence in base 2, i.e. each of the first 2^m points in its own 1 / 2^m interval
+    #[test]
+    fn sobol_stratified() {
+        for dimension in 0..8 {
+            let mut cells: Vec<u32> = (0..64).map(|i| sobol(i, dimension) >> 26).collect();
+            cells.sort();
+            assert_eq!(cells, (0..64).collect::<Vec<u32>>(), "dimension {}", dimension);
+        }
+    }
+
+    #[test]
+    fn samples_in_range() {
+        let mut rng = Rng::new(7, 3);
+        for sampler in [Sampler::Random, Sampler::Halton, Sampler::Sobol] {
+            for i in 0..256 {
+                let (x, y, t) = sampler.camera_sample(i, 256, 12345, &mut rng);
+                assert!([x, y, t].iter().all(|v| (0.0..1.0).contains(v)), "{:?} sample {} is {:?}", sampler, i, (x, y, t));
+            }
+        }
+    }
+
+    // whichever strategy a direction came from, its weights under the two of them add up to one
+    #[test]
+    fn power_heuristic_weights_sum_to_one() {
+        for &(a, b) in &[(1.0, 1.0), (0.3, 7.0), (1e-4, 2.5), (4.0, 0.0), (0.0, 0.5)] {
+            let sum = power_heuristic(a, b) + power_heuristic(b, a);
+            assert!((sum - 1.0).abs() < 1e-6, "{} and {} give weights adding up to {}", a, b, sum);
+        }
+        assert_eq!(power_heuristic(2.0, 2.0), 0.5);
+        assert_eq!(power_heuristic(0.0, 0.0), 0.0);
+    }
+
+    // the pdfs should integrate to one over the sphere, and the directions drawn should lean towards the axis as much as the pdf says
+    // which for cos^n is an average cosine of (n + 1) / (n + 2)
+    #[test]
+    fn lobes_match_their_pdfs() {
+        let axis = Vec3f::new3f(0.36, 0.48, 0.8);
+        let count = 4096;
+        let uniform = |i: u32| {
+            let (z, phi) = (1.0 - 2.0 * halton(i, 0), 2.0 * PI * halton(i, 1));
+            let r = (1.0 - z * z).sqrt();
+            Vec3f::new3f(r * phi.cos(), r * phi.sin(), z)
+        };
+
+        for exponent in [1.0, 4.0, 20.0] {
+            let (mut total, mut cosine) = (0.0, 0.0);
+            for i in 0..count {
+                total += phong_lobe_pdf(&axis, exponent, &uniform(i)) as f64 * 4.0 * std::f64::consts::PI;
+                cosine += phong_lobe(&axis, exponent, halton(i, 0), halton(i, 1)).dot(&axis) as f64;
+            }
+            let (total, cosine) = (total / count as f64, cosine / count as f64);
+            assert!((total - 1.0).abs() < 0.05, "phong lobe of {} integrates to {}", exponent, total);
+            assert!((cosine - (exponent as f64 + 1.0) / (exponent as f64 + 2.0)).abs() < 0.01, "phong lobe of {} has mean cosine {}", exponent, cosine);
+        }
+
+        let (mut total, mut cosine) = (0.0, 0.0);
+        for i in 0..count {
+            total += cosine_hemisphere_pdf(&axis, &uniform(i)) as f64 * 4.0 * std::f64::consts::PI;
+            cosine += cosine_hemisphere(&axis, halton(i, 0), halton(i, 1)).dot(&axis) as f64;
+        }
+        let (total, cosine) = (total / count as f64, cosine / count as f64);
+        assert!((total - 1.0).abs() < 0.05, "cosine hemisphere integrates to {}", total);
+        assert!((cosine - 2.0 / 3.0).abs() < 0.01, "cosine hemisphere has mean cosine {}", cosine);
+    }
+}
@@ -0,0 +1,71 @@
+use crate::background::Background;
+use crate::camera::Camera;
+use crate::light::Light;
+use crate::material::Material;
+use crate::medium::Volume;
+use crate::object::Object;
+use crate::postprocess::Effect;
+
+/// everything a ray can run into, or fail to
+pub struct Scene<T: Object + ?Sized> {
+    lights: Vec<Light>,
+    objects: Vec<Box<T>>,
+    background: Background,
+    // the checkerboard under everything, if any
+    floor: Option<Material>,
+    // fog and smoke, which unlike objects rays travel through rather than stop at
+    volumes: Vec<Volume>,
+    camera: Camera,
+    // applied to the picture once it's rendered, in order
+    effects: Vec<Effect>,
+}
+
+impl<T: Object + ?Sized> Scene<T> {
+    pub fn new(lights: Vec<Light>, objects: Vec<Box<T>>, background: Background, floor: Option<Material>) -> Self {
+        Scene { lights, objects, background, floor, volumes: Vec::new(), camera: Camera::default(), effects: Vec::new() }
+    }
+
+    pub fn lights(&self) -> &[Light] {
+        &self.lights
+    }
+
+    pub fn objects(&self) -> &[Box<T>] {
+        &self.objects
+    }
+
+    pub fn background(&self) -> &Background {
+        &self.background
+    }
+
+    pub fn floor(&self) -> Option<&Material> {
+        self.floor.as_ref()
+    }
+
+    pub fn volumes(&self) -> &[Volume] {
+        &self.volumes
+    }
+
+    pub fn add_volume(&mut self, volume: Volume) {
+        self.volumes.push(volume);
+    }
+
+    pub fn camera(&self) -> &Camera {
+        &self.camera
+    }
+
+    pub fn set_camera(&mut self, camera: Camera) {
+        self.camera = camera;
+    }
+
+    pub fn effects(&self) -> &[Effect] {
+        &self.effects
+    }
+
+    pub fn add_effect(&mut self, effect: Effect) {
+        self.effects.push(effect);
+    }
+
+    pub fn set_background(&mut self, background: Background) {
+        self.background = background;
+    }
+}
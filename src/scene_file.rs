@@ -0,0 +1,728 @@
+// plain text scene description, one thing per line:
+//
+//   # comments start with a hash
+//   texture veins marble low=0.1,0.1,0.12 high=0.9,0.9,0.85 scale=1.5 strength=0.6
+//   material stone color=1,1,1 albedo=0.6,0.3,0.1,0 specular=50 diffuse_map=veins
+//   sphere center=-3,0,-16 radius=2 material=stone
+//   sphere center=4,2,-20 material=stone scale=3,1,1 rotate=0,0,30
+//   box min=-1,-4,-12 max=1,-2,-10 material=stone
+//   box center=3,-3,-14 size=2,2,2 rotate=0,45,0 material=stone
+//   cylinder base=0,-4,-15 radius=1 height=3 material=stone
+//   cone base=-3,-4,-15 radius=1 height=2 material=stone
+//   disk center=0,5,-15 normal=0,-1,0 radius=2 material=stone
+//   torus center=0,-3,-15 major=2 minor=0.5 rotate=90,0,0 material=stone
+//   box center=0,0,-15 size=2,2,2 as=block
+//   cylinder base=0,-2,-15 radius=0.5 height=4 as=hole
+//   csg difference a=block b=hole material=stone
+//   sdf blob sphere center=0,0,-15 radius=2
+//   sdf lumpy displace shape=blob amplitude=0.2 scale=3
+//   marched sdf=lumpy material=stone
+//   mesh pine path=pine.obj material=needles
+//   instance mesh=pine translate=3,-4,-20 scale=1.5
+//   sphere center=0,0,-15 radius=3 as=cloud
+//   volume shape=cloud scattering=0.4,0.4,0.4 absorption=0.05,0.05,0.05 g=0.2
+//   fog scattering=0.02,0.02,0.02 g=0.5 reach=60
+//   sphere center=-2,0,-15 center_end=2,0,-15 radius=1 material=stone
+//   box center=0,-3,-15 size=1,1,1 rotate_end=0,90,0 translate_end=0,2,0 material=stone
+//   camera position=0,2,5 look_at=0,0,-16 fov=50 shutter=0,1 samples=16
+//   light position=1:-20,20,20/48:20,20,20 intensity=bezier/1:0.5/24:2/48:0.5
+//   light position=-20,20,20 intensity=1.5
+//   background sky model=preetham turbidity=3 sun_elevation=35
+//   floor material=tiles
+//
+// everything after the keyword (and name, for textures and materials) is key=value, vectors being comma separated
+// textures have to be declared before the materials using them, and materials before the objects
+// a mesh is loaded once and only shows up where instances of it are placed, in its own material unless they give another
+// objects can be given a transform: scaled (by one factor or one per axis), then rotated (degrees around x, then y, then z), then moved
+// scaling and rotating happen around the object's own center (the middle of the base for cylinders and cones, the origin for meshes and csg)
+// giving any of those a _end version (scale_end, rotate_end, translate_end, or center_end for spheres) makes the object move
+// it's where the plain ones put it at time 0 and where the _end ones do at time 1, and in between at times in between
+// the camera's shutter is open for some stretch of that time, and samples rays per pixel spread over it blur whatever moves
+// the camera sits at position looking towards look_at, with a vertical field of view of fov degrees
+// any number (or list of them) can be keyframed instead, as frame:value keys separated by slashes, see keyframe.rs
+// the scene is read again for every frame of an animation, so that goes for camera, lights, transforms and materials alike
+// any object given as=<name> isn't placed in the scene but kept as a solid for csg (union, intersection or difference) to combine
+// solids only need a material if they're placed, and each can only be used once, its name being free again after that
+// sdf lines build up signed distance functions the same way, used up by whatever refers to them, and marched places one in the scene
+// glowing csg and marched objects find points on themselves to light the scene from by trial and error, which makes them slow lights, fractals most of all
+// volume fills a solid with a medium instead (which hides the solid itself), while fog fills the whole scene with one
+// both take absorption and scattering per unit of distance and a henyey-greenstein g between -1 (backwards) and 1 (forwards)
+// fog's reach is how far away the background is taken to be, anything past it would be lost in the fog entirely
+// post lines add effects to the finished picture, applied in the order they're listed, see postprocess.rs:
+//   post bloom threshold=1 strength=0.5 radius=12
+//   post vignette strength=0.4
+//   post chromatic_aberration amount=0.005
+//   post grain amount=0.05 seed=1
+
+use std::collections::HashMap;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+use std::sync::Arc;
+use crate::background::{Background, EnvironmentMap};
+use crate::camera::Camera;
+use crate::geometry::{Placement, Vec3f, Vec4f};
+use crate::image::Image;
+use crate::keyframe::Track;
+use crate::csg::{Csg, CsgOp};
+use crate::light::Light;
+use crate::mesh::{Instance, Mesh};
+use crate::material::Material;
+use crate::medium::{Medium, Volume};
+use crate::object::{Cone, Cuboid, Cylinder, Disk, Object, Sphere, Torus, Transformed};
+use crate::postprocess::Effect;
+use crate::scene::Scene;
+use crate::sdf::{Sdf, SdfObject};
+use crate::sky::{self, Sky, SkyModel};
+use crate::texture::{Texture, WrapMode};
+
+// the key=value pairs of one line
+// every key has to be picked up by the time we're done with the line, so typos don't go unnoticed
+struct Properties {
+    values: HashMap<String, String>,
+}
+
+impl Properties {
+    // keyframed values are looked up for the frame being loaded straight away, so nothing else needs to know about them
+    fn parse(tokens: &[&str], frame: f32) -> Result<Self, String> {
+        let mut values = HashMap::new();
+        for token in tokens {
+            let (key, value) = token.split_once('=').ok_or_else(|| format!("expected key=value, got {}", token))?;
+            let value = match Track::parse(value) {
+                Some(track) => {
+                    let numbers = track.map_err(|msg| format!("{}: {}", key, msg))?.at(frame);
+                    numbers.iter().map(|n| n.to_string()).collect::<Vec<String>>().join(",")
+                }
+                None => value.to_string(),
+            };
+            if values.insert(key.to_string(), value).is_some() {
+                return Err(format!("{} given twice", key));
+            }
+        }
+
+        Ok(Properties { values })
+    }
+
+    fn text(&mut self, key: &str) -> Option<String> {
+        self.values.remove(key)
+    }
+
+    fn required_text(&mut self, key: &str) -> Result<String, String> {
+        self.text(key).ok_or_else(|| format!("missing {}", key))
+    }
+
+    fn floats(&mut self, key: &str, count: usize) -> Result<Option<Vec<f32>>, String> {
+        let text = match self.text(key) {
+            Some(text) => text,
+            None => return Ok(None),
+        };
+        let values = text.split(',')
+            .map(|v| v.trim().parse::<f32>().map_err(|_| format!("{} should be a number, got {}", key, v)))
+            .collect::<Result<Vec<f32>, String>>()?;
+
+        if values.len() != count {
+            return Err(format!("{} should have {} components, got {}", key, count, values.len()));
+        }
+        Ok(Some(values))
+    }
+
+    fn float(&mut self, key: &str, default: f32) -> Result<f32, String> {
+        Ok(self.floats(key, 1)?.map_or(default, |v| v[0]))
+    }
+
+    fn int(&mut self, key: &str, default: u32) -> Result<u32, String> {
+        match self.text(key) {
+            Some(text) => text.parse().map_err(|_| format!("{} should be a whole number, got {}", key, text)),
+            None => Ok(default),
+        }
+    }
+
+    fn vec3(&mut self, key: &str, default: &Vec3f) -> Result<Vec3f, String> {
+        Ok(self.floats(key, 3)?.map_or_else(|| default.clone(), |v| Vec3f::from(&v)))
+    }
+
+    // one factor for all axes, or one for each
+    fn scale(&mut self, key: &str) -> Result<Option<Vec3f>, String> {
+        let text = match self.text(key) {
+            None => return Ok(None),
+            Some(text) => text,
+        };
+        let factors = text.split(',')
+            .map(|v| v.trim().parse::<f32>().map_err(|_| format!("{} should be a number, got {}", key, v)))
+            .collect::<Result<Vec<f32>, String>>()?;
+        match factors.len() {
+            1 => Ok(Some(Vec3f::new3f(factors[0], factors[0], factors[0]))),
+            3 => Ok(Some(Vec3f::from(&factors))),
+            n => Err(format!("{} should have 1 or 3 components, got {}", key, n)),
+        }
+    }
+
+    fn required_vec3(&mut self, key: &str) -> Result<Vec3f, String> {
+        self.floats(key, 3)?.map(|v| Vec3f::from(&v)).ok_or_else(|| format!("missing {}", key))
+    }
+
+    fn finish(self) -> Result<(), String> {
+        match self.values.keys().next() {
+            Some(key) => Err(format!("unknown property {}", key)),
+            None => Ok(()),
+        }
+    }
+}
+
+// everything declared so far while reading a file
+struct Loader<'a> {
+    // relative paths in the file are relative to the file itself
+    base_dir: &'a Path,
+    // which frame of the animation we're loading, for any keyframed values
+    frame: f32,
+    textures: HashMap<String, Arc<Texture>>,
+    // shared, so instances of meshes don't each need their own copy
+    materials: HashMap<String, Arc<Material>>,
+    // meshes along with the material their instances get by default
+    meshes: HashMap<String, (Arc<Mesh>, Option<Arc<Material>>)>,
+    lights: Vec<Light>,
+    objects: Vec<Box<dyn Object>>,
+    // named with as=, waiting to be combined by csg
+    solids: HashMap<String, Box<dyn Object>>,
+    sdfs: HashMap<String, Sdf>,
+    volumes: Vec<Volume>,
+    camera: Option<Camera>,
+    effects: Vec<Effect>,
+    background: Option<Background>,
+    floor: Option<Material>,
+}
+
+impl<'a> Loader<'a> {
+    fn texture(&self, name: &str) -> Result<Arc<Texture>, String> {
+        self.textures.get(name).cloned().ok_or_else(|| format!("no texture called {}", name))
+    }
+
+    fn material(&self, name: &str) -> Result<Arc<Material>, String> {
+        self.materials.get(name).cloned().ok_or_else(|| format!("no material called {}", name))
+    }
+
+    // textures are only ever about color, so any alpha channel gets left behind
+    fn load_image(&self, path: &str) -> Result<Image, String> {
+        let full_path = self.base_dir.join(path);
+        Image::load(&full_path.to_string_lossy())
+            .map(Image::without_alpha)
+            .map_err(|err| format!("could not load {}: {}", full_path.display(), err))
+    }
+
+    fn line(&mut self, tokens: &[&str]) -> Result<(), String> {
+        let named = |tokens: &[&str]| tokens.get(1).map(|name| name.to_string()).ok_or_else(|| format!("{} needs a name", tokens[0]));
+
+        match tokens[0] {
+            "texture" => {
+                let name = named(tokens)?;
+                let kind = tokens.get(2).ok_or("texture needs a kind")?;
+                let mut props = Properties::parse(&tokens[3..], self.frame)?;
+                let texture = self.parse_texture(kind, &mut props)?;
+                props.finish()?;
+                self.textures.insert(name, Arc::new(texture));
+            }
+            "material" => {
+                let name = named(tokens)?;
+                let mut props = Properties::parse(&tokens[2..], self.frame)?;
+                // numbered in the order they're declared, for the material id aov
+                let id = self.materials.get(&name).map_or(self.materials.len() as u32 + 1, |material| material.id());
+                let material = self.parse_material(&mut props)?.with_id(id);
+                props.finish()?;
+                self.materials.insert(name, Arc::new(material));
+            }
+            "sphere" | "box" | "cylinder" | "cone" | "disk" | "torus" => {
+                let mut props = Properties::parse(&tokens[1..], self.frame)?;
+                let name = props.text("as");
+                let object = self.parse_shape(tokens[0], name.is_some(), &mut props)?;
+                props.finish()?;
+                self.add_object(name, object)?;
+            }
+            "csg" => {
+                let op = match tokens.get(1) {
+                    Some(&"union") => CsgOp::Union,
+                    Some(&"intersection") => CsgOp::Intersection,
+                    Some(&"difference") => CsgOp::Difference,
+                    _ => return Err("csg needs one of union, intersection or difference".to_string()),
+                };
+                let mut props = Properties::parse(&tokens[2..], self.frame)?;
+                let name = props.text("as");
+                let a = self.take_solid(&props.required_text("a")?)?;
+                let b = self.take_solid(&props.required_text("b")?)?;
+                let material = self.object_material(name.is_some(), &mut props)?;
+                let object = self.transformed(Csg::new(op, a, b, &material), &Vec3f::zero(), &mut props)?;
+                props.finish()?;
+                self.add_object(name, object)?;
+            }
+            "mesh" => {
+                let name = named(tokens)?;
+                let mut props = Properties::parse(&tokens[2..], self.frame)?;
+                let full_path = self.base_dir.join(props.required_text("path")?);
+                let mesh = Mesh::load_obj(&full_path.to_string_lossy()).map_err(|err| format!("could not load {}: {}", full_path.display(), err))?;
+                let material = props.text("material").map(|m| self.material(&m)).transpose()?;
+                props.finish()?;
+                self.meshes.insert(name, (Arc::new(mesh), material));
+            }
+            "instance" => {
+                let mut props = Properties::parse(&tokens[1..], self.frame)?;
+                let mesh_name = props.required_text("mesh")?;
+                let (mesh, default_material) = self.meshes.get(&mesh_name).ok_or_else(|| format!("no mesh called {}", mesh_name))?;
+                let material = match props.text("material") {
+                    Some(name) => self.material(&name)?,
+                    None => default_material.clone().ok_or_else(|| format!("mesh {} has no material of its own, so instances need one", mesh_name))?,
+                };
+                let name = props.text("as");
+                let instance = Instance::new(mesh.clone(), material);
+                let object = self.transformed(instance, &Vec3f::zero(), &mut props)?;
+                props.finish()?;
+                self.add_object(name, object)?;
+            }
+            "sdf" => {
+                let name = named(tokens)?;
+                let kind = tokens.get(2).ok_or("sdf needs a kind")?;
+                let mut props = Properties::parse(&tokens[3..], self.frame)?;
+                let sdf = self.parse_sdf(kind, &mut props)?;
+                props.finish()?;
+                if self.sdfs.contains_key(&name) {
+                    return Err(format!("there's already an sdf called {}", name));
+                }
+                self.sdfs.insert(name, sdf);
+            }
+            "marched" => {
+                let mut props = Properties::parse(&tokens[1..], self.frame)?;
+                let name = props.text("as");
+                let sdf = self.take_sdf(&props.required_text("sdf")?)?;
+                let material = self.object_material(name.is_some(), &mut props)?;
+                let pivot = sdf.bounds().0;
+                let object = self.transformed(SdfObject::new(sdf, &material), &pivot, &mut props)?;
+                props.finish()?;
+                self.add_object(name, object)?;
+            }
+            "volume" => {
+                let mut props = Properties::parse(&tokens[1..], self.frame)?;
+                let boundary = self.take_solid(&props.required_text("shape")?)?;
+                let medium = self.parse_medium(&mut props)?;
+                props.finish()?;
+                self.volumes.push(Volume::bounded(medium, boundary));
+            }
+            "fog" => {
+                let mut props = Properties::parse(&tokens[1..], self.frame)?;
+                let medium = self.parse_medium(&mut props)?;
+                let reach = props.float("reach", 100.0)?;
+                props.finish()?;
+                self.volumes.push(Volume::global(medium, reach));
+            }
+            "camera" => {
+                let mut props = Properties::parse(&tokens[1..], self.frame)?;
+                let position = props.floats("position", 3)?;
+                let look_at = props.floats("look_at", 3)?;
+                let up = props.floats("up", 3)?;
+                let fov = props.floats("fov", 1)?;
+                // the default camera doesn't go through looking_at, so that it stays exactly the way it's always been
+                let mut camera = if position.is_none() && look_at.is_none() && up.is_none() && fov.is_none() {
+                    Camera::default()
+                } else {
+                    Camera::looking_at(&position.map_or_else(Vec3f::zero, |p| Vec3f::from(&p)),
+                                       &look_at.map_or_else(|| Vec3f::new3f(0.0, 0.0, -1.0), |p| Vec3f::from(&p)),
+                                       &up.map_or_else(|| Vec3f::new3f(0.0, 1.0, 0.0), |u| Vec3f::from(&u)),
+                                       fov.map_or(60.0, |f| f[0]).to_radians())
+                };
+                if let Some(shutter) = props.floats("shutter", 2)? {
+                    camera = camera.with_shutter(shutter[0], shutter[1]);
+                }
+                camera = camera.with_samples(props.int("samples", 1)?);
+                props.finish()?;
+                self.camera = Some(camera);
+            }
+            "post" => {
+                let kind = tokens.get(1).ok_or("post needs one of bloom, vignette, chromatic_aberration or grain")?;
+                let mut props = Properties::parse(&tokens[2..], self.frame)?;
+                let effect = match *kind {
+                    "bloom" => Effect::Bloom {
+                        threshold: props.float("threshold", 1.0)?,
+                        strength: props.float("strength", 0.5)?,
+                        radius: props.float("radius", 12.0)?,
+                    },
+                    "vignette" => Effect::Vignette { strength: props.float("strength", 0.4)? },
+                    "chromatic_aberration" => Effect::ChromaticAberration { amount: props.float("amount", 0.005)? },
+                    "grain" => Effect::FilmGrain { amount: props.float("amount", 0.05)?, seed: props.int("seed", 1)? as u64 },
+                    _ => return Err("post needs one of bloom, vignette, chromatic_aberration or grain".to_string()),
+                };
+                props.finish()?;
+                self.effects.push(effect);
+            }
+            "light" => {
+                let mut props = Properties::parse(&tokens[1..], self.frame)?;
+                let light = Light::new(&props.required_vec3("position")?, props.float("intensity", 1.0)?);
+                props.finish()?;
+                self.lights.push(light);
+            }
+            "background" => {
+                let kind = tokens.get(1).ok_or("background needs a kind")?;
+                let mut props = Properties::parse(&tokens[2..], self.frame)?;
+                let background = self.parse_background(kind, &mut props)?;
+                props.finish()?;
+                self.background = Some(background);
+            }
+            "floor" => {
+                let mut props = Properties::parse(&tokens[1..], self.frame)?;
+                let material = (*self.material(&props.required_text("material")?)?).clone();
+                props.finish()?;
+                self.floor = Some(material);
+            }
+            other => return Err(format!("don't know what a {} is", other)),
+        }
+
+        Ok(())
+    }
+
+    // into the scene, or put aside as a solid if it has a name
+    // names can be reused once csg has taken the solid, but not while it's still waiting
+    fn add_object(&mut self, name: Option<String>, object: Box<dyn Object>) -> Result<(), String> {
+        match name {
+            Some(name) if self.solids.contains_key(&name) => return Err(format!("there's already a solid called {}", name)),
+            Some(name) => {
+                self.solids.insert(name, object);
+            }
+            None => self.objects.push(object),
+        }
+        Ok(())
+    }
+
+    fn object_material(&self, is_solid: bool, props: &mut Properties) -> Result<Arc<Material>, String> {
+        match props.text("material") {
+            Some(name) => self.material(&name),
+            // csg gives the result its own material, so this never gets seen
+            None if is_solid => Ok(Arc::new(Material::new(&Vec3f::new3f(1.0, 1.0, 1.0), &Vec4f::from(&[1.0, 0.0, 0.0, 0.0]), 1.0, 1.0))),
+            None => Err("missing material".to_string()),
+        }
+    }
+
+    fn take_solid(&mut self, name: &str) -> Result<Box<dyn Object>, String> {
+        self.solids.remove(name).ok_or_else(|| format!("no solid called {} (or it's already been used)", name))
+    }
+
+    fn take_sdf(&mut self, name: &str) -> Result<Sdf, String> {
+        self.sdfs.remove(name).ok_or_else(|| format!("no sdf called {} (or it's already been used)", name))
+    }
+
+    fn parse_sdf(&mut self, kind: &str, props: &mut Properties) -> Result<Sdf, String> {
+        Ok(match kind {
+            "sphere" => Sdf::Sphere { center: props.required_vec3("center")?, radius: props.float("radius", 1.0)? },
+            "box" => Sdf::Cuboid {
+                center: props.required_vec3("center")?,
+                half_size: &props.vec3("size", &Vec3f::new3f(1.0, 1.0, 1.0))? * 0.5,
+                rounding: props.float("rounding", 0.0)?,
+            },
+            "torus" => Sdf::Torus { center: props.required_vec3("center")?, major: props.float("major", 1.0)?, minor: props.float("minor", 0.25)? },
+            "capsule" => Sdf::Capsule { a: props.required_vec3("from")?, b: props.required_vec3("to")?, radius: props.float("radius", 0.5)? },
+            "mandelbulb" => Sdf::Mandelbulb {
+                center: props.required_vec3("center")?,
+                scale: props.float("scale", 1.0)?,
+                power: props.float("power", 8.0)?,
+                iterations: props.int("iterations", 8)?,
+            },
+            "union" | "intersection" | "difference" => {
+                let a = Box::new(self.take_sdf(&props.required_text("a")?)?);
+                let b = Box::new(self.take_sdf(&props.required_text("b")?)?);
+                let smoothness = props.float("smooth", 0.0)?;
+                match kind {
+                    "union" => Sdf::Union { a, b, smoothness },
+                    "intersection" => Sdf::Intersection { a, b, smoothness },
+                    _ => Sdf::Difference { a, b, smoothness },
+                }
+            }
+            "displace" => Sdf::Displace {
+                shape: Box::new(self.take_sdf(&props.required_text("shape")?)?),
+                amplitude: props.float("amplitude", 0.1)?,
+                scale: props.float("scale", 1.0)?,
+            },
+            other => return Err(format!("unknown sdf kind {}", other)),
+        })
+    }
+
+    fn parse_shape(&self, kind: &str, is_solid: bool, props: &mut Properties) -> Result<Box<dyn Object>, String> {
+        let material = self.object_material(is_solid, props)?;
+
+        match kind {
+            "sphere" => {
+                let center = props.required_vec3("center")?;
+                let radius = props.float("radius", 1.0)?;
+                let sphere = match props.floats("center_end", 3)? {
+                    Some(end) => Sphere::moving(center.clone(), &Vec3f::from(&end), radius, &material),
+                    None => Sphere::new(center.clone(), radius, &material),
+                };
+                self.transformed(sphere, &center, props)
+            }
+            "box" => {
+                let (min, max) = match props.floats("size", 3)? {
+                    Some(size) => {
+                        let half = &Vec3f::from(&size) * 0.5;
+                        let center = props.required_vec3("center")?;
+                        (&center - &half, &center + &half)
+                    }
+                    None => (props.required_vec3("min")?, props.required_vec3("max")?),
+                };
+                let center = &(&min + &max) * 0.5;
+                self.transformed(Cuboid::new(&min, &max, &material), &center, props)
+            }
+            "cylinder" | "cone" => {
+                let base = props.required_vec3("base")?;
+                let radius = props.float("radius", 1.0)?;
+                let height = props.float("height", 1.0)?;
+                if kind == "cylinder" {
+                    self.transformed(Cylinder::new(base.clone(), radius, height, &material), &base, props)
+                } else {
+                    self.transformed(Cone::new(base.clone(), radius, height, &material), &base, props)
+                }
+            }
+            "disk" => {
+                let center = props.required_vec3("center")?;
+                let disk = Disk::new(center.clone(), &props.vec3("normal", &Vec3f::new3f(0.0, 1.0, 0.0))?, props.float("radius", 1.0)?, &material);
+                self.transformed(disk, &center, props)
+            }
+            _ => {
+                let center = props.required_vec3("center")?;
+                let torus = Torus::new(center.clone(), props.float("major", 1.0)?, props.float("minor", 0.25)?, &material);
+                self.transformed(torus, &center, props)
+            }
+        }
+    }
+
+    // wraps the object in whatever transform the line gives it, if any, scaling and rotating it around pivot
+    // the same keys ending in _end make it move, from the first transform at time 0 to the second at time 1
+    fn transformed<O: Object + 'static>(&self, object: O, pivot: &Vec3f, props: &mut Properties) -> Result<Box<dyn Object>, String> {
+        let scale = props.scale("scale")?;
+        let rotate = props.floats("rotate", 3)?;
+        let translate = props.floats("translate", 3)?;
+        let scale_end = props.scale("scale_end")?;
+        let rotate_end = props.floats("rotate_end", 3)?;
+        let translate_end = props.floats("translate_end", 3)?;
+        let moves = scale_end.is_some() || rotate_end.is_some() || translate_end.is_some();
+        if !moves && scale.is_none() && rotate.is_none() && translate.is_none() {
+            return Ok(Box::new(object));
+        }
+
+        let start = Placement {
+            pivot: pivot.clone(),
+            scale: scale.unwrap_or_else(|| Vec3f::new3f(1.0, 1.0, 1.0)),
+            rotation: rotate.map_or_else(Vec3f::zero, |r| Vec3f::from(&r)),
+            translation: translate.map_or_else(Vec3f::zero, |t| Vec3f::from(&t)),
+        };
+        if !moves {
+            let transformed = Transformed::new(object, start.matrix()).ok_or("transform squashes the object flat")?;
+            return Ok(Box::new(transformed));
+        }
+
+        // whatever doesn't change keeps its starting value
+        let end = Placement {
+            pivot: pivot.clone(),
+            scale: scale_end.unwrap_or_else(|| start.scale.clone()),
+            rotation: rotate_end.map_or_else(|| start.rotation.clone(), |r| Vec3f::from(&r)),
+            translation: translate_end.map_or_else(|| start.translation.clone(), |t| Vec3f::from(&t)),
+        };
+        let transformed = Transformed::moving(object, start, end).ok_or("transform squashes the object flat")?;
+        Ok(Box::new(transformed))
+    }
+
+    fn parse_texture(&self, kind: &str, props: &mut Properties) -> Result<Texture, String> {
+        let white = Vec3f::new3f(1.0, 1.0, 1.0);
+        let black = Vec3f::zero();
+
+        Ok(match kind {
+            "image" => {
+                let image = self.load_image(&props.required_text("path")?)?;
+                let wrap = match props.text("wrap").as_deref() {
+                    None | Some("repeat") => WrapMode::Repeat,
+                    Some("clamp") => WrapMode::Clamp,
+                    Some(other) => return Err(format!("wrap should be repeat or clamp, got {}", other)),
+                };
+                Texture::image(Arc::new(image), wrap, props.float("scale", 1.0)?)
+            }
+            "checker" => Texture::Checker {
+                even: props.vec3("even", &white)?,
+                odd: props.vec3("odd", &black)?,
+                scale: props.float("scale", 1.0)?,
+            },
+            "noise" => Texture::Noise {
+                low: props.vec3("low", &black)?,
+                high: props.vec3("high", &white)?,
+                scale: props.float("scale", 1.0)?,
+            },
+            "turbulence" => Texture::Turbulence {
+                low: props.vec3("low", &black)?,
+                high: props.vec3("high", &white)?,
+                scale: props.float("scale", 1.0)?,
+                octaves: props.int("octaves", 6)?,
+            },
+            "marble" => Texture::Marble {
+                low: props.vec3("low", &black)?,
+                high: props.vec3("high", &white)?,
+                scale: props.float("scale", 1.0)?,
+                octaves: props.int("octaves", 6)?,
+                strength: props.float("strength", 0.5)?,
+            },
+            "wood" => Texture::Wood {
+                light: props.vec3("light", &Vec3f::new3f(0.8, 0.6, 0.35))?,
+                dark: props.vec3("dark", &Vec3f::new3f(0.45, 0.25, 0.1))?,
+                scale: props.float("scale", 4.0)?,
+                strength: props.float("strength", 1.0)?,
+            },
+            other => return Err(format!("unknown texture kind {}", other)),
+        })
+    }
+
+    fn parse_material(&self, props: &mut Properties) -> Result<Material, String> {
+        let albedo = props.floats("albedo", 4)?.unwrap_or_else(|| vec![1.0, 0.0, 0.0, 0.0]);
+        let mut material = Material::new(&props.vec3("color", &Vec3f::new3f(1.0, 1.0, 1.0))?,
+                                         &Vec4f::from(&albedo),
+                                         props.float("specular", 1.0)?,
+                                         props.float("ior", 1.0)?)
+            .with_emission(&props.vec3("emission", &Vec3f::zero())?, props.float("emission_strength", 1.0)?);
+
+        if let Some(name) = props.text("diffuse_map") {
+            material = material.with_diffuse_map(self.texture(&name)?);
+        }
+        if let Some(name) = props.text("specular_map") {
+            material = material.with_specular_map(self.texture(&name)?);
+        }
+        if let Some(name) = props.text("roughness_map") {
+            material = material.with_roughness_map(self.texture(&name)?);
+        }
+        if let Some(name) = props.text("normal_map") {
+            material = material.with_normal_map(self.texture(&name)?);
+        }
+        if let Some(name) = props.text("bump_map") {
+            material = material.with_bump_map(self.texture(&name)?, props.float("bump_strength", 1.0)?);
+        }
+
+        Ok(material)
+    }
+
+    fn parse_medium(&self, props: &mut Properties) -> Result<Medium, String> {
+        Ok(Medium::new(&props.vec3("absorption", &Vec3f::zero())?,
+                       &props.vec3("scattering", &Vec3f::zero())?,
+                       props.float("g", 0.0)?))
+    }
+
+    fn parse_background(&self, kind: &str, props: &mut Properties) -> Result<Background, String> {
+        Ok(match kind {
+            "flat" => Background::Color(props.required_vec3("color")?),
+            "envmap" => {
+                let image = self.load_image(&props.required_text("path")?)?;
+                Background::Environment(EnvironmentMap::new(image, props.float("rotation", 0.0)?.to_radians(), props.float("intensity", 1.0)?))
+            }
+            "sky" => {
+                let model = match props.text("model").as_deref() {
+                    None | Some("preetham") => SkyModel::Preetham { turbidity: props.float("turbidity", 3.0)? },
+                    Some("gradient") => SkyModel::Gradient {
+                        zenith: props.vec3("zenith", &Vec3f::new3f(0.25, 0.45, 0.9))?,
+                        horizon: props.vec3("horizon", &Vec3f::new3f(0.8, 0.85, 0.9))?,
+                    },
+                    Some(other) => return Err(format!("unknown sky model {}", other)),
+                };
+                let sun_dir = sky::sun_direction(props.float("sun_elevation", 35.0)?.to_radians(), props.float("sun_azimuth", -40.0)?.to_radians());
+                Background::sky(Sky::new(model,
+                                         &sun_dir,
+                                         props.float("sun_size", 1.0)?.to_radians(),
+                                         props.float("sun_intensity", 2.0)?,
+                                         props.float("intensity", 1.0)?))
+            }
+            other => return Err(format!("unknown background kind {}", other)),
+        })
+    }
+}
+
+/// read a scene from a file in the format described at the top of this file, as it is on the given frame
+/// scenes that don't say otherwise get the usual flat cyan background and no floor
+pub fn load(path: &str, default_background: Background, frame: f32) -> io::Result<Scene<dyn Object>> {
+    let source = std::fs::read_to_string(path)?;
+    let base_dir = Path::new(path).parent().unwrap_or_else(|| Path::new("."));
+    let mut loader = Loader {
+        base_dir,
+        frame,
+        textures: HashMap::new(),
+        materials: HashMap::new(),
+        meshes: HashMap::new(),
+        lights: Vec::new(),
+        objects: Vec::new(),
+        solids: HashMap::new(),
+        sdfs: HashMap::new(),
+        volumes: Vec::new(),
+        camera: None,
+        effects: Vec::new(),
+        background: None,
+        floor: None,
+    };
+
+    for (number, line) in source.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("");
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if tokens.is_empty() {
+            continue;
+        }
+
+        loader.line(&tokens).map_err(|msg| io::Error::new(ErrorKind::InvalidData, format!("{}:{}: {}", path, number + 1, msg)))?;
+    }
+
+    let mut scene = Scene::new(loader.lights, loader.objects, loader.background.unwrap_or(default_background), loader.floor);
+    for volume in loader.volumes {
+        scene.add_volume(volume);
+    }
+    if let Some(camera) = loader.camera {
+        scene.set_camera(camera);
+    }
+    for effect in loader.effects {
+        scene.add_effect(effect);
+    }
+
+    Ok(scene)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_source(name: &str, source: &str) -> io::Result<Scene<dyn Object>> {
+        let path = std::env::temp_dir().join(format!("tinyrt_{}_{}.scene", std::process::id(), name)).to_string_lossy().into_owned();
+        std::fs::write(&path, source).unwrap();
+        let scene = load(&path, Background::Color(Vec3f::zero()), 0.0);
+        std::fs::remove_file(&path).unwrap();
+        scene
+    }
+
+    #[test]
+    fn duplicate_solid_names() {
+        let source = "material red color=1,0,0\n\
+                      sphere center=0,0,-5 radius=1 as=ball\n\
+                      sphere center=0,0,-6 radius=1 as=ball\n";
+        let err = load_source("duplicate", source).err().expect("the second ball replaced the first");
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().ends_with(":3: there's already a solid called ball"), "{}", err);
+
+        // once csg has used it up, the name is free again
+        let source = "material red color=1,0,0\n\
+                      sphere center=0,0,-5 radius=1 as=ball\n\
+                      box center=0,0,-5 size=1,1,1 as=block\n\
+                      csg union a=ball b=block as=both\n\
+                      sphere center=0,0,-6 radius=1 as=ball\n\
+                      csg union a=ball b=both material=red\n";
+        assert!(load_source("reused", source).is_ok());
+    }
+
+    // the scene file version of the built in scene should have everything it has
+    #[test]
+    fn default_scene_file() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes/default.scene");
+        let scene = load(&path.to_string_lossy(), Background::Color(Vec3f::zero()), 0.0).unwrap();
+        let built_in = crate::default_scene();
+        assert_eq!((scene.objects().len(), scene.lights().len()), (built_in.objects().len(), built_in.lights().len()));
+
+        let ids = |scene: &Scene<dyn Object>| scene.objects().iter().map(|object| object.get_material().id()).collect::<Vec<u32>>();
+        assert_eq!(ids(&scene), ids(&built_in));
+        let (floor, built_in_floor) = (scene.floor().unwrap(), built_in.floor().unwrap());
+        assert_eq!(floor.id(), built_in_floor.id());
+        for point in [Vec3f::new3f(0.5, -4.0, -15.5), Vec3f::new3f(2.5, -4.0, -15.5)] {
+            let uv = crate::geometry::Vec2f::from(&[0.0, 0.0]);
+            assert_eq!(floor.resolve(&uv, &point).color(), built_in_floor.resolve(&uv, &point).color());
+        }
+    }
+}
@@ -0,0 +1,399 @@
+// shapes described by signed distance functions: how far a point is from the surface, negative inside
+// rendered by sphere tracing, stepping along the ray by the distance to the nearest surface until we're on it
+// see https://iquilezles.org/articles/distfunctions/ for where most of these come from
+
+use std::cell::OnceCell;
+use std::f32::consts::PI;
+use crate::geometry::{Vec2f, Vec3f};
+use crate::material::Material;
+use crate::noise;
+use crate::object::{Object, SurfaceHit};
+use crate::sampling::{self, Rng};
+
+// close enough to count as on the surface
+static SURFACE_DISTANCE: f32 = 1e-4;
+static MAX_STEPS: u32 = 512;
+// offset for the finite differences that give the normal
+static GRADIENT_STEP: f32 = 1e-3;
+// how many lines through the bounds area() counts the crossings of
+static AREA_LINES: u32 = 4096;
+// how many lines sample_surface tries before giving up on finding a point
+static MAX_TRIES: u32 = 64;
+// the share of lines that get all their crossings counted, the odd few through the most convoluted bits of fractals don't
+static COUNTED_LINES: f32 = 0.99;
+
+#[derive(Debug)]
+pub enum Sdf {
+    Sphere { center: Vec3f, radius: f32 },
+    // box with its edges rounded off by rounding, which also grows it by that much
+    Cuboid { center: Vec3f, half_size: Vec3f, rounding: f32 },
+    // lying flat around the y axis, like the torus object
+    Torus { center: Vec3f, major: f32, minor: f32 },
+    // sausage between two points
+    Capsule { a: Vec3f, b: Vec3f, radius: f32 },
+    // the 3d mandelbrot fractal, about scale across, power 8 being the classic look
+    Mandelbulb { center: Vec3f, scale: f32, power: f32, iterations: u32 },
+    // smoothness blends the two together over about that distance instead of leaving a crease, 0 for none
+    Union { a: Box<Sdf>, b: Box<Sdf>, smoothness: f32 },
+    Intersection { a: Box<Sdf>, b: Box<Sdf>, smoothness: f32 },
+    // a with b carved out of it
+    Difference { a: Box<Sdf>, b: Box<Sdf>, smoothness: f32 },
+    // pushes the surface in and out by up to amplitude with perlin noise, scale being how many bumps per unit
+    Displace { shape: Box<Sdf>, amplitude: f32, scale: f32 },
+}
+
+// polynomial smooth minimum, which turns into the plain one as k goes to 0
+fn smooth_min(a: f32, b: f32, k: f32) -> f32 {
+    if k <= 0.0 {
+        return a.min(b);
+    }
+    let h = (0.5 + 0.5 * (b - a) / k).clamp(0.0, 1.0);
+    b * (1.0 - h) + a * h - k * h * (1.0 - h)
+}
+
+fn smooth_max(a: f32, b: f32, k: f32) -> f32 {
+    -smooth_min(-a, -b, k)
+}
+
+// smallest sphere around both spheres
+fn enclosing(a: (Vec3f, f32), b: (Vec3f, f32)) -> (Vec3f, f32) {
+    let between = &b.0 - &a.0;
+    let distance = between.magnitude();
+    if distance + b.1 <= a.1 {
+        return a;
+    }
+    if distance + a.1 <= b.1 {
+        return b;
+    }
+
+    let radius = (distance + a.1 + b.1) / 2.0;
+    let center = &a.0 + &(&between * ((radius - a.1) / distance));
+    (center, radius)
+}
+
+impl Sdf {
+    /// signed distance from p to the surface, or at least never more than that, which is all sphere tracing needs
+    pub fn distance(&self, p: &Vec3f) -> f32 {
+        match self {
+            Sdf::Sphere { center, radius } => (p - center).magnitude() - radius,
+            Sdf::Cuboid { center, half_size, rounding } => {
+                let q: Vec<f32> = (0..3).map(|i| (p[i] - center[i]).abs() - half_size[i]).collect();
+                let outside = q.iter().map(|v| v.max(0.0) * v.max(0.0)).sum::<f32>().sqrt();
+                let inside = q[0].max(q[1]).max(q[2]).min(0.0);
+                outside + inside - rounding
+            }
+            Sdf::Torus { center, major, minor } => {
+                let d = p - center;
+                let ring = (d[0] * d[0] + d[2] * d[2]).sqrt() - major;
+                (ring * ring + d[1] * d[1]).sqrt() - minor
+            }
+            Sdf::Capsule { a, b, radius } => {
+                let pa = p - a;
+                let ba = b - a;
+                let h = (pa.dot(&ba) / ba.dot(&ba)).clamp(0.0, 1.0);
+                (&pa - &(&ba * h)).magnitude() - radius
+            }
+            Sdf::Mandelbulb { center, scale, power, iterations } => {
+                let local = &(p - center) * (1.0 / scale);
+                mandelbulb(&local, *power, *iterations) * scale
+            }
+            Sdf::Union { a, b, smoothness } => smooth_min(a.distance(p), b.distance(p), *smoothness),
+            Sdf::Intersection { a, b, smoothness } => smooth_max(a.distance(p), b.distance(p), *smoothness),
+            Sdf::Difference { a, b, smoothness } => smooth_max(a.distance(p), -b.distance(p), *smoothness),
+            Sdf::Displace { shape, amplitude, scale } => shape.distance(p) + amplitude * noise::perlin(&(p * *scale)),
+        }
+    }
+
+    /// sphere (center, radius) the whole surface fits in
+    pub fn bounds(&self) -> (Vec3f, f32) {
+        match self {
+            Sdf::Sphere { center, radius } => (center.clone(), *radius),
+            Sdf::Cuboid { center, half_size, rounding } => (center.clone(), half_size.magnitude() + rounding),
+            Sdf::Torus { center, major, minor } => (center.clone(), major + minor),
+            Sdf::Capsule { a, b, radius } => (&(a + b) * 0.5, (b - a).magnitude() / 2.0 + radius),
+            // the bulb itself stays within about 1.2 of its center
+            Sdf::Mandelbulb { center, scale, .. } => (center.clone(), 1.25 * scale),
+            Sdf::Union { a, b, smoothness } => {
+                let (center, radius) = enclosing(a.bounds(), b.bounds());
+                (center, radius + smoothness)
+            }
+            Sdf::Intersection { a, .. } | Sdf::Difference { a, .. } => a.bounds(),
+            Sdf::Displace { shape, amplitude, .. } => {
+                let (center, radius) = shape.bounds();
+                (center, radius + amplitude.abs())
+            }
+        }
+    }
+
+    // how much faster than 1 the distance can change per unit moved
+    // exact distances have 1, blending and displacing bend the field so sphere tracing has to take shorter steps to not overshoot
+    fn steepness(&self) -> f32 {
+        match self {
+            Sdf::Sphere { .. } | Sdf::Cuboid { .. } | Sdf::Torus { .. } | Sdf::Capsule { .. } => 1.0,
+            // the distance estimate overshoots a bit close to the surface
+            Sdf::Mandelbulb { .. } => 1.5,
+            Sdf::Union { a, b, .. } | Sdf::Intersection { a, b, .. } | Sdf::Difference { a, b, .. } => a.steepness().max(b.steepness()),
+            // perlin noise changes by up to about 2.5 per unit
+            Sdf::Displace { shape, amplitude, scale } => shape.steepness() + 2.5 * amplitude.abs() * scale,
+        }
+    }
+}
+
+// distance estimate for the mandelbulb of the given power centered at the origin
+// see http://blog.hvidtfeldts.net/index.php/2011/09/distance-estimated-3d-fractals-v-the-mandelbulb-different-de-approximations/
+fn mandelbulb(p: &Vec3f, power: f32, iterations: u32) -> f32 {
+    let (mut x, mut y, mut z) = (p[0], p[1], p[2]);
+    let mut dr = 1.0;
+    let mut r = 0.0;
+
+    for _ in 0..iterations {
+        r = (x * x + y * y + z * z).sqrt();
+        if r > 2.0 {
+            break;
+        }
+
+        // raise to the power in spherical coordinates, then add the starting point back like z^n + c
+        let theta = (z / r.max(1e-9)).clamp(-1.0, 1.0).acos() * power;
+        let phi = y.atan2(x) * power;
+        dr = r.powf(power - 1.0) * power * dr + 1.0;
+        let zr = r.powf(power);
+        x = zr * theta.sin() * phi.cos() + p[0];
+        y = zr * theta.sin() * phi.sin() + p[1];
+        z = zr * theta.cos() + p[2];
+    }
+
+    0.5 * r.max(1e-9).ln() * r / dr
+}
+
+/// a signed distance function, marched as an object
+/// uvs are the sphere's, taken around the middle of its bounds
+#[derive(Debug)]
+pub struct SdfObject {
+    sdf: Sdf,
+    material: Material,
+    // cached, these get asked for on every ray
+    center: Vec3f,
+    radius: f32,
+    steepness: f32,
+    // the area, and how many crossings of a line it counts at most, only needed for glowing ones
+    surface: OnceCell<(f32, usize)>,
+}
+
+impl SdfObject {
+    pub fn new(sdf: Sdf, material: &Material) -> Self {
+        let (center, radius) = sdf.bounds();
+        let steepness = sdf.steepness();
+        SdfObject { sdf, material: material.clone(), center, radius, steepness, surface: OnceCell::new() }
+    }
+
+    // central differences, pointing the way the distance grows, which is outwards
+    fn normal(&self, p: &Vec3f) -> Vec3f {
+        let gradient: Vec<f32> = (0..3).map(|i| {
+            let (mut ahead, mut behind) = (p.clone(), p.clone());
+            ahead[i] += GRADIENT_STEP;
+            behind[i] -= GRADIENT_STEP;
+            self.sdf.distance(&ahead) - self.sdf.distance(&behind)
+        }).collect();
+        let normal = Vec3f::from(&gradient);
+        if normal.magnitude() > 0.0 { normal.normalize() } else { Vec3f::new3f(0.0, 1.0, 0.0) }
+    }
+
+    fn hit_at(&self, distance: f32, p: &Vec3f) -> SurfaceHit {
+        let normal = self.normal(p);
+        let d = (p - &self.center).normalize();
+        let uv = Vec2f::from(&[0.5 + d[0].atan2(d[2]) / (2.0 * PI), 0.5 + d[1].clamp(-1.0, 1.0).asin() / PI]);
+        // east around the y axis, kept in the surface
+        let east = Vec3f::new3f(d[2], 0.0, -d[0]);
+        let east = &east - &(&normal * normal.dot(&east));
+        let tangent = if east.magnitude() > 1e-6 { east.normalize() } else { crate::sampling::basis(&normal).0 };
+
+        SurfaceHit { distance, normal, uv, tangent }
+    }
+
+    // where the ray is inside the bounding sphere, None if it never is (in front of orig)
+    fn bounds_range(&self, orig: &Vec3f, dir: &Vec3f) -> Option<(f32, f32)> {
+        let l = &self.center - orig;
+        let tca = l.dot(dir);
+        let d2 = l.dot(&l) - tca * tca;
+        if d2 > self.radius * self.radius {
+            return None;
+        }
+        let thc = (self.radius * self.radius - d2).sqrt();
+        if tca + thc < 0.0 { None } else { Some(((tca - thc).max(0.0), tca + thc)) }
+    }
+
+    // marches from start to the next place the distance changes sign, up to end
+    // works from either side of the surface, outside looking for a way in or inside for a way out
+    fn march(&self, orig: &Vec3f, dir: &Vec3f, start: f32, end: f32) -> Option<f32> {
+        let mut t = start;
+        let side = if self.sdf.distance(&(orig + &(dir * t))) < 0.0 { -1.0 } else { 1.0 };
+
+        for _ in 0..MAX_STEPS {
+            if t > end {
+                return None;
+            }
+            let d = side * self.sdf.distance(&(orig + &(dir * t)));
+            if d < SURFACE_DISTANCE {
+                return Some(t);
+            }
+            t += d / self.steepness;
+        }
+
+        None
+    }
+
+    // a line through the bounding sphere, as (origin, direction) with the origin on the way in
+    // uniform numbers give lines spread evenly over every direction and every spot they could pass through
+    fn line(&self, u: f32, v: f32, s: f32, w: f32) -> (Vec3f, Vec3f) {
+        let z = 1.0 - 2.0 * u;
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * PI * v;
+        let dir = Vec3f::new3f(r * phi.cos(), r * phi.sin(), z);
+
+        // where it passes the plane through the center, evenly over the disk the sphere covers
+        let (e1, e2) = sampling::basis(&dir);
+        let (r, phi) = (self.radius * s.sqrt(), 2.0 * PI * w);
+        let offset = &(&e1 * (r * phi.cos())) + &(&e2 * (r * phi.sin()));
+        (&(&self.center + &offset) - &(&dir * self.radius), dir)
+    }
+
+    // by cauchy-crofton, lines through the bounding sphere cross the surface 2 * area / (4 pi radius^2) times on average
+    // counted on halton lines, which comes within a percent or so
+    // the crossings past what nearly all lines have aren't counted, as sample_surface would take too many tries to land on them
+    fn surface(&self) -> (f32, usize) {
+        *self.surface.get_or_init(|| {
+            let mut counts: Vec<usize> = (0..AREA_LINES).map(|i| {
+                let (orig, dir) = self.line(sampling::halton(i, 0), sampling::halton(i, 1), sampling::halton(i, 2), sampling::halton(i, 3));
+                self.crossings_until(&orig, &dir, usize::MAX).len()
+            }).collect();
+            counts.sort_unstable();
+            let most = counts[((AREA_LINES as f32 * COUNTED_LINES) as usize).min(counts.len() - 1)].max(1);
+            let total: usize = counts.iter().map(|&n| n.min(most)).sum();
+            (total as f32 / AREA_LINES as f32 * 2.0 * PI * self.radius * self.radius, most)
+        })
+    }
+
+    // crossings() up to the first limit of them, which is all sample_surface needs
+    fn crossings_until(&self, orig: &Vec3f, dir: &Vec3f, limit: usize) -> Vec<SurfaceHit> {
+        let mut crossings = Vec::new();
+        let (mut start, end) = match self.bounds_range(orig, dir) {
+            Some(range) => range,
+            None => return crossings,
+        };
+
+        // after each crossing, hop past the surface and look for the next one from the other side
+        // far enough to be clear of it, or marching would start out on the surface and find the same crossing again
+        let distance = |t: f32| self.sdf.distance(&(orig + &(dir * t)));
+        while crossings.len() < limit {
+            let t = match self.march(orig, dir, start, end) {
+                Some(t) => t,
+                None => break,
+            };
+            let mut hop = 2.0 * SURFACE_DISTANCE;
+            start = t + hop;
+            while start <= end && distance(start).abs() < SURFACE_DISTANCE {
+                hop *= 2.0;
+                start = t + hop;
+            }
+            // a ray that only grazed the surface is on the same side as just as far before it, and didn't cross it after all
+            if (distance(t - hop) < 0.0) != (distance(start) < 0.0) {
+                crossings.push(self.hit_at(t, &(orig + &(dir * t))));
+            }
+        }
+
+        crossings
+    }
+}
+
+impl Object for SdfObject {
+    fn ray_intersect(&self, orig: &Vec3f, dir: &Vec3f, _time: f32) -> Option<SurfaceHit> {
+        let (start, end) = self.bounds_range(orig, dir)?;
+        let t = self.march(orig, dir, start, end)?;
+        Some(self.hit_at(t, &(orig + &(dir * t))))
+    }
+
+    fn crossings(&self, orig: &Vec3f, dir: &Vec3f, _time: f32) -> Vec<SurfaceHit> {
+        self.crossings_until(orig, dir, usize::MAX)
+    }
+
+    fn get_material(&self) -> &Material {
+        &self.material
+    }
+
+    fn area(&self) -> f32 {
+        self.surface().0
+    }
+
+    // a random line through the bounds, and one of its crossings picked out of as many as area() counts
+    // lines pass through every bit of surface equally often, so the crossings are spread evenly over it
+    // lines with fewer crossings than that get thrown back every so often, to keep them from counting for more
+    fn sample_surface(&self, u: f32, v: f32, _time: f32) -> Option<(Vec3f, Vec3f)> {
+        let most = self.surface().1;
+        // the rest of the random numbers come from a hash of the ones given
+        let mut rng = Rng::new(sampling::hash(&[u.to_bits() as u64, v.to_bits() as u64]), 0);
+        let (mut u, mut v) = (u, v);
+        for _ in 0..MAX_TRIES {
+            let (orig, dir) = self.line(u, v, rng.next_f32(), rng.next_f32());
+            let pick = ((rng.next_f32() * most as f32) as usize).min(most - 1);
+            if let Some(hit) = self.crossings_until(&orig, &dir, pick + 1).get(pick) {
+                return Some((&orig + &(&dir * hit.distance), hit.normal.clone()));
+            }
+            u = rng.next_f32();
+            v = rng.next_f32();
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object(sdf: Sdf) -> SdfObject {
+        let material = Material::new(&Vec3f::new3f(1.0, 1.0, 1.0), &Vec3f::new3f(1.0, 0.0, 0.0), 1.0, 1.0);
+        SdfObject::new(sdf, &material)
+    }
+
+    #[test]
+    fn areas() {
+        let ball = object(Sdf::Sphere { center: Vec3f::new3f(1.0, 2.0, 3.0), radius: 2.0 });
+        assert!((ball.area() - 16.0 * PI).abs() < 0.01 * 16.0 * PI, "sphere area {}", ball.area());
+
+        // the bounding sphere is much bigger than a box, the surface of a cube of side 2 being 24
+        let cube = object(Sdf::Cuboid { center: Vec3f::zero(), half_size: Vec3f::new3f(1.0, 1.0, 1.0), rounding: 0.0 });
+        assert!((cube.area() - 24.0).abs() < 0.02 * 24.0, "cube area {}", cube.area());
+    }
+
+    #[test]
+    fn samples_on_the_surface() {
+        let cube = object(Sdf::Cuboid { center: Vec3f::zero(), half_size: Vec3f::new3f(1.0, 1.0, 1.0), rounding: 0.0 });
+        let mut rng = Rng::new(3, 0);
+        let mut faces = [0; 6];
+        for _ in 0..600 {
+            let (point, normal) = cube.sample_surface(rng.next_f32(), rng.next_f32(), 0.0).unwrap();
+            assert!(cube.sdf.distance(&point).abs() < 1e-3, "{:?} is off the surface", point);
+            let axis = (0..3).max_by(|&a, &b| point[a].abs().total_cmp(&point[b].abs())).unwrap();
+            assert!(normal[axis] * point[axis].signum() > 0.9, "{:?} at {:?}", normal, point);
+            faces[axis as usize * 2 + (point[axis] > 0.0) as usize] += 1;
+        }
+        // evenly over the faces, about 100 each
+        assert!(faces.iter().all(|&n| n > 60 && n < 140), "{:?}", faces);
+    }
+
+    #[test]
+    fn crossings_alternate() {
+        let ball = object(Sdf::Sphere { center: Vec3f::zero(), radius: 1.0 });
+        // rays closer and closer to grazing, which sphere tracing struggles to get clear of the surface on
+        for i in 0..200 {
+            let offset = 1.0 - 0.5f32.powi(i / 10) * (1.0 + (i % 10) as f32) / 20.0;
+            let orig = Vec3f::new3f(offset, 0.0, 5.0);
+            let dir = Vec3f::new3f(0.0, 0.0, -1.0);
+            let crossings = ball.crossings(&orig, &dir, 0.0);
+            assert!(crossings.len() <= 2, "{} crossings at {}", crossings.len(), offset);
+            for (k, hit) in crossings.iter().enumerate() {
+                assert_eq!(hit.normal.dot(&dir) < 0.0, k % 2 == 0, "crossing {} at {} of {:?}", k, offset, crossings);
+            }
+        }
+    }
+}
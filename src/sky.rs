@@ -0,0 +1,226 @@
+use std::f32::consts::PI;
+use crate::geometry::Vec3f;
+use crate::image::Image;
+
+// preetham's luminance comes out in kcd/m^2, this brings a midday zenith down to roughly the brightness of our point lights
+static LUMINANCE_SCALE: f32 = 0.05;
+
+pub enum SkyModel {
+    // linear blend from horizon to zenith, flat ground color below
+    Gradient { zenith: Vec3f, horizon: Vec3f },
+    // "a practical analytic model for daylight", preetham, shirley and smits 1999
+    // turbidity is how hazy the air is, 2 is crisp and clear, 10 is a summer haze
+    Preetham { turbidity: f32 },
+}
+
+/// procedural sky dome with a sun, an alternative to an environment map for outdoor scenes
+pub struct Sky {
+    model: SkyModel,
+    // unit vector pointing towards the sun
+    sun_dir: Vec3f,
+    // cosine of the angular radius of the sun disk
+    sun_cos_radius: f32,
+    sun_radiance: Vec3f,
+    ground: Vec3f,
+    intensity: f32,
+    // preetham's perez distribution coefficients A through E, for luminance Y and chromaticities x and y
+    perez: [[f32; 5]; 3],
+    // Yxy at the zenith, which the distribution is relative to
+    zenith: [f32; 3],
+}
+
+// perez et al's all-weather sky luminance distribution
+// theta is the angle from the zenith of the view direction, gamma the angle between the view direction and the sun
+fn perez(c: &[f32; 5], cos_theta: f32, gamma: f32) -> f32 {
+    (1.0 + c[0] * (c[1] / cos_theta.max(0.01)).exp()) * (1.0 + c[2] * (c[3] * gamma).exp() + c[4] * gamma.cos() * gamma.cos())
+}
+
+fn xyy_to_rgb(big_y: f32, x: f32, y: f32) -> Vec3f {
+    if y <= 0.0 {
+        return Vec3f::zero();
+    }
+
+    let big_x = x / y * big_y;
+    let big_z = (1.0 - x - y) / y * big_y;
+
+    // XYZ to linear sRGB
+    Vec3f::new3f(
+        (3.2406 * big_x - 1.5372 * big_y - 0.4986 * big_z).max(0.0),
+        (-0.9689 * big_x + 1.8758 * big_y + 0.0415 * big_z).max(0.0),
+        (0.0557 * big_x - 0.2040 * big_y + 1.0570 * big_z).max(0.0))
+}
+
+/// direction from elevation above the horizon and azimuth clockwise from -z (straight ahead of the camera), both in radians
+pub fn sun_direction(elevation: f32, azimuth: f32) -> Vec3f {
+    Vec3f::new3f(elevation.cos() * azimuth.sin(), elevation.sin(), -elevation.cos() * azimuth.cos())
+}
+
+impl Sky {
+    /// sun_irradiance is how much light the sun delivers to a surface facing it, independent of how big the disk looks
+    pub fn new(model: SkyModel, sun_dir: &Vec3f, sun_angular_radius: f32, sun_irradiance: f32, intensity: f32) -> Self {
+        let sun_dir = sun_dir.normalize();
+        let theta_sun = sun_dir[1].clamp(-1.0, 1.0).acos();
+        let (perez, zenith) = match &model {
+            SkyModel::Preetham { turbidity } => {
+                let t = *turbidity;
+                let perez = [
+                    [0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703],
+                    [-0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452],
+                    [-0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529],
+                ];
+
+                // the sun can dip below the horizon, but the fit only covers it being above
+                let theta = theta_sun.min(PI / 2.0);
+                let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta);
+                let zenith_luminance = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192).max(0.0);
+                let chromaticity = |m: [[f32; 4]; 3]| {
+                    let thetas = [theta * theta * theta, theta * theta, theta, 1.0];
+                    let ts = [t * t, t, 1.0];
+                    (0..3).map(|i| ts[i] * (0..4).map(|j| m[i][j] * thetas[j]).sum::<f32>()).sum::<f32>()
+                };
+                let zenith_x = chromaticity([[0.00166, -0.00375, 0.00209, 0.0],
+                                             [-0.02903, 0.06377, -0.03202, 0.00394],
+                                             [0.11693, -0.21196, 0.06052, 0.25886]]);
+                let zenith_y = chromaticity([[0.00275, -0.00610, 0.00317, 0.0],
+                                             [-0.04214, 0.08970, -0.04153, 0.00516],
+                                             [0.15346, -0.26756, 0.06670, 0.26688]]);
+
+                (perez, [zenith_luminance, zenith_x, zenith_y])
+            }
+            SkyModel::Gradient { .. } => ([[0.0; 5]; 3], [0.0; 3]),
+        };
+
+        let mut sky = Sky {
+            model,
+            sun_dir: sun_dir.clone(),
+            sun_cos_radius: sun_angular_radius.cos(),
+            sun_radiance: Vec3f::zero(),
+            ground: Vec3f::zero(),
+            intensity,
+            perez,
+            zenith,
+        };
+
+        // the sun takes on the tint of the sky right next to it, reddening as it sets
+        // its radiance is whatever spreads sun_irradiance over the solid angle of the disk
+        let around_sun = sky.sky_radiance(&Vec3f::new3f(sun_dir[0], sun_dir[1].max(0.05), sun_dir[2]).normalize());
+        let around_luminance = 0.2126 * around_sun[0] + 0.7152 * around_sun[1] + 0.0722 * around_sun[2];
+        let sun_solid_angle = 2.0 * PI * (1.0 - sky.sun_cos_radius);
+        if around_luminance > 0.0 && sun_solid_angle > 0.0 && sun_dir[1] > 0.0 {
+            sky.sun_radiance = &around_sun * (sun_irradiance / (around_luminance * sun_solid_angle));
+        }
+
+        // the ground is lit by the sky above it, so take a dim version of the horizon right under the sun
+        let horizon = sky.sky_radiance(&Vec3f::new3f(sun_dir[0], 0.0, sun_dir[2]).normalize());
+        sky.ground = &horizon * 0.3;
+
+        sky
+    }
+
+    // the sky dome alone, without sun disk or ground
+    fn sky_radiance(&self, dir: &Vec3f) -> Vec3f {
+        let up = dir[1].max(0.0);
+
+        match &self.model {
+            SkyModel::Gradient { zenith, horizon } => &(&(horizon * (1.0 - up)) + &(zenith * up)) * self.intensity,
+            SkyModel::Preetham { .. } => {
+                let cos_gamma = dir.dot(&self.sun_dir).clamp(-1.0, 1.0);
+                let gamma = cos_gamma.acos();
+                let theta_sun = self.sun_dir[1].clamp(0.0, 1.0).acos();
+                let relative = |i: usize| perez(&self.perez[i], up, gamma) / perez(&self.perez[i], 1.0, theta_sun);
+
+                let big_y = self.zenith[0] * relative(0) * LUMINANCE_SCALE * self.intensity;
+                xyy_to_rgb(big_y, self.zenith[1] * relative(1), self.zenith[2] * relative(2))
+            }
+        }
+    }
+
+    pub fn radiance(&self, dir: &Vec3f) -> Vec3f {
+        if dir[1] < 0.0 {
+            return self.ground.clone();
+        }
+
+        let sky = self.sky_radiance(dir);
+        if dir.dot(&self.sun_dir) >= self.sun_cos_radius {
+            &sky + &self.sun_radiance
+        } else {
+            sky
+        }
+    }
+
+    /// tabulate the sky as an equirectangular image, so it can be importance sampled like an environment map
+    /// each pixel averages a few directions so the sun disk shows up even when it's smaller than a pixel
+    pub fn bake(&self, width: usize, height: usize) -> Image {
+        let sub = 3;
+        let mut pixels = Vec::with_capacity(width * height);
+
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = Vec3f::zero();
+                for sy in 0..sub {
+                    for sx in 0..sub {
+                        let u = (x as f32 + (sx as f32 + 0.5) / sub as f32) / width as f32;
+                        let v = (y as f32 + (sy as f32 + 0.5) / sub as f32) / height as f32;
+                        let phi = (u - 0.5) * 2.0 * PI;
+                        let theta = v * PI;
+                        let dir = Vec3f::new3f(theta.sin() * phi.sin(), theta.cos(), -theta.sin() * phi.cos());
+                        sum = &sum + &self.radiance(&dir);
+                    }
+                }
+                pixels.push(&sum * (1.0 / (sub * sub) as f32));
+            }
+        }
+
+        Image::new(width, height, pixels)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::luminance;
+
+    #[test]
+    fn gradient() {
+        let model = SkyModel::Gradient { zenith: Vec3f::new3f(0.2, 0.4, 1.0), horizon: Vec3f::new3f(1.0, 1.0, 1.0) };
+        let sky = Sky::new(model, &sun_direction(0.5, 0.0), 0.01, 0.0, 2.0);
+        assert_eq!(sky.radiance(&Vec3f::new3f(0.0, 1.0, 0.0)), Vec3f::new3f(0.4, 0.8, 2.0));
+        assert_eq!(sky.radiance(&Vec3f::new3f(0.0, 0.0, 1.0)), Vec3f::new3f(2.0, 2.0, 2.0));
+        // a dim version of the horizon, whichever way down we look
+        assert_eq!(sky.radiance(&Vec3f::new3f(0.6, -0.8, 0.0)), Vec3f::new3f(0.6, 0.6, 0.6));
+    }
+
+    // however big the sun looks, it should light a surface facing it by the irradiance it was given
+    #[test]
+    fn sun_irradiance() {
+        for &radius in &[0.005, 0.05] {
+            let sky = Sky::new(SkyModel::Preetham { turbidity: 3.0 }, &sun_direction(0.6, 1.0), radius, 5.0, 1.0);
+            let irradiance = luminance(&sky.sun_radiance) * 2.0 * PI * (1.0 - sky.sun_cos_radius);
+            assert!((irradiance - 5.0).abs() < 0.01, "sun of radius {} gives {}", radius, irradiance);
+        }
+
+        // a sun that's set doesn't shine
+        let sky = Sky::new(SkyModel::Preetham { turbidity: 3.0 }, &sun_direction(-0.1, 0.0), 0.01, 5.0, 1.0);
+        assert_eq!(sky.sun_radiance, Vec3f::zero());
+    }
+
+    #[test]
+    fn preetham() {
+        let sun = sun_direction(0.4, 0.5);
+        let sky = Sky::new(SkyModel::Preetham { turbidity: 2.5 }, &sun, 0.01, 0.0, 1.0);
+        for y in 0..16 {
+            for x in 0..32 {
+                let (theta, phi) = (PI * (y as f32 + 0.5) / 16.0, 2.0 * PI * x as f32 / 32.0);
+                let color = sky.radiance(&Vec3f::new3f(theta.sin() * phi.sin(), theta.cos(), -theta.sin() * phi.cos()));
+                assert!((0..3).all(|i| color[i].is_finite() && color[i] >= 0.0), "{:?} at {},{}", color, x, y);
+            }
+        }
+
+        // clear skies are blue overhead and brightest around the sun
+        let zenith = sky.radiance(&Vec3f::new3f(0.0, 1.0, 0.0));
+        assert!(zenith[2] > zenith[0], "zenith is {:?}", zenith);
+        let near_sun = sky.radiance(&sun_direction(0.5, 0.5));
+        let away = sky.radiance(&sun_direction(0.5, 0.5 + PI));
+        assert!(luminance(&near_sun) > luminance(&away), "{:?} next to the sun, {:?} away from it", near_sun, away);
+    }
+}
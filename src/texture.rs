@@ -0,0 +1,172 @@
+use std::sync::Arc;
+use crate::geometry::{Vec2f, Vec3f};
+use crate::image::Image;
+use crate::noise;
+
+/// what happens to uv coordinates outside of [0, 1]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WrapMode {
+    // tile the image over and over
+    Repeat,
+    // smear the edge pixels outwards
+    Clamp,
+}
+
+/// something that varies over a surface, plugged into one of a material's channels
+/// the procedural ones are solid textures: they fill space and objects are carved out of them, so they don't need uvs
+/// scale is how many times the pattern repeats per unit of distance (or across the uv square for images)
+#[derive(Debug)]
+pub enum Texture {
+    Image { image: Arc<Image>, wrap: WrapMode, scale: f32 },
+    // alternating cubes
+    Checker { even: Vec3f, odd: Vec3f, scale: f32 },
+    // plain perlin noise, blending between low and high
+    Noise { low: Vec3f, high: Vec3f, scale: f32 },
+    Turbulence { low: Vec3f, high: Vec3f, scale: f32, octaves: u32 },
+    // stripes along x, bent around by turbulence, strength being how much
+    Marble { low: Vec3f, high: Vec3f, scale: f32, octaves: u32, strength: f32 },
+    // concentric rings around the y axis, wobbled by noise, strength being how much
+    Wood { light: Vec3f, dark: Vec3f, scale: f32, strength: f32 },
+}
+
+fn mix(a: &Vec3f, b: &Vec3f, t: f32) -> Vec3f {
+    &(a * (1.0 - t)) + &(b * t)
+}
+
+impl Texture {
+    pub fn image(image: Arc<Image>, wrap: WrapMode, scale: f32) -> Self {
+        Texture::Image { image, wrap, scale }
+    }
+
+    /// value of the texture at surface coordinates uv, point being where that is in space
+    pub fn sample(&self, uv: &Vec2f, point: &Vec3f) -> Vec3f {
+        match self {
+            Texture::Image { image, wrap, scale } => bilinear(image, *wrap, uv[0] * scale, uv[1] * scale),
+            Texture::Checker { even, odd, scale } => {
+                let p = point * *scale;
+                let cell = p[0].floor() as i64 + p[1].floor() as i64 + p[2].floor() as i64;
+                if cell & 1 == 0 { even.clone() } else { odd.clone() }
+            }
+            Texture::Noise { low, high, scale } => mix(low, high, 0.5 + 0.5 * noise::perlin(&(point * *scale))),
+            Texture::Turbulence { low, high, scale, octaves } => mix(low, high, noise::turbulence(&(point * *scale), *octaves)),
+            Texture::Marble { low, high, scale, octaves, strength } => {
+                let p = point * *scale;
+                let phase = p[0] + strength * noise::turbulence(&p, *octaves) * 10.0;
+                mix(low, high, 0.5 + 0.5 * phase.sin())
+            }
+            Texture::Wood { light, dark, scale, strength } => {
+                let p = point * *scale;
+                let radius = (p[0] * p[0] + p[2] * p[2]).sqrt() + strength * noise::perlin(&(&p * 0.5));
+                let ring = radius - radius.floor();
+                // sharpen the rings so the dark bands are narrower than the light ones
+                mix(light, dark, ring.powf(3.0))
+            }
+        }
+    }
+}
+
+fn wrap_texel(i: i64, size: usize, wrap: WrapMode) -> usize {
+    match wrap {
+        WrapMode::Repeat => i.rem_euclid(size as i64) as usize,
+        WrapMode::Clamp => i.clamp(0, size as i64 - 1) as usize,
+    }
+}
+
+// blend the four texels around uv by how close each one's center is
+// v runs from the bottom of the image up, while image rows run top down
+fn bilinear(image: &Image, wrap: WrapMode, u: f32, v: f32) -> Vec3f {
+    let x = u * image.width() as f32 - 0.5;
+    let y = (1.0 - v) * image.height() as f32 - 0.5;
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+
+    let texel = |dx: i64, dy: i64| {
+        image.get(wrap_texel(x0 as i64 + dx, image.width(), wrap), wrap_texel(y0 as i64 + dy, image.height(), wrap))
+    };
+    let top = &(texel(0, 0) * (1.0 - fx)) + &(texel(1, 0) * fx);
+    let bottom = &(texel(0, 1) * (1.0 - fx)) + &(texel(1, 1) * fx);
+
+    &(&top * (1.0 - fy)) + &(&bottom * fy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grey(value: f32) -> Vec3f {
+        Vec3f::new3f(value, value, value)
+    }
+
+    // 2x2, with v running up the picture: black and red along the bottom, green and blue along the top
+    fn squares(wrap: WrapMode) -> Texture {
+        let pixels = vec![Vec3f::new3f(0.0, 1.0, 0.0), Vec3f::new3f(0.0, 0.0, 1.0), Vec3f::zero(), Vec3f::new3f(1.0, 0.0, 0.0)];
+        Texture::image(Arc::new(Image::new(2, 2, pixels)), wrap, 1.0)
+    }
+
+    fn at(texture: &Texture, u: f32, v: f32) -> Vec3f {
+        texture.sample(&Vec2f::from(&[u, v]), &Vec3f::zero())
+    }
+
+    #[test]
+    fn image_texels() {
+        let texture = squares(WrapMode::Clamp);
+        // the middle of each texel is exactly it, and halfway between two is half of each
+        assert_eq!(at(&texture, 0.25, 0.25), Vec3f::zero());
+        assert_eq!(at(&texture, 0.75, 0.25), Vec3f::new3f(1.0, 0.0, 0.0));
+        assert_eq!(at(&texture, 0.25, 0.75), Vec3f::new3f(0.0, 1.0, 0.0));
+        assert_eq!(at(&texture, 0.5, 0.75), Vec3f::new3f(0.0, 0.5, 0.5));
+        assert_eq!(at(&texture, 0.5, 0.5), Vec3f::new3f(0.25, 0.25, 0.25));
+    }
+
+    #[test]
+    fn image_wrapping() {
+        // past the left edge, clamping smears out the edge texel while repeating blends in the far side
+        assert_eq!(at(&squares(WrapMode::Clamp), 0.0, 0.25), Vec3f::zero());
+        assert_eq!(at(&squares(WrapMode::Repeat), 0.0, 0.25), Vec3f::new3f(0.5, 0.0, 0.0));
+        // repeating goes on forever, either way
+        let texture = squares(WrapMode::Repeat);
+        assert_eq!(at(&texture, 3.75, -1.75), at(&texture, 0.75, 0.25));
+
+        // scale tiles the image that many times across the uv square
+        let tiled = Texture::image(Arc::new(Image::new(2, 2, vec![Vec3f::zero(), grey(1.0), grey(1.0), Vec3f::zero()])), WrapMode::Repeat, 2.0);
+        assert_eq!(at(&tiled, 0.125, 0.125), at(&tiled, 0.625, 0.625));
+    }
+
+    #[test]
+    fn checker() {
+        let texture = Texture::Checker { even: grey(1.0), odd: Vec3f::zero(), scale: 0.5 };
+        let at = |x: f32, y: f32, z: f32| texture.sample(&Vec2f::from(&[0.0, 0.0]), &Vec3f::new3f(x, y, z));
+        // cubes 2 units across, the one with its corner at the origin being even
+        assert_eq!(at(0.5, 0.5, 0.5), grey(1.0));
+        assert_eq!(at(1.9, 1.9, 1.9), grey(1.0));
+        assert_eq!(at(2.1, 0.5, 0.5), Vec3f::zero());
+        assert_eq!(at(-0.1, 0.5, 0.5), Vec3f::zero());
+        assert_eq!(at(2.1, 2.1, 0.5), grey(1.0));
+    }
+
+    // the procedural ones only ever blend between their two colors
+    #[test]
+    fn procedural_in_range() {
+        let (low, high) = (Vec3f::new3f(0.2, 0.1, 0.0), Vec3f::new3f(0.8, 0.5, 0.4));
+        let textures = [
+            Texture::Noise { low: low.clone(), high: high.clone(), scale: 3.0 },
+            Texture::Turbulence { low: low.clone(), high: high.clone(), scale: 2.0, octaves: 5 },
+            Texture::Marble { low: low.clone(), high: high.clone(), scale: 1.5, octaves: 4, strength: 0.7 },
+            Texture::Wood { light: high.clone(), dark: low.clone(), scale: 4.0, strength: 0.3 },
+        ];
+        for texture in &textures {
+            let mut seen = (f32::MAX, f32::MIN);
+            for i in 0..500 {
+                let point = Vec3f::new3f(i as f32 * 0.0731, (i % 17) as f32 * 0.19, (i % 5) as f32 * -0.43);
+                let color = texture.sample(&Vec2f::from(&[0.0, 0.0]), &point);
+                // all three channels are the same blend
+                let t = (color[0] - low[0]) / (high[0] - low[0]);
+                assert!((-1e-4..=1.0 + 1e-4).contains(&t), "{:?} gives {:?} at {:?}", texture, color, point);
+                assert!((color[1] - (low[1] + t * (high[1] - low[1]))).abs() < 1e-4, "{:?} gives {:?}", texture, color);
+                seen = (seen.0.min(t), seen.1.max(t));
+            }
+            // and actually vary
+            assert!(seen.1 - seen.0 > 0.3, "{:?} only covers {:?}", texture, seen);
+        }
+    }
+}